@@ -0,0 +1,206 @@
+//! Builds the order book from the UDP market data feed, filling gaps from the recovery
+//! service, and prints the top of book as it changes.
+//!
+//! ```text
+//! MARKET_DATA_ADDR=127.0.0.1:9880 MARKET_DATA_RECOVERY_ADDR=127.0.0.1:9881 cargo run --release
+//! cargo run --example market_data_listener
+//! ```
+//!
+//! `MARKET_DATA_ADDR` and `MARKET_DATA_RECOVERY_ADDR` say where to listen and recover from;
+//! a multicast group is joined on the default interface. `DROP_EVERY=n` throws away every
+//! nth packet to exercise recovery.
+
+use single_threaded_orderbook::sbe::Side;
+use single_threaded_orderbook::sbe::market_data::{self, MarketData, RetransmitRequest};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream, UdpSocket};
+
+fn main() -> io::Result<()> {
+    let feed_addr: SocketAddr = env_or("MARKET_DATA_ADDR", "127.0.0.1:9880").parse().map_err(invalid)?;
+    let recovery_addr = env_or("MARKET_DATA_RECOVERY_ADDR", "127.0.0.1:9881");
+    let drop_every: u64 = env_or("DROP_EVERY", "0").parse().map_err(invalid)?;
+
+    // listening before the snapshot is taken, so nothing after it is missed
+    let socket = UdpSocket::bind(feed_addr)?;
+    if let SocketAddr::V4(addr) = feed_addr
+        && addr.ip().is_multicast()
+    {
+        socket.join_multicast_v4(addr.ip(), &Ipv4Addr::UNSPECIFIED)?;
+    }
+    let mut recovery = Recovery::connect(&recovery_addr)?;
+    let mut book = Book::default();
+    book.l3_sequence = recovery.snapshot(&mut book)?;
+    println!("snapshot at l3 sequence {}: {} orders", book.l3_sequence, book.orders.len());
+
+    let mut next_sequence = None;
+    let mut datagram = [0u8; market_data::MAX_PACKET_LEN];
+    let mut packets = 0u64;
+    loop {
+        let n = socket.recv(&mut datagram)?;
+        packets += 1;
+        if drop_every > 0 && packets.is_multiple_of(drop_every) {
+            continue;
+        }
+        let (sequence, messages) = market_data::decode_packet(&datagram[..n]).map_err(invalid)?;
+        let expected = *next_sequence.get_or_insert(sequence);
+
+        if sequence > expected {
+            println!("gap: expected {expected}, got {sequence}; retransmitting");
+            for message in recovery.retransmit(expected, sequence - expected)? {
+                book.apply(&message)?;
+            }
+        }
+        // a retransmitted or duplicate packet may overlap what was already applied
+        let skip = expected.saturating_sub(sequence) as usize;
+        for message in messages.iter().skip(skip) {
+            book.apply(message)?;
+        }
+        next_sequence = Some(next_sequence.unwrap().max(sequence + messages.len() as u64));
+        if !messages.is_empty() {
+            book.print_top();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Book {
+    l3_sequence: u64,
+    orders: HashMap<[u8; 16], (Side, u64, u64)>,
+    bids: BTreeMap<u64, u64>,
+    asks: BTreeMap<u64, u64>,
+}
+
+impl Book {
+    fn apply(&mut self, message: &MarketData) -> io::Result<()> {
+        let l3_sequence = match message {
+            MarketData::AddOrder(m) => m.l3_sequence,
+            MarketData::ModifyOrder(m) => m.l3_sequence,
+            MarketData::DeleteOrder(m) => m.l3_sequence,
+            MarketData::ExecuteOrder(m) => m.l3_sequence,
+            MarketData::Trade(trade) => {
+                println!("trade {}: {} @ {} ({:?} took)", trade.trade_id, trade.quantity, trade.price, trade.taker_side);
+                return Ok(());
+            }
+            _ => return Ok(()),
+        };
+        if l3_sequence <= self.l3_sequence {
+            return Ok(());
+        }
+        if l3_sequence != self.l3_sequence + 1 {
+            return Err(io::Error::other(format!("book changes {} to {} are lost, a new snapshot is needed", self.l3_sequence + 1, l3_sequence - 1)));
+        }
+        self.l3_sequence = l3_sequence;
+
+        match message {
+            MarketData::AddOrder(m) => self.add(m.order_id, m.side, m.price, m.quantity),
+            MarketData::ModifyOrder(m) => {
+                self.remove(m.order_id);
+                self.add(m.order_id, m.side, m.price, m.quantity);
+            }
+            MarketData::DeleteOrder(m) => self.remove(m.order_id),
+            MarketData::ExecuteOrder(m) => {
+                self.remove(m.order_id);
+                if m.remaining > 0 {
+                    self.add(m.order_id, m.side, m.price, m.remaining);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn add(&mut self, order_id: [u8; 16], side: Side, price: u64, quantity: u64) {
+        *self.level(side).entry(price).or_default() += quantity;
+        self.orders.insert(order_id, (side, price, quantity));
+    }
+
+    fn remove(&mut self, order_id: [u8; 16]) {
+        let Some((side, price, quantity)) = self.orders.remove(&order_id) else {
+            return;
+        };
+        let level = self.level(side);
+        let total = level.entry(price).or_default();
+        *total -= quantity;
+        if *total == 0 {
+            level.remove(&price);
+        }
+    }
+
+    fn level(&mut self, side: Side) -> &mut BTreeMap<u64, u64> {
+        match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        }
+    }
+
+    fn print_top(&self) {
+        let bid = self.bids.iter().next_back();
+        let ask = self.asks.iter().next();
+        println!("l3 {}: bid {bid:?} ask {ask:?} (price, sats)", self.l3_sequence);
+    }
+}
+
+struct Recovery {
+    stream: TcpStream,
+    buf: Vec<u8>,
+}
+
+impl Recovery {
+    fn connect(addr: &str) -> io::Result<Self> {
+        Ok(Recovery { stream: TcpStream::connect(addr)?, buf: Vec::new() })
+    }
+
+    fn send(&mut self, message: MarketData) -> io::Result<()> {
+        let mut out = Vec::new();
+        message.encode_frame(&mut out);
+        self.stream.write_all(&out)
+    }
+
+    fn recv(&mut self) -> io::Result<MarketData> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(message) = market_data::take_message(&mut self.buf) {
+                return message.map_err(invalid);
+            }
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    /// Loads a snapshot into `book`, returning its L3 sequence.
+    fn snapshot(&mut self, book: &mut Book) -> io::Result<u64> {
+        self.send(MarketData::SnapshotRequest)?;
+        loop {
+            match self.recv()? {
+                MarketData::SnapshotOrder(o) => book.add(o.order_id, o.side, o.price, o.quantity),
+                MarketData::SnapshotComplete(complete) => return Ok(complete.l3_sequence),
+                other => return Err(invalid(format!("unexpected {other:?} in a snapshot"))),
+            }
+        }
+    }
+
+    fn retransmit(&mut self, from_sequence: u64, count: u64) -> io::Result<Vec<MarketData>> {
+        let count = count.min(u32::MAX as u64) as u32;
+        self.send(MarketData::RetransmitRequest(RetransmitRequest { from_sequence, count }))?;
+        let MarketData::RetransmitResponse(response) = self.recv()? else {
+            return Err(invalid("expected a retransmit response"));
+        };
+        if response.from_sequence != from_sequence || response.count < count {
+            // the book messages among them are caught by the L3 sequence check
+            println!("only {} messages from {} could be retransmitted", response.count, response.from_sequence);
+        }
+        (0..response.count).map(|_| self.recv()).collect()
+    }
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.into())
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
@@ -0,0 +1,204 @@
+//! Round-trip latency of the binary order entry protocol against the HTTP API.
+//!
+//! Start the server with the binary acceptor on, an admin key to issue the test user's key
+//! with, and order rate limits high enough not to throttle the HTTP half, then run this
+//! against it with the same admin key:
+//!
+//! ```text
+//! export ADMIN_API_KEY=admin ADMIN_API_SECRET=0123456789abcdef0123456789abcdef
+//! SBE_ADDR=127.0.0.1:9879 RATE_LIMIT_IP_ORDERS=100000/100000 RATE_LIMIT_KEY_ORDERS=100000/100000 cargo run --release
+//! cargo run --release --example sbe_latency -- [iterations]
+//! ```
+//!
+//! Each iteration places a bid far below any sensible market and cancels it, timing each
+//! request until its answer: the HTTP response on a kept-alive connection, or the execution
+//! report on the binary one. `HTTP_ADDR` and `SBE_ADDR` say where to connect.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use single_threaded_orderbook::sbe::client::Client;
+use single_threaded_orderbook::sbe::{ExecType, Message, Side};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// One micro USDC above zero would do, but a whole dollar keeps the numbers readable.
+const PRICE: &str = "1";
+const PRICE_MICRO: u64 = 1_000_000;
+const QUANTITY: &str = "0.0001";
+const QUANTITY_SATS: u64 = 10_000;
+
+fn main() -> io::Result<()> {
+    let iterations: usize = match std::env::args().nth(1) {
+        Some(n) => n.parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "iterations must be a number"))?,
+        None => 10_000,
+    };
+    let http_addr = std::env::var("HTTP_ADDR").unwrap_or_else(|_| "127.0.0.1:8080".into());
+    let sbe_addr = std::env::var("SBE_ADDR").unwrap_or_else(|_| "127.0.0.1:9879".into());
+    let warmup = (iterations / 10).max(1);
+
+    let mut http = Http::connect(&http_addr)?;
+    let (api_key, secret) = http.setup_user()?;
+
+    let mut http_place = Vec::with_capacity(iterations);
+    let mut http_cancel = Vec::with_capacity(iterations);
+    let order = format!(r#"{{"side":"bid","price":"{PRICE}","quantity":"{QUANTITY}","client_order_id":null}}"#);
+    for i in 0..warmup + iterations {
+        let started = Instant::now();
+        let reply = http.signed_post("/create_order", &order, &api_key, &secret)?;
+        let placed = started.elapsed();
+        let order_id = json_field(&reply, "order_id")?;
+
+        let started = Instant::now();
+        http.signed_post("/cancel_order", &format!(r#"{{"order_id":"{order_id}"}}"#), &api_key, &secret)?;
+        let cancelled = started.elapsed();
+        if i >= warmup {
+            http_place.push(placed);
+            http_cancel.push(cancelled);
+        }
+    }
+
+    let mut client = Client::connect(&sbe_addr, &api_key, &secret)?;
+    let mut sbe_place = Vec::with_capacity(iterations);
+    let mut sbe_cancel = Vec::with_capacity(iterations);
+    for i in 0..warmup + iterations {
+        // fresh ids every run, since client order ids are remembered per user
+        let cl_ord_id = 2 * i as u64 + 1;
+
+        let started = Instant::now();
+        client.new_order(cl_ord_id, Side::Buy, PRICE_MICRO, QUANTITY_SATS)?;
+        expect_report(&mut client, ExecType::New)?;
+        let placed = started.elapsed();
+
+        let started = Instant::now();
+        client.cancel_order(cl_ord_id + 1, cl_ord_id)?;
+        expect_report(&mut client, ExecType::Cancelled)?;
+        let cancelled = started.elapsed();
+        if i >= warmup {
+            sbe_place.push(placed);
+            sbe_cancel.push(cancelled);
+        }
+    }
+
+    println!("{iterations} round trips each, after {warmup} warmup, in microseconds:");
+    println!("{:<20} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}", "", "mean", "p50", "p90", "p99", "p99.9", "max");
+    report("http create_order", &mut http_place);
+    report("sbe new order", &mut sbe_place);
+    report("http cancel_order", &mut http_cancel);
+    report("sbe cancel", &mut sbe_cancel);
+    Ok(())
+}
+
+fn expect_report(client: &mut Client, exec_type: ExecType) -> io::Result<()> {
+    match client.recv()? {
+        Message::ExecutionReport(report) if report.exec_type == exec_type => Ok(()),
+        other => Err(io::Error::other(format!("expected a {exec_type:?} execution report, got {other:?}"))),
+    }
+}
+
+fn report(name: &str, samples: &mut [Duration]) {
+    samples.sort();
+    let micros = |d: Duration| d.as_secs_f64() * 1e6;
+    let percentile = |p: f64| micros(samples[((samples.len() - 1) as f64 * p).round() as usize]);
+    let mean = micros(samples.iter().sum::<Duration>()) / samples.len() as f64;
+    println!(
+        "{name:<20} {mean:>8.1} {:>8.1} {:>8.1} {:>8.1} {:>8.1} {:>8.1}",
+        percentile(0.5),
+        percentile(0.9),
+        percentile(0.99),
+        percentile(0.999),
+        micros(samples[samples.len() - 1]),
+    );
+}
+
+/// Just enough HTTP/1.1 to make requests on one kept-alive connection.
+struct Http {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Http {
+    fn connect(addr: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(Http { reader: BufReader::new(stream.try_clone()?), writer: stream })
+    }
+
+    /// A new user with 1000 USDC and a key allowed to trade and deposit.
+    fn setup_user(&mut self) -> io::Result<(String, String)> {
+        let reply = self.post("/initialize_user", "", &[])?;
+        let user_id = reply.trim_start_matches("engine replied: ").trim().to_string();
+        let (admin_key, admin_secret) = match (std::env::var("ADMIN_API_KEY"), std::env::var("ADMIN_API_SECRET")) {
+            (Ok(key), Ok(secret)) => (key, secret),
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "ADMIN_API_KEY and ADMIN_API_SECRET must be set")),
+        };
+        let reply = self.signed_post(
+            "/admin/create_api_key",
+            &format!(r#"{{"user_id":"{user_id}","scopes":["trade","deposit"]}}"#),
+            &admin_key,
+            &admin_secret,
+        )?;
+        let (api_key, secret) = (json_field(&reply, "api_key")?, json_field(&reply, "secret")?);
+        self.signed_post("/deposit", r#"{"asset":"USDC","amount":"1000"}"#, &api_key, &secret)?;
+        Ok((api_key, secret))
+    }
+
+    fn signed_post(&mut self, path: &str, body: &str, api_key: &str, secret: &str) -> io::Result<String> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis().to_string();
+        let nonce = uuid::Uuid::new_v4().simple().to_string();
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        for part in [timestamp.as_str(), nonce.as_str(), "POST", path, body] {
+            mac.update(part.as_bytes());
+        }
+        let signature = hex::encode(mac.finalize().into_bytes());
+        let headers = [
+            ("X-API-KEY", api_key),
+            ("X-API-TIMESTAMP", &timestamp),
+            ("X-API-NONCE", &nonce),
+            ("X-API-SIGNATURE", &signature),
+        ];
+        self.post(path, body, &headers)
+    }
+
+    fn post(&mut self, path: &str, body: &str, headers: &[(&str, &str)]) -> io::Result<String> {
+        let mut request = format!("POST {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\nContent-Length: {}\r\n", body.len());
+        for (name, value) in headers {
+            request.push_str(&format!("{name}: {value}\r\n"));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+        self.writer.write_all(request.as_bytes())?;
+
+        let mut status = String::new();
+        self.reader.read_line(&mut status)?;
+        let mut content_length = 0;
+        loop {
+            let mut line = String::new();
+            self.reader.read_line(&mut line)?;
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':')
+                && name.eq_ignore_ascii_case("content-length")
+            {
+                content_length = value.trim().parse().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "bad content-length"))?;
+            }
+        }
+        let mut body = vec![0; content_length];
+        self.reader.read_exact(&mut body)?;
+        let body = String::from_utf8_lossy(&body).into_owned();
+
+        if status.split_whitespace().nth(1) != Some("200") {
+            return Err(io::Error::other(format!("POST {path}: {} {body}", status.trim())));
+        }
+        Ok(body)
+    }
+}
+
+fn json_field(body: &str, field: &str) -> io::Result<String> {
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    value[field]
+        .as_str()
+        .map(String::from)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("no {field} in {body}")))
+}
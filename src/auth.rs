@@ -0,0 +1,360 @@
+use actix_web::{FromRequest, HttpRequest, HttpResponse, dev::Payload, error::InternalError, web};
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::ops::Deref;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use crate::rate_limit;
+
+/// How far a request's timestamp may be from the server clock, either way.
+pub const RECV_WINDOW_MS: u64 = 30_000;
+const MAX_NONCE_LEN: usize = 64;
+
+pub const API_KEY_HEADER: &str = "X-API-KEY";
+pub const TIMESTAMP_HEADER: &str = "X-API-TIMESTAMP";
+pub const NONCE_HEADER: &str = "X-API-NONCE";
+pub const SIGNATURE_HEADER: &str = "X-API-SIGNATURE";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Balances and orders.
+    Read,
+    /// Placing and cancelling orders.
+    Trade,
+    /// Crediting funds with `/deposit`.
+    Deposit,
+    /// Moving funds out. No endpoint takes it yet; it's kept separate from `Deposit` so a
+    /// key that can fund an account can't also drain it.
+    Withdraw,
+    /// The `/admin` endpoints. Only the operator's configured key has it.
+    Admin,
+    /// The back office drop copy of every user's executions; issued by an admin.
+    #[serde(rename = "drop_copy")]
+    DropCopy,
+}
+
+impl Scope {
+    /// A scope that can be granted to a user's key.
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "read" => Some(Scope::Read),
+            "trade" => Some(Scope::Trade),
+            "deposit" => Some(Scope::Deposit),
+            "withdraw" => Some(Scope::Withdraw),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+    pub api_key: String,
+    pub user_id: Uuid,
+    pub scopes: Vec<Scope>,
+    /// Users a drop copy key may see, or every user if `None`.
+    pub accounts: Option<HashSet<Uuid>>,
+    secret: String,
+}
+
+/// Nonces a key has used recently, kept for twice the receive window so a replay with a
+/// timestamp still inside the window is always caught.
+#[derive(Debug, Default)]
+struct NonceWindow {
+    seen: HashSet<String>,
+    order: VecDeque<(u64, String)>,
+}
+
+impl NonceWindow {
+    fn insert(&mut self, nonce: &str, now: u64) -> bool {
+        while let Some((at, _)) = self.order.front() {
+            if now.saturating_sub(*at) < 2 * RECV_WINDOW_MS {
+                break;
+            }
+            let (_, old) = self.order.pop_front().unwrap();
+            self.seen.remove(&old);
+        }
+        if !self.seen.insert(nonce.to_string()) {
+            return false;
+        }
+        self.order.push_back((now, nonce.to_string()));
+        true
+    }
+}
+
+/// API keys issued to users, and the operator's admin key.
+///
+/// Requests are signed with HMAC-SHA256 over `timestamp + nonce + method + path + body`,
+/// where `path` includes any query string and `body` is the raw request body, and send the
+/// key, timestamp (milliseconds), nonce and hex signature in the `X-API-*` headers.
+#[derive(Debug, Default)]
+pub struct ApiKeys {
+    keys: Mutex<HashMap<String, ApiKey>>,
+    nonces: Mutex<HashMap<String, NonceWindow>>,
+}
+
+impl ApiKeys {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Issues a new key for `user_id`, returning the key and its secret.
+    pub fn create(&self, user_id: Uuid, scopes: Vec<Scope>) -> (String, String) {
+        let api_key = Uuid::new_v4().simple().to_string();
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        self.insert(api_key.clone(), secret.clone(), user_id, scopes, None);
+        (api_key, secret)
+    }
+
+    /// Issues a back office key for the drop copy of `accounts`, or of every user if `None`.
+    pub fn create_drop_copy(&self, accounts: Option<HashSet<Uuid>>) -> (String, String) {
+        let api_key = Uuid::new_v4().simple().to_string();
+        let secret = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        self.insert(api_key.clone(), secret.clone(), Uuid::nil(), vec![Scope::DropCopy], accounts);
+        (api_key, secret)
+    }
+
+    /// Registers the operator's key, which belongs to no user and only has the admin scope.
+    pub fn add_admin(&self, api_key: String, secret: String) {
+        self.insert(api_key, secret, Uuid::nil(), vec![Scope::Admin], None);
+    }
+
+    fn insert(&self, api_key: String, secret: String, user_id: Uuid, scopes: Vec<Scope>, accounts: Option<HashSet<Uuid>>) {
+        self.keys.lock().unwrap().insert(api_key.clone(), ApiKey {
+            api_key,
+            user_id,
+            scopes,
+            accounts,
+            secret,
+        });
+    }
+
+    pub fn revoke(&self, api_key: &str) -> bool {
+        self.nonces.lock().unwrap().remove(api_key);
+        self.keys.lock().unwrap().remove(api_key).is_some()
+    }
+
+    /// Checks a signature over `timestamp + nonce + rest` and uses up the nonce.
+    pub fn verify(&self, api_key: &str, timestamp: &str, nonce: &str, signature: &str, rest: &[u8]) -> Result<ApiKey, &'static str> {
+        let now = now_millis();
+        let sent_at: u64 = timestamp.parse().map_err(|_| "invalid timestamp")?;
+        if now.abs_diff(sent_at) > RECV_WINDOW_MS {
+            return Err("timestamp outside receive window");
+        }
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+            return Err("nonce must be 1 to 64 characters");
+        }
+
+        let key = self.keys.lock().unwrap().get(api_key).cloned().ok_or("unknown api key")?;
+        let signature = hex::decode(signature).map_err(|_| "invalid signature")?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(key.secret.as_bytes()).unwrap();
+        mac.update(timestamp.as_bytes());
+        mac.update(nonce.as_bytes());
+        mac.update(rest);
+        mac.verify_slice(&signature).map_err(|_| "invalid signature")?;
+
+        if !self.nonces.lock().unwrap().entry(api_key.to_string()).or_default().insert(nonce, now) {
+            return Err("nonce already used");
+        }
+        Ok(key)
+    }
+
+    /// Checks the `X-API-*` headers of a request whose raw body is `body`.
+    pub fn verify_request(&self, req: &HttpRequest, body: &[u8]) -> Result<ApiKey, &'static str> {
+        let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+        let (api_key, timestamp, nonce, signature) = match (header(API_KEY_HEADER), header(TIMESTAMP_HEADER), header(NONCE_HEADER), header(SIGNATURE_HEADER)) {
+            (Some(k), Some(t), Some(n), Some(s)) => (k, t, n, s),
+            _ => return Err("missing authentication headers"),
+        };
+
+        let path = req.uri().path_and_query().map_or(req.path(), |p| p.as_str());
+        let mut rest = Vec::with_capacity(req.method().as_str().len() + path.len() + body.len());
+        rest.extend_from_slice(req.method().as_str().as_bytes());
+        rest.extend_from_slice(path.as_bytes());
+        rest.extend_from_slice(body);
+        self.verify(api_key, timestamp, nonce, signature, &rest)
+    }
+}
+
+/// A signed request body and the user behind the key that signed it. An empty body is
+/// read as JSON `null`.
+pub struct Authenticated<T> {
+    pub api_key: String,
+    pub user_id: Uuid,
+    pub scopes: Vec<Scope>,
+    pub body: T,
+}
+
+impl<T> Authenticated<T> {
+    /// A 403 response if the key wasn't granted `scope`.
+    pub fn missing_scope(&self, scope: Scope) -> Option<HttpResponse> {
+        if self.scopes.contains(&scope) {
+            None
+        } else {
+            Some(HttpResponse::Forbidden().body(format!("api key lacks the {:?} scope", scope).to_lowercase()))
+        }
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Authenticated<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let req = req.clone();
+        let bytes = web::Bytes::from_request(&req, payload);
+
+        Box::pin(async move {
+            let body = bytes.await?;
+            let keys = req.app_data::<web::Data<ApiKeys>>().expect("api keys are not configured");
+            let key = keys
+                .verify_request(&req, &body)
+                .map_err(|e| InternalError::from_response(e, HttpResponse::Unauthorized().body(e)))?;
+            rate_limit::charge_authenticated(&req, &key.api_key)?;
+
+            let json: &[u8] = if body.is_empty() { b"null" } else { &body };
+            let parsed = serde_json::from_slice(json).map_err(|e| {
+                let msg = format!("invalid request body: {e}");
+                InternalError::from_response(msg.clone(), HttpResponse::BadRequest().body(msg))
+            })?;
+
+            Ok(Authenticated {
+                api_key: key.api_key,
+                user_id: key.user_id,
+                scopes: key.scopes,
+                body: parsed,
+            })
+        })
+    }
+}
+
+/// A signed request body from the operator's admin key. Anything else gets 401 or 403.
+pub struct Admin<T>(pub T);
+
+impl<T> Deref for Admin<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + 'static> FromRequest for Admin<T> {
+    type Error = actix_web::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let auth = Authenticated::<T>::from_request(req, payload);
+
+        Box::pin(async move {
+            let auth = auth.await?;
+            if let Some(resp) = auth.missing_scope(Scope::Admin) {
+                return Err(InternalError::from_response("not an admin key", resp).into());
+            }
+            Ok(Admin(auth.body))
+        })
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn keys() -> ApiKeys {
+        let keys = ApiKeys::new();
+        keys.insert("key".into(), SECRET.into(), Uuid::new_v4(), vec![Scope::Trade], None);
+        keys
+    }
+
+    fn sign(secret: &str, timestamp: &str, nonce: &str, rest: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(timestamp.as_bytes());
+        mac.update(nonce.as_bytes());
+        mac.update(rest);
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    const REST: &[u8] = b"POST/order{\"side\":\"bid\"}";
+
+    #[test]
+    fn signed_request_is_accepted() {
+        let keys = keys();
+        let timestamp = now_millis().to_string();
+        let signature = sign(SECRET, &timestamp, "n1", REST);
+        let key = keys.verify("key", &timestamp, "n1", &signature, REST).unwrap();
+        assert_eq!(key.scopes, vec![Scope::Trade]);
+    }
+
+    #[test]
+    fn signature_must_match_the_secret_and_the_request() {
+        let keys = keys();
+        let timestamp = now_millis().to_string();
+        let signature = sign("not the secret", &timestamp, "n1", REST);
+        assert_eq!(keys.verify("key", &timestamp, "n1", &signature, REST).unwrap_err(), "invalid signature");
+
+        let signature = sign(SECRET, &timestamp, "n1", REST);
+        assert_eq!(keys.verify("key", &timestamp, "n1", &signature, b"POST/order{}").unwrap_err(), "invalid signature");
+        assert_eq!(keys.verify("other", &timestamp, "n1", &signature, REST).unwrap_err(), "unknown api key");
+        assert_eq!(keys.verify("key", &timestamp, "n1", "not hex", REST).unwrap_err(), "invalid signature");
+        // a failed signature doesn't use up the nonce
+        assert!(keys.verify("key", &timestamp, "n1", &signature, REST).is_ok());
+    }
+
+    #[test]
+    fn timestamp_must_be_inside_the_receive_window() {
+        let keys = keys();
+        let now = now_millis();
+        for sent_at in [now - RECV_WINDOW_MS - 1_000, now + RECV_WINDOW_MS + 1_000] {
+            let timestamp = sent_at.to_string();
+            let signature = sign(SECRET, &timestamp, "n1", REST);
+            assert_eq!(
+                keys.verify("key", &timestamp, "n1", &signature, REST).unwrap_err(),
+                "timestamp outside receive window"
+            );
+        }
+        let signature = sign(SECRET, "soon", "n1", REST);
+        assert_eq!(keys.verify("key", "soon", "n1", &signature, REST).unwrap_err(), "invalid timestamp");
+    }
+
+    #[test]
+    fn nonces_cant_be_replayed() {
+        let keys = keys();
+        keys.insert("other".into(), SECRET.into(), Uuid::new_v4(), vec![Scope::Read], None);
+        let timestamp = now_millis().to_string();
+        let signature = sign(SECRET, &timestamp, "n1", REST);
+
+        assert!(keys.verify("key", &timestamp, "n1", &signature, REST).is_ok());
+        assert_eq!(keys.verify("key", &timestamp, "n1", &signature, REST).unwrap_err(), "nonce already used");
+        // nonces are per key
+        assert!(keys.verify("other", &timestamp, "n1", &signature, REST).is_ok());
+
+        let long = "n".repeat(MAX_NONCE_LEN + 1);
+        let signature = sign(SECRET, &timestamp, &long, REST);
+        assert_eq!(keys.verify("key", &timestamp, &long, &signature, REST).unwrap_err(), "nonce must be 1 to 64 characters");
+    }
+
+    #[test]
+    fn nonce_window_forgets_after_twice_the_receive_window() {
+        let mut window = NonceWindow::default();
+        assert!(window.insert("a", 0));
+        assert!(window.insert("b", RECV_WINDOW_MS));
+        assert!(!window.insert("a", 2 * RECV_WINDOW_MS - 1));
+        assert!(window.insert("a", 2 * RECV_WINDOW_MS));
+        assert!(!window.insert("b", 2 * RECV_WINDOW_MS));
+    }
+}
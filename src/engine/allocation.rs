@@ -0,0 +1,212 @@
+use std::collections::VecDeque;
+use serde::{Serialize, Deserialize};
+
+use super::orderbook::Order;
+
+/// Splits an incoming quantity across the resting orders of one price level.
+///
+/// `quantity` never exceeds the level's total. Implementations return `(queue index, fill)`
+/// pairs with fills summing to exactly `quantity`, and must be deterministic for the same queue.
+pub trait AllocationStrategy {
+    fn allocate(&self, queue: &VecDeque<Order>, quantity: u64) -> Vec<(usize, u64)>;
+}
+
+/// Matching algorithm a market is configured with.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchingAlgorithm {
+    /// Price-time priority.
+    Fifo,
+    /// Proportional to resting size. Shares below `min_allocation` sats are dropped and
+    /// everything left over after rounding goes out in time priority.
+    ProRata { min_allocation: u64, top_order_priority: bool },
+    /// `fifo_bps` of the incoming quantity is matched in time priority, the rest pro-rata.
+    Hybrid { fifo_bps: u32, min_allocation: u64, top_order_priority: bool },
+}
+
+impl MatchingAlgorithm {
+    pub fn strategy(&self) -> Box<dyn AllocationStrategy + Send> {
+        match *self {
+            MatchingAlgorithm::Fifo => Box::new(Fifo),
+            MatchingAlgorithm::ProRata { min_allocation, top_order_priority } => Box::new(ProRata {
+                min_allocation,
+                top_order_priority,
+            }),
+            MatchingAlgorithm::Hybrid { fifo_bps, min_allocation, top_order_priority } => Box::new(Hybrid {
+                fifo_bps,
+                pro_rata: ProRata { min_allocation, top_order_priority },
+            }),
+        }
+    }
+}
+
+pub struct Fifo;
+
+impl AllocationStrategy for Fifo {
+    fn allocate(&self, queue: &VecDeque<Order>, quantity: u64) -> Vec<(usize, u64)> {
+        let mut fills = vec![0; queue.len()];
+        fill_in_time_priority(queue, &mut fills, quantity);
+        collect(fills)
+    }
+}
+
+pub struct ProRata {
+    pub min_allocation: u64,
+    pub top_order_priority: bool,
+}
+
+impl ProRata {
+    fn allocate_into(&self, queue: &VecDeque<Order>, fills: &mut [u64], quantity: u64) {
+        let mut remaining = quantity;
+
+        if self.top_order_priority && remaining > 0 {
+            let open = queue[0].quantity - fills[0];
+            let top = open.min(remaining);
+            fills[0] += top;
+            remaining -= top;
+        }
+
+        let open: Vec<u64> = queue.iter().zip(fills.iter()).map(|(o, f)| o.quantity - f).collect();
+        let total_open: u128 = open.iter().map(|q| *q as u128).sum();
+
+        if remaining > 0 && total_open > 0 {
+            let pool = remaining as u128;
+            for (i, q) in open.iter().enumerate() {
+                let share = (pool * *q as u128 / total_open) as u64;
+                if share > 0 && share >= self.min_allocation {
+                    fills[i] += share;
+                    remaining -= share;
+                }
+            }
+        }
+
+        fill_in_time_priority(queue, fills, remaining);
+    }
+}
+
+impl AllocationStrategy for ProRata {
+    fn allocate(&self, queue: &VecDeque<Order>, quantity: u64) -> Vec<(usize, u64)> {
+        let mut fills = vec![0; queue.len()];
+        self.allocate_into(queue, &mut fills, quantity);
+        collect(fills)
+    }
+}
+
+pub struct Hybrid {
+    pub fifo_bps: u32,
+    pub pro_rata: ProRata,
+}
+
+impl AllocationStrategy for Hybrid {
+    fn allocate(&self, queue: &VecDeque<Order>, quantity: u64) -> Vec<(usize, u64)> {
+        let mut fills = vec![0; queue.len()];
+        let fifo_qty = (quantity as u128 * self.fifo_bps as u128 / 10_000) as u64;
+
+        fill_in_time_priority(queue, &mut fills, fifo_qty);
+        self.pro_rata.allocate_into(queue, &mut fills, quantity - fifo_qty);
+        collect(fills)
+    }
+}
+
+/// Tops up orders from the front of the queue until `quantity` is used up.
+fn fill_in_time_priority(queue: &VecDeque<Order>, fills: &mut [u64], mut quantity: u64) {
+    for (i, order) in queue.iter().enumerate() {
+        if quantity == 0 {
+            break;
+        }
+        let take = (order.quantity - fills[i]).min(quantity);
+        fills[i] += take;
+        quantity -= take;
+    }
+}
+
+fn collect(fills: Vec<u64>) -> Vec<(usize, u64)> {
+    fills
+        .into_iter()
+        .enumerate()
+        .filter(|(_, f)| *f > 0)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::orderbook::Side;
+    use uuid::Uuid;
+
+    fn level(quantities: &[u64]) -> VecDeque<Order> {
+        quantities
+            .iter()
+            .map(|&quantity| Order {
+                id: Uuid::new_v4(),
+                user_id: Uuid::new_v4(),
+                side: Side::Bid,
+                price: 100_000_000,
+                quantity,
+                peg: None,
+            })
+            .collect()
+    }
+
+    fn pro_rata(min_allocation: u64, top_order_priority: bool) -> ProRata {
+        ProRata { min_allocation, top_order_priority }
+    }
+
+    #[test]
+    fn fifo_fills_from_the_front() {
+        assert_eq!(Fifo.allocate(&level(&[10, 20, 70]), 25), vec![(0, 10), (1, 15)]);
+    }
+
+    #[test]
+    fn pro_rata_rounds_down_and_gives_the_remainder_in_time_priority() {
+        // 1.5, 3 and 10.5 round down to 1, 3 and 10; the one sat left goes to the front
+        assert_eq!(pro_rata(0, false).allocate(&level(&[10, 20, 70]), 15), vec![(0, 2), (1, 3), (2, 10)]);
+    }
+
+    #[test]
+    fn pro_rata_fills_the_whole_level() {
+        assert_eq!(pro_rata(0, false).allocate(&level(&[10, 20, 70]), 100), vec![(0, 10), (1, 20), (2, 70)]);
+    }
+
+    #[test]
+    fn pro_rata_drops_shares_below_min_allocation() {
+        // shares of 1 and 3 are dropped, so the 5 left over goes to the front of the queue
+        assert_eq!(pro_rata(5, false).allocate(&level(&[10, 20, 70]), 15), vec![(0, 5), (2, 10)]);
+    }
+
+    #[test]
+    fn pro_rata_skips_full_orders_when_handing_out_the_remainder() {
+        let fills = pro_rata(0, false).allocate(&level(&[1, 1, 1]), 2);
+        assert_eq!(fills, vec![(0, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn top_order_priority_fills_the_first_order_before_splitting() {
+        // the first order takes all 10, then 30 is split 20:70 into 6 and 23, plus 1 remainder
+        assert_eq!(pro_rata(0, true).allocate(&level(&[10, 20, 70]), 40), vec![(0, 10), (1, 7), (2, 23)]);
+    }
+
+    #[test]
+    fn hybrid_splits_between_time_priority_and_pro_rata() {
+        let hybrid = Hybrid { fifo_bps: 5_000, pro_rata: pro_rata(0, false) };
+        // 20 in time priority fills the first order and half the second, then 20 is split
+        // over the 10 and 70 still open
+        assert_eq!(hybrid.allocate(&level(&[10, 20, 70]), 40), vec![(0, 10), (1, 13), (2, 17)]);
+    }
+
+    #[test]
+    fn fills_add_up_to_the_quantity() {
+        let queue = level(&[7, 13, 29, 3, 48]);
+        let strategies: Vec<Box<dyn AllocationStrategy + Send>> = vec![
+            MatchingAlgorithm::Fifo.strategy(),
+            MatchingAlgorithm::ProRata { min_allocation: 4, top_order_priority: true }.strategy(),
+            MatchingAlgorithm::Hybrid { fifo_bps: 3_333, min_allocation: 2, top_order_priority: false }.strategy(),
+        ];
+        for strategy in strategies {
+            for quantity in 0..=100 {
+                let fills = strategy.allocate(&queue, quantity);
+                assert_eq!(fills.iter().map(|(_, f)| f).sum::<u64>(), quantity);
+                assert!(fills.iter().all(|&(i, f)| f <= queue[i].quantity));
+            }
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::{Engine, calculate_cost_usdc_micro, now_millis};
+use super::feed::L3Event;
+use super::market::MarketState;
+use super::matching::{Trade, settle_trade};
+use super::orderbook::{Order, OrderBook, Side};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuctionInfo {
+    pub market: String,
+    pub state: MarketState,
+    /// Price the book would uncross at right now, in micro USDC.
+    pub indicative_price: Option<u64>,
+    /// Sats that would trade at `indicative_price`.
+    pub indicative_volume: u64,
+    /// Bid quantity at or above the indicative price left unmatched (negative: ask surplus).
+    pub imbalance: i128,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Uncross {
+    pub price: u64,
+    pub volume: u64,
+    pub imbalance: i128,
+}
+
+/// Finds the single price that maximises executed volume across a crossed book.
+///
+/// Ties are broken by the smallest imbalance, then by distance to `reference` (the last
+/// trade price) when there is one, then by the lower price.
+pub fn clearing_price(book: &OrderBook, reference: Option<u64>) -> Option<Uncross> {
+    let bids: Vec<(u64, u64)> = book.bids.iter().map(|(p, q)| (*p, q.iter().map(|o| o.quantity).sum())).collect();
+    let asks: Vec<(u64, u64)> = book.asks.iter().map(|(p, q)| (*p, q.iter().map(|o| o.quantity).sum())).collect();
+
+    let mut candidates: Vec<u64> = bids.iter().chain(asks.iter()).map(|(p, _)| *p).collect();
+    candidates.sort_unstable();
+    candidates.dedup();
+
+    let mut best: Option<Uncross> = None;
+    for price in candidates {
+        let buy: u64 = bids.iter().filter(|(p, _)| *p >= price).map(|(_, q)| q).sum();
+        let sell: u64 = asks.iter().filter(|(p, _)| *p <= price).map(|(_, q)| q).sum();
+        let volume = buy.min(sell);
+        if volume == 0 {
+            continue;
+        }
+
+        let candidate = Uncross {
+            price,
+            volume,
+            imbalance: buy as i128 - sell as i128,
+        };
+        let better = match best {
+            None => true,
+            Some(b) if volume != b.volume => volume > b.volume,
+            Some(b) if candidate.imbalance.abs() != b.imbalance.abs() => candidate.imbalance.abs() < b.imbalance.abs(),
+            Some(b) => match reference {
+                Some(r) => price.abs_diff(r) < b.price.abs_diff(r),
+                None => false,
+            },
+        };
+        if better {
+            best = Some(candidate);
+        }
+    }
+    best
+}
+
+impl Engine {
+    pub(super) fn auction_info(&self) -> AuctionInfo {
+        let uncross = clearing_price(&self.orderbook, self.last_trade_price);
+        AuctionInfo {
+            market: self.market.symbol.clone(),
+            state: self.market.state,
+            indicative_price: uncross.map(|u| u.price),
+            indicative_volume: uncross.map_or(0, |u| u.volume),
+            imbalance: uncross.map_or(0, |u| u.imbalance),
+        }
+    }
+
+    /// Executes every crossing order at the clearing price. Bids and asks are each filled in
+    /// price-time priority; fills are paired off in that order, with the bid reported as the
+    /// taker. Bids give back the difference between their limit and the clearing price.
+    pub(super) fn uncross_book(&mut self) -> Vec<Trade> {
+        let uncross = match clearing_price(&self.orderbook, self.last_trade_price) {
+            Some(u) => u,
+            None => return Vec::new(),
+        };
+        let price = uncross.price;
+        let now = now_millis();
+
+        let bid_fills = plan_fills(self.orderbook.bids.range(price..).rev().flat_map(|(_, q)| q.iter()), uncross.volume);
+        let ask_fills = plan_fills(self.orderbook.asks.range(..=price).flat_map(|(_, q)| q.iter()), uncross.volume);
+
+        let mut trades = Vec::new();
+        let (mut b, mut a) = (0, 0);
+        let mut bid_left = bid_fills.first().map_or(0, |f| f.quantity);
+        let mut ask_left = ask_fills.first().map_or(0, |f| f.quantity);
+
+        while b < bid_fills.len() && a < ask_fills.len() {
+            let bid = &bid_fills[b];
+            let ask = &ask_fills[a];
+            let qty = bid_left.min(ask_left);
+            let cost = calculate_cost_usdc_micro(price, qty).unwrap();
+
+            settle_trade(&mut self.balances, bid.user_id, ask.user_id, qty, cost);
+            // what this piece had locked at the bid's limit, taken off the order's remaining
+            // quantity so the pieces add up to exactly what was locked for the whole order
+            let resting = bid.resting - (bid.quantity - bid_left);
+            let reserved = calculate_cost_usdc_micro(bid.price, resting).unwrap()
+                - calculate_cost_usdc_micro(bid.price, resting - qty).unwrap();
+            debug_assert!(reserved >= cost, "bid {} reserved {reserved} for a fill costing {cost}", bid.order_id);
+            let refund = reserved - cost;
+            let usdc = self.balances.users.get_mut(&bid.user_id).unwrap().assets.get_mut("USDC").unwrap();
+            usdc.locked = usdc.locked.checked_sub(refund).expect("auction refund exceeds the buyer's locked USDC");
+            usdc.available += refund;
+
+            self.order_records.record_fill(bid.order_id, qty, price, now);
+            self.order_records.record_fill(ask.order_id, qty, price, now);
+
+            trades.push(Trade {
+                taker_order_id: bid.order_id,
+                maker_order_id: ask.order_id,
+                maker_user_id: ask.user_id,
+                taker_user_id: bid.user_id,
+                taker_side: Side::Bid,
+                price,
+                quantity: qty,
+            });
+
+            bid_left -= qty;
+            ask_left -= qty;
+            if bid_left == 0 {
+                b += 1;
+                bid_left = bid_fills.get(b).map_or(0, |f| f.quantity);
+            }
+            if ask_left == 0 {
+                a += 1;
+                ask_left = ask_fills.get(a).map_or(0, |f| f.quantity);
+            }
+        }
+
+        self.apply_fills(Side::Bid, &bid_fills);
+        self.apply_fills(Side::Ask, &ask_fills);
+        trades
+    }
+
+    /// Takes uncrossed quantity off the resting orders and drops the ones that are done.
+    fn apply_fills(&mut self, side: Side, fills: &[PlannedFill]) {
+        let now = now_millis();
+        let book_side = match side {
+            Side::Bid => &mut self.orderbook.bids,
+            Side::Ask => &mut self.orderbook.asks,
+        };
+
+        for fill in fills {
+            let queue = book_side.get_mut(&fill.price).unwrap();
+            let order = queue.iter_mut().find(|o| o.id == fill.order_id).unwrap();
+            self.risk.track_open(order.user_id, side, fill.price, order.quantity, order.quantity - fill.quantity);
+            order.quantity -= fill.quantity;
+            self.feed.publish_l3(L3Event::Execute {
+                order_id: fill.order_id,
+                side,
+                price: fill.price,
+                executed: fill.quantity,
+                remaining: order.quantity,
+            }, now);
+
+            if order.quantity == 0 {
+                queue.retain(|o| o.id != fill.order_id);
+                self.order_index.remove(&fill.order_id);
+            }
+            if queue.is_empty() {
+                book_side.remove(&fill.price);
+            }
+        }
+    }
+}
+
+struct PlannedFill {
+    order_id: Uuid,
+    user_id: Uuid,
+    price: u64,
+    /// The order's quantity before the uncross.
+    resting: u64,
+    quantity: u64,
+}
+
+fn plan_fills<'a>(orders: impl Iterator<Item = &'a Order>, volume: u64) -> Vec<PlannedFill> {
+    let mut left = volume;
+    let mut fills = Vec::new();
+    for order in orders {
+        if left == 0 {
+            break;
+        }
+        let quantity = order.quantity.min(left);
+        left -= quantity;
+        fills.push(PlannedFill {
+            order_id: order.id,
+            user_id: order.user_id,
+            price: order.price,
+            resting: order.quantity,
+            quantity,
+        });
+    }
+    fills
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::testing::{BTC, USDC, add_user, balance, engine};
+
+    fn book(bids: &[(u64, u64)], asks: &[(u64, u64)]) -> OrderBook {
+        let mut book = OrderBook::new();
+        let orders = bids.iter().map(|o| (Side::Bid, o)).chain(asks.iter().map(|o| (Side::Ask, o)));
+        for (side, &(price, quantity)) in orders {
+            book.add_order(Order {
+                id: Uuid::new_v4(),
+                user_id: Uuid::new_v4(),
+                side,
+                price: price * USDC,
+                quantity,
+                peg: None,
+            });
+        }
+        book
+    }
+
+    fn price(book: &OrderBook, reference: Option<u64>) -> Option<u64> {
+        clearing_price(book, reference.map(|r| r * USDC)).map(|u| u.price / USDC)
+    }
+
+    #[test]
+    fn uncrossed_book_has_no_clearing_price() {
+        assert_eq!(price(&book(&[(99, 10)], &[(100, 10)]), None), None);
+    }
+
+    #[test]
+    fn clearing_price_maximises_volume() {
+        let book = book(&[(105, 10)], &[(100, 3), (104, 7)]);
+        let uncross = clearing_price(&book, None).unwrap();
+        assert_eq!((uncross.price, uncross.volume, uncross.imbalance), (104 * USDC, 10, 0));
+    }
+
+    #[test]
+    fn clearing_price_ties_go_to_the_smaller_imbalance() {
+        // 10 trade at every price, but only at 100 is nothing left over; that beats the
+        // reference price being closer to 105
+        let book = book(&[(105, 10)], &[(100, 10), (103, 5)]);
+        assert_eq!(price(&book, Some(105)), Some(100));
+        assert_eq!(clearing_price(&book, None).unwrap().imbalance, 0);
+    }
+
+    #[test]
+    fn clearing_price_ties_go_to_the_price_nearest_the_reference() {
+        let book = book(&[(105, 10)], &[(100, 10)]);
+        assert_eq!(price(&book, Some(104)), Some(105));
+        assert_eq!(price(&book, Some(101)), Some(100));
+    }
+
+    #[test]
+    fn clearing_price_ties_go_to_the_lower_price() {
+        let book = book(&[(105, 10)], &[(100, 10)]);
+        assert_eq!(price(&book, None), Some(100));
+        // a reference of 102.5 is equally far from both
+        assert_eq!(clearing_price(&book, Some(102_500_000)).unwrap().price, 100 * USDC);
+    }
+
+    #[test]
+    fn uncross_book_refunds_bids_down_to_what_their_rest_still_needs() {
+        let mut engine = engine();
+        engine.set_market_state(MarketState::Auction);
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let seller = add_user(&mut engine, BTC, 0);
+
+        // 3 sats at 150 USDC lock 4 micro USDC (4.5 rounded down); each piece of the fill
+        // releases its own share of that, so the sats still resting keep exactly their cost
+        let bid = Uuid::new_v4();
+        engine.create_order(bid, buyer, Side::Bid, 150 * USDC, 3).unwrap();
+        engine.create_order(Uuid::new_v4(), seller, Side::Ask, 100 * USDC, 1).unwrap();
+        engine.create_order(Uuid::new_v4(), seller, Side::Ask, 100 * USDC, 1).unwrap();
+        assert_eq!(balance(&engine, buyer, "USDC").1, 4);
+
+        let trades = engine.uncross_book();
+        assert_eq!(trades.iter().map(|t| (t.price, t.quantity)).collect::<Vec<_>>(), vec![(100 * USDC, 1), (100 * USDC, 1)]);
+        assert!(!engine.orderbook.is_crossed());
+
+        let (available, locked) = balance(&engine, buyer, "USDC");
+        assert_eq!(locked, calculate_cost_usdc_micro(150 * USDC, 1).unwrap());
+        assert_eq!(balance(&engine, buyer, "BTC"), (2, 0));
+        assert_eq!(balance(&engine, seller, "USDC"), (2, 0));
+        assert_eq!(available + locked + 2, 1_000 * USDC);
+    }
+
+    #[test]
+    fn uncross_book_fills_in_price_time_priority_at_one_price() {
+        let mut engine = engine();
+        engine.set_market_state(MarketState::Auction);
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let seller = add_user(&mut engine, 2 * BTC, 0);
+
+        engine.create_order(Uuid::new_v4(), buyer, Side::Bid, 105 * USDC, 3 * BTC / 2).unwrap();
+        engine.create_order(Uuid::new_v4(), seller, Side::Ask, 100 * USDC, BTC / 2).unwrap();
+        engine.create_order(Uuid::new_v4(), seller, Side::Ask, 102 * USDC, BTC / 2).unwrap();
+
+        let trades = engine.uncross_book();
+        assert!(trades.iter().all(|t| t.price == 102 * USDC));
+        assert_eq!(trades.iter().map(|t| t.quantity).sum::<u64>(), BTC);
+
+        // 1 BTC bought at 102 out of 157.5 locked; 0.5 BTC still rests at 105
+        assert_eq!(balance(&engine, buyer, "USDC"), (1_000 * USDC - 157_500_000 + 3 * USDC, 52_500_000));
+        assert_eq!(balance(&engine, seller, "USDC"), (102 * USDC, 0));
+        assert_eq!(engine.orderbook.best_bid(), Some(105 * USDC));
+        assert_eq!(engine.orderbook.best_ask(), None);
+    }
+}
@@ -0,0 +1,37 @@
+use uuid::Uuid;
+
+use super::Engine;
+use super::client_orders::OrderKey;
+use super::orderbook::Side;
+
+/// Most items accepted in one batch.
+pub const MAX_BATCH_SIZE: usize = 50;
+
+/// One limit order in a batch, already converted to micro USDC and sats.
+#[derive(Debug, Clone)]
+pub struct BatchOrder {
+    pub side: Side,
+    pub price: u64,
+    pub quantity: u64,
+    pub client_order_id: Option<String>,
+}
+
+impl Engine {
+    /// Places each order in turn, exactly as separate `CreateOrder` commands would, so later
+    /// orders see the book and balances left by earlier ones. Yields the order id or the
+    /// rejection for each.
+    pub(super) fn batch_create_orders(&mut self, user_id: Uuid, orders: Vec<BatchOrder>) -> Vec<Result<String, String>> {
+        orders
+            .into_iter()
+            .map(|order| self.submit_limit_order(user_id, order.side, order.price, order.quantity, order.client_order_id))
+            .collect()
+    }
+
+    /// Cancels each order in turn; one failing doesn't stop the rest.
+    pub(super) fn batch_cancel_orders(&mut self, user_id: Uuid, orders: Vec<OrderKey>) -> Vec<Result<String, String>> {
+        orders
+            .iter()
+            .map(|order| self.cancel_order(user_id, order))
+            .collect()
+    }
+}
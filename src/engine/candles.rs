@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, BTreeSet};
+use serde::{Serialize, Deserialize};
+
+use super::calculate_cost_usdc_micro;
+
+/// Most candles returned by one query.
+pub const MAX_CANDLES_PER_QUERY: usize = 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CandleInterval {
+    #[serde(rename = "1m")]
+    OneMinute,
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[serde(rename = "15m")]
+    FifteenMinutes,
+    #[serde(rename = "1h")]
+    OneHour,
+    #[serde(rename = "1d")]
+    OneDay,
+}
+
+impl CandleInterval {
+    pub const ALL: [CandleInterval; 5] = [
+        CandleInterval::OneMinute,
+        CandleInterval::FiveMinutes,
+        CandleInterval::FifteenMinutes,
+        CandleInterval::OneHour,
+        CandleInterval::OneDay,
+    ];
+
+    pub fn millis(&self) -> u64 {
+        match self {
+            CandleInterval::OneMinute => 60_000,
+            CandleInterval::FiveMinutes => 5 * 60_000,
+            CandleInterval::FifteenMinutes => 15 * 60_000,
+            CandleInterval::OneHour => 60 * 60_000,
+            CandleInterval::OneDay => 24 * 60 * 60_000,
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|i| i.as_str() == s)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CandleInterval::OneMinute => "1m",
+            CandleInterval::FiveMinutes => "5m",
+            CandleInterval::FifteenMinutes => "15m",
+            CandleInterval::OneHour => "1h",
+            CandleInterval::OneDay => "1d",
+        }
+    }
+}
+
+/// OHLCV for one interval. Prices are micro USDC, base volume sats, quote volume micro USDC.
+/// Times are UTC milliseconds; `open_time` is a multiple of the interval length.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Candle {
+    pub interval: CandleInterval,
+    pub open_time: u64,
+    pub close_time: u64,
+    pub open: u64,
+    pub high: u64,
+    pub low: u64,
+    pub close: u64,
+    pub base_volume: u64,
+    pub quote_volume: u64,
+    pub trade_count: u64,
+}
+
+/// Candles for every interval, built trade by trade. Intervals without trades have no candle.
+#[derive(Debug, Clone)]
+pub struct CandleStore {
+    candles: BTreeMap<CandleInterval, BTreeMap<u64, Candle>>,
+    changed: BTreeSet<(CandleInterval, u64)>,
+}
+
+impl CandleStore {
+    pub fn new() -> Self {
+        Self {
+            candles: BTreeMap::new(),
+            changed: BTreeSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, price: u64, quantity: u64, timestamp: u64) {
+        let quote = calculate_cost_usdc_micro(price, quantity).unwrap_or(u64::MAX);
+
+        for interval in CandleInterval::ALL {
+            let length = interval.millis();
+            let open_time = timestamp / length * length;
+            let candle = self.candles
+                .entry(interval)
+                .or_default()
+                .entry(open_time)
+                .or_insert(Candle {
+                    interval,
+                    open_time,
+                    close_time: open_time + length - 1,
+                    open: price,
+                    high: price,
+                    low: price,
+                    close: price,
+                    base_volume: 0,
+                    quote_volume: 0,
+                    trade_count: 0,
+                });
+
+            candle.high = candle.high.max(price);
+            candle.low = candle.low.min(price);
+            candle.close = price;
+            candle.base_volume = candle.base_volume.saturating_add(quantity);
+            candle.quote_volume = candle.quote_volume.saturating_add(quote);
+            candle.trade_count += 1;
+            self.changed.insert((interval, open_time));
+        }
+    }
+
+    /// Candles opening in `[start, end)`, oldest first.
+    pub fn range(&self, interval: CandleInterval, start: u64, end: u64) -> Vec<Candle> {
+        match self.candles.get(&interval) {
+            Some(candles) if start < end => candles
+                .range(start..end)
+                .map(|(_, c)| c.clone())
+                .take(MAX_CANDLES_PER_QUERY)
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Candles touched since the last call.
+    pub fn take_changed(&mut self) -> Vec<Candle> {
+        std::mem::take(&mut self.changed)
+            .into_iter()
+            .filter_map(|(interval, open_time)| self.candles.get(&interval)?.get(&open_time).cloned())
+            .collect()
+    }
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::records::OrderRecords;
+
+/// Identical submissions with the same client order id inside this window get the
+/// original reply back instead of placing a second order.
+pub const CLIENT_ORDER_DEDUP_WINDOW_MS: u64 = 5 * 60 * 1000;
+
+/// How a request refers to an existing order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum OrderKey {
+    OrderId(Uuid),
+    ClientOrderId(String),
+}
+
+#[derive(Debug, Clone)]
+struct ClientOrderEntry {
+    order_id: Uuid,
+    fingerprint: String,
+    submitted_at: u64,
+    reply: Result<String, String>,
+}
+
+pub enum ClientOrderCheck {
+    /// Unused id (or one whose order is done), go ahead and place the order.
+    New,
+    /// Retry of a recent submission, answer with the stored reply.
+    Replay(Result<String, String>),
+    /// The id belongs to an open order or a different recent request.
+    Duplicate,
+}
+
+/// Maps `(user, client order id)` to the engine order it created. A mapping lives as long
+/// as the order's record does.
+#[derive(Debug, Clone)]
+pub struct ClientOrders {
+    entries: HashMap<(Uuid, String), ClientOrderEntry>,
+}
+
+impl ClientOrders {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn check(&self, user_id: Uuid, client_order_id: &str, fingerprint: &str, now: u64, records: &OrderRecords) -> ClientOrderCheck {
+        let entry = match self.entries.get(&(user_id, client_order_id.to_string())) {
+            Some(e) => e,
+            None => return ClientOrderCheck::New,
+        };
+
+        let within_window = now.saturating_sub(entry.submitted_at) < CLIENT_ORDER_DEDUP_WINDOW_MS;
+        if within_window && entry.fingerprint == fingerprint {
+            return ClientOrderCheck::Replay(entry.reply.clone());
+        }
+
+        let open = records
+            .get(&entry.order_id)
+            .is_some_and(|r| !r.status.is_terminal());
+        if open || within_window {
+            ClientOrderCheck::Duplicate
+        } else {
+            ClientOrderCheck::New
+        }
+    }
+
+    pub fn remember(&mut self, user_id: Uuid, client_order_id: String, order_id: Uuid, fingerprint: String, reply: Result<String, String>, now: u64) {
+        self.entries.insert((user_id, client_order_id), ClientOrderEntry {
+            order_id,
+            fingerprint,
+            submitted_at: now,
+            reply,
+        });
+    }
+
+    pub fn order_id(&self, user_id: Uuid, client_order_id: &str) -> Option<Uuid> {
+        self.entries
+            .get(&(user_id, client_order_id.to_string()))
+            .map(|e| e.order_id)
+    }
+
+    /// Drops the mapping once its order has been pruned, unless the id was reused since.
+    pub fn forget(&mut self, user_id: Uuid, client_order_id: &str, order_id: Uuid) {
+        let key = (user_id, client_order_id.to_string());
+        if self.entries.get(&key).is_some_and(|e| e.order_id == order_id) {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::engine::orderbook::Side;
+    use crate::engine::records::{OrderRecord, OrderStatus};
+    use crate::engine::testing::{BTC, USDC, add_user, balance, engine, resting};
+
+    const NOW: u64 = 1_000_000_000;
+
+    fn open_order(records: &mut OrderRecords, user_id: Uuid) -> Uuid {
+        let record = OrderRecord::new(Uuid::new_v4(), user_id, Side::Bid, Some(100), 1, NOW);
+        let order_id = record.id;
+        records.insert(record);
+        order_id
+    }
+
+    fn check(orders: &ClientOrders, user_id: Uuid, fingerprint: &str, now: u64, records: &OrderRecords) -> &'static str {
+        match orders.check(user_id, "c1", fingerprint, now, records) {
+            ClientOrderCheck::New => "new",
+            ClientOrderCheck::Replay(_) => "replay",
+            ClientOrderCheck::Duplicate => "duplicate",
+        }
+    }
+
+    #[test]
+    fn same_request_is_replayed_and_a_different_one_is_a_duplicate() {
+        let (mut orders, mut records) = (ClientOrders::new(), OrderRecords::new());
+        let user_id = Uuid::new_v4();
+        assert_eq!(check(&orders, user_id, "a", NOW, &records), "new");
+
+        let order_id = open_order(&mut records, user_id);
+        orders.remember(user_id, "c1".into(), order_id, "a".into(), Ok(order_id.to_string()), NOW);
+        match orders.check(user_id, "c1", "a", NOW + 1, &records) {
+            ClientOrderCheck::Replay(reply) => assert_eq!(reply, Ok(order_id.to_string())),
+            _ => panic!("expected a replay"),
+        }
+        assert_eq!(check(&orders, user_id, "b", NOW + 1, &records), "duplicate");
+        // ids are per user
+        assert_eq!(check(&orders, Uuid::new_v4(), "b", NOW + 1, &records), "new");
+    }
+
+    #[test]
+    fn ids_can_be_reused_once_the_window_has_passed_and_the_order_is_done() {
+        let (mut orders, mut records) = (ClientOrders::new(), OrderRecords::new());
+        let user_id = Uuid::new_v4();
+        let order_id = open_order(&mut records, user_id);
+        orders.remember(user_id, "c1".into(), order_id, "a".into(), Ok(order_id.to_string()), NOW);
+
+        let later = NOW + CLIENT_ORDER_DEDUP_WINDOW_MS;
+        assert_eq!(check(&orders, user_id, "a", later - 1, &records), "replay");
+        // still open, so even the same request can't reuse the id
+        assert_eq!(check(&orders, user_id, "a", later, &records), "duplicate");
+
+        records.finish(order_id, OrderStatus::Cancelled, later);
+        assert_eq!(check(&orders, user_id, "a", later, &records), "new");
+        assert_eq!(check(&orders, user_id, "b", later - 1, &records), "duplicate");
+    }
+
+    #[test]
+    fn forget_leaves_a_reused_id_alone() {
+        let mut orders = ClientOrders::new();
+        let user_id = Uuid::new_v4();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        orders.remember(user_id, "c1".into(), first, "a".into(), Ok(first.to_string()), NOW);
+        orders.remember(user_id, "c1".into(), second, "b".into(), Ok(second.to_string()), NOW);
+
+        orders.forget(user_id, "c1", first);
+        assert_eq!(orders.order_id(user_id, "c1"), Some(second));
+        orders.forget(user_id, "c1", second);
+        assert_eq!(orders.order_id(user_id, "c1"), None);
+    }
+
+    #[test]
+    fn engine_replays_retries_and_cancels_by_client_order_id() {
+        let mut engine = engine();
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let submit = |engine: &mut Engine, price| engine.submit_limit_order(buyer, Side::Bid, price, BTC, Some("c1".into()));
+
+        let order_id = submit(&mut engine, 100 * USDC).unwrap();
+        assert_eq!(submit(&mut engine, 100 * USDC), Ok(order_id.clone()));
+        assert_eq!(submit(&mut engine, 101 * USDC), Err("duplicate client order id".to_string()));
+        assert_eq!(resting(&engine, Side::Bid), vec![(100 * USDC, BTC)]);
+        assert_eq!(balance(&engine, buyer, "USDC"), (900 * USDC, 100 * USDC));
+
+        let key = OrderKey::ClientOrderId("c1".into());
+        assert_eq!(engine.resolve_order_key(buyer, &key), Some(Uuid::parse_str(&order_id).unwrap()));
+        assert_eq!(engine.resolve_order_key(Uuid::new_v4(), &key), None);
+        engine.cancel_order(buyer, &key).unwrap();
+        assert!(resting(&engine, Side::Bid).is_empty());
+        assert_eq!(engine.order_records.get(&Uuid::parse_str(&order_id).unwrap()).unwrap().client_order_id.as_deref(), Some("c1"));
+    }
+
+    #[test]
+    fn rejected_orders_replay_their_rejection() {
+        let mut engine = engine();
+        let buyer = add_user(&mut engine, 0, 10 * USDC);
+        let reply = engine.submit_limit_order(buyer, Side::Bid, 100 * USDC, BTC, Some("c1".into()));
+        assert_eq!(reply, Err("insufficient USDC funds".to_string()));
+        assert_eq!(engine.submit_limit_order(buyer, Side::Bid, 100 * USDC, BTC, Some("c1".into())), reply);
+    }
+}
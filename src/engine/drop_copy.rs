@@ -0,0 +1,77 @@
+use std::collections::VecDeque;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::user_feed::UserEvent;
+
+/// Number of recent drop copy records kept for replay.
+pub const DROP_COPY_REPLAY_CAPACITY: usize = 100_000;
+
+/// A fill or order state change for any user. Sequences are global, start at 1 and have no
+/// gaps, so a consumer can resume from the last one it processed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DropCopyRecord {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub user_id: Uuid,
+    /// Only `Order` and `Fill`; balances aren't copied.
+    #[serde(flatten)]
+    pub event: UserEvent,
+}
+
+pub enum DropCopyReplay {
+    /// Every record after the requested sequence, and the sequence of the latest one.
+    Records { sequence: u64, records: Vec<DropCopyRecord> },
+    /// The requested sequence is older than the replay buffer.
+    TooOld { oldest: u64 },
+}
+
+/// Sequences every execution across all users for back office consumers and keeps the
+/// recent ones for replay.
+pub struct DropCopy {
+    sequence: u64,
+    recent: VecDeque<DropCopyRecord>,
+    tx: broadcast::Sender<DropCopyRecord>,
+}
+
+impl DropCopy {
+    pub fn new(tx: broadcast::Sender<DropCopyRecord>) -> Self {
+        Self {
+            sequence: 0,
+            recent: VecDeque::new(),
+            tx,
+        }
+    }
+
+    pub fn publish(&mut self, user_id: Uuid, event: UserEvent, now: u64) {
+        self.sequence += 1;
+        let record = DropCopyRecord {
+            sequence: self.sequence,
+            timestamp: now,
+            user_id,
+            event,
+        };
+
+        if self.recent.len() == DROP_COPY_REPLAY_CAPACITY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(record.clone());
+        let _ = self.tx.send(record);
+    }
+
+    /// Records with a sequence above `after`; none if `after` is `None`, for a consumer that
+    /// only wants what happens from now on.
+    pub fn since(&self, after: Option<u64>) -> DropCopyReplay {
+        let Some(after) = after else {
+            return DropCopyReplay::Records { sequence: self.sequence, records: Vec::new() };
+        };
+        match self.recent.front() {
+            Some(oldest) if after < oldest.sequence - 1 => DropCopyReplay::TooOld { oldest: oldest.sequence },
+            _ => DropCopyReplay::Records {
+                sequence: self.sequence,
+                records: self.recent.iter().filter(|r| r.sequence > after).cloned().collect(),
+            },
+        }
+    }
+}
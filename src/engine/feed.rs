@@ -0,0 +1,295 @@
+use std::collections::{BTreeSet, VecDeque};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::{DepthLevel, Engine, now_millis};
+use super::candles::Candle;
+use super::matching::Trade;
+use super::orderbook::{Order, Side};
+use super::ticker::Ticker;
+use crate::math;
+
+/// Number of recent L3 updates kept for `/l3_updates` catch-up.
+pub const L3_REPLAY_CAPACITY: usize = 10_000;
+
+/// Order-by-order book change. Prices are micro USDC, quantities sats; user ids are never
+/// included and `price` is always the order's resting level. An `Add` joins the back of its
+/// level, a `Modify` is a reprice that moves the order to the back of the new level, and an
+/// `Execute` that leaves nothing removes the order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum L3Event {
+    Add { order_id: Uuid, side: Side, price: u64, quantity: u64 },
+    Modify { order_id: Uuid, side: Side, old_price: u64, price: u64, quantity: u64 },
+    Delete { order_id: Uuid, side: Side, price: u64 },
+    Execute { order_id: Uuid, side: Side, price: u64, executed: u64, remaining: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct L3Update {
+    pub sequence: u64,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: L3Event,
+}
+
+/// Price levels that changed during one engine command. A level with `orders: 0` is gone.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DepthUpdate {
+    pub sequence: u64,
+    pub timestamp: u64,
+    /// `OrderBook::checksum` of the book once this update is applied.
+    pub checksum: u32,
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PublicTrade {
+    pub trade_id: u64,
+    pub timestamp: u64,
+    pub price: String,
+    pub quantity: String,
+    pub taker_side: Side,
+}
+
+/// Top of book. Sizes are the total quantity resting at the best price.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Bbo {
+    pub market: String,
+    pub bid_price: Option<String>,
+    pub bid_size: Option<String>,
+    pub ask_price: Option<String>,
+    pub ask_size: Option<String>,
+}
+
+/// Everything the engine publishes to market data consumers.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum FeedEvent {
+    L3(L3Update),
+    Depth(DepthUpdate),
+    Trade(PublicTrade),
+    Ticker(Box<Ticker>),
+    Candle(Candle),
+    Bbo(Bbo),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct L3Order {
+    pub order_id: Uuid,
+    pub price: u64,
+    pub quantity: u64,
+    /// Index within the price level's queue, 0 being next to trade.
+    pub position: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct L3Snapshot {
+    pub market: String,
+    /// Sequence of the last update already reflected in the snapshot.
+    pub sequence: u64,
+    pub bids: Vec<L3Order>,
+    pub asks: Vec<L3Order>,
+}
+
+pub enum L3Replay {
+    Updates(Vec<L3Update>),
+    /// The requested sequence is older than the replay buffer; take a new snapshot.
+    TooOld { oldest: u64 },
+}
+
+/// Sequences book changes, keeps the recent ones for catch-up and fans them out to
+/// subscribers. Sending never blocks the engine; with no subscribers events are just dropped.
+pub struct MarketFeed {
+    l3_sequence: u64,
+    recent_l3: VecDeque<L3Update>,
+    depth_sequence: u64,
+    changed_bids: BTreeSet<u64>,
+    changed_asks: BTreeSet<u64>,
+    trade_sequence: u64,
+    last_ticker: Option<Ticker>,
+    last_bbo: Option<Bbo>,
+    tx: broadcast::Sender<FeedEvent>,
+}
+
+impl MarketFeed {
+    pub fn new(tx: broadcast::Sender<FeedEvent>) -> Self {
+        Self {
+            l3_sequence: 0,
+            recent_l3: VecDeque::new(),
+            depth_sequence: 0,
+            changed_bids: BTreeSet::new(),
+            changed_asks: BTreeSet::new(),
+            trade_sequence: 0,
+            last_ticker: None,
+            last_bbo: None,
+            tx,
+        }
+    }
+
+    pub fn l3_sequence(&self) -> u64 {
+        self.l3_sequence
+    }
+
+    pub fn depth_sequence(&self) -> u64 {
+        self.depth_sequence
+    }
+
+    pub fn publish_l3(&mut self, event: L3Event, now: u64) {
+        match &event {
+            L3Event::Add { side, price, .. } | L3Event::Delete { side, price, .. } | L3Event::Execute { side, price, .. } => {
+                self.level_changed(*side, *price);
+            }
+            L3Event::Modify { side, old_price, price, .. } => {
+                self.level_changed(*side, *old_price);
+                self.level_changed(*side, *price);
+            }
+        }
+
+        self.l3_sequence += 1;
+        let update = L3Update {
+            sequence: self.l3_sequence,
+            timestamp: now,
+            event,
+        };
+
+        if self.recent_l3.len() == L3_REPLAY_CAPACITY {
+            self.recent_l3.pop_front();
+        }
+        self.recent_l3.push_back(update.clone());
+        let _ = self.tx.send(FeedEvent::L3(update));
+    }
+
+    pub fn resume_trade_ids(&mut self, last_trade_id: u64) {
+        self.trade_sequence = last_trade_id;
+    }
+
+    /// Publishes a trade and returns the id it was given.
+    pub fn publish_trade(&mut self, trade: &Trade, now: u64) -> u64 {
+        self.trade_sequence += 1;
+        let _ = self.tx.send(FeedEvent::Trade(PublicTrade {
+            trade_id: self.trade_sequence,
+            timestamp: now,
+            price: math::micro_to_price_string(trade.price),
+            quantity: math::sats_to_btc_string(trade.quantity),
+            taker_side: trade.taker_side,
+        }));
+        self.trade_sequence
+    }
+
+    fn level_changed(&mut self, side: Side, price: u64) {
+        match side {
+            Side::Bid => self.changed_bids.insert(price),
+            Side::Ask => self.changed_asks.insert(price),
+        };
+    }
+
+    /// Updates with a sequence above `after`.
+    pub fn l3_since(&self, after: u64) -> L3Replay {
+        match self.recent_l3.front() {
+            Some(oldest) if after + 1 < oldest.sequence => L3Replay::TooOld { oldest: oldest.sequence },
+            _ => L3Replay::Updates(self.recent_l3.iter().filter(|u| u.sequence > after).cloned().collect()),
+        }
+    }
+}
+
+impl Engine {
+    /// Publishes the depth levels, candles, top of book and ticker changed by the command just
+    /// handled, so depth subscribers see one sequenced update per command. The BBO and ticker
+    /// only go out when they differ from the last ones sent.
+    pub(super) fn flush_feed(&mut self) {
+        let now = now_millis();
+        let changed_bids = std::mem::take(&mut self.feed.changed_bids);
+        let changed_asks = std::mem::take(&mut self.feed.changed_asks);
+
+        if !changed_bids.is_empty() || !changed_asks.is_empty() {
+            let level = |side, price: u64| {
+                let book_side = match side {
+                    Side::Bid => &self.orderbook.bids,
+                    Side::Ask => &self.orderbook.asks,
+                };
+                let queue = book_side.get(&price);
+                DepthLevel {
+                    price: math::micro_to_price_string(price),
+                    quantity: math::sats_to_btc_string(queue.map_or(0, |q| q.iter().map(|o| o.quantity).sum())),
+                    orders: queue.map_or(0, |q| q.len()),
+                }
+            };
+
+            self.feed.depth_sequence += 1;
+            let update = DepthUpdate {
+                sequence: self.feed.depth_sequence,
+                timestamp: now,
+                checksum: self.orderbook.checksum(),
+                bids: changed_bids.iter().rev().map(|p| level(Side::Bid, *p)).collect(),
+                asks: changed_asks.iter().map(|p| level(Side::Ask, *p)).collect(),
+            };
+            let _ = self.feed.tx.send(FeedEvent::Depth(update));
+        }
+
+        for candle in self.candles.take_changed() {
+            let _ = self.feed.tx.send(FeedEvent::Candle(candle));
+        }
+        if let Some(journal) = &mut self.journal
+            && let Err(e) = journal.flush()
+        {
+            println!("failed to flush trade journal: {e}");
+        }
+
+        let bbo = self.bbo();
+        if self.feed.last_bbo.as_ref() != Some(&bbo) {
+            self.feed.last_bbo = Some(bbo.clone());
+            let _ = self.feed.tx.send(FeedEvent::Bbo(bbo));
+        }
+
+        let ticker = self.ticker(now);
+        if self.feed.last_ticker.as_ref() != Some(&ticker) {
+            self.feed.last_ticker = Some(ticker.clone());
+            let _ = self.feed.tx.send(FeedEvent::Ticker(Box::new(ticker)));
+        }
+    }
+
+    pub(super) fn bbo(&self) -> Bbo {
+        let top = |book_side: Option<(&u64, &VecDeque<Order>)>| match book_side {
+            Some((price, queue)) => (
+                Some(math::micro_to_price_string(*price)),
+                Some(math::sats_to_btc_string(queue.iter().map(|o| o.quantity).sum())),
+            ),
+            None => (None, None),
+        };
+        let (bid_price, bid_size) = top(self.orderbook.bids.iter().next_back());
+        let (ask_price, ask_size) = top(self.orderbook.asks.iter().next());
+
+        Bbo {
+            market: self.market.symbol.clone(),
+            bid_price,
+            bid_size,
+            ask_price,
+            ask_size,
+        }
+    }
+
+    /// Every resting order in book priority, without user ids, as of the current L3 sequence.
+    pub(super) fn l3_snapshot(&self) -> L3Snapshot {
+        L3Snapshot {
+            market: self.market.symbol.clone(),
+            sequence: self.feed.l3_sequence(),
+            bids: l3_orders(self.orderbook.bids.iter().rev()),
+            asks: l3_orders(self.orderbook.asks.iter()),
+        }
+    }
+}
+
+fn l3_orders<'a>(levels: impl Iterator<Item = (&'a u64, &'a VecDeque<Order>)>) -> Vec<L3Order> {
+    levels
+        .flat_map(|(price, queue)| {
+            queue.iter().enumerate().map(move |(position, o)| L3Order {
+                order_id: o.id,
+                price: *price,
+                quantity: o.quantity,
+                position,
+            })
+        })
+        .collect()
+}
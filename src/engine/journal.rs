@@ -0,0 +1,79 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::Engine;
+use super::orderbook::Side;
+
+/// A printed trade as written to the journal. Prices are micro USDC, quantities sats.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JournaledTrade {
+    pub trade_id: u64,
+    pub timestamp: u64,
+    pub price: u64,
+    pub quantity: u64,
+    pub taker_side: Side,
+}
+
+/// Append-only file of every trade, one JSON object per line.
+pub struct TradeJournal {
+    writer: BufWriter<File>,
+}
+
+impl TradeJournal {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+
+    /// Reads back every trade in the journal. A missing file is an empty journal; a line
+    /// that doesn't parse (e.g. cut short by a crash) is skipped.
+    pub fn read(path: &Path) -> io::Result<Vec<JournaledTrade>> {
+        let file = match File::open(path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut trades = Vec::new();
+        for line in BufReader::new(file).lines() {
+            match serde_json::from_str(&line?) {
+                Ok(trade) => trades.push(trade),
+                Err(e) => println!("skipping unreadable trade journal line: {e}"),
+            }
+        }
+        Ok(trades)
+    }
+
+    pub fn append(&mut self, trade: &JournaledTrade) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, trade)?;
+        self.writer.write_all(b"\n")
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl Engine {
+    /// Rebuilds candle history and the 24h ticker from the journal at `path` and appends new
+    /// trades to it. Trade ids carry on from the last journaled trade.
+    pub(super) fn open_journal(&mut self, path: &Path) -> io::Result<()> {
+        let trades = TradeJournal::read(path)?;
+        for trade in &trades {
+            self.candles.record(trade.price, trade.quantity, trade.timestamp);
+            self.day_stats.record(trade.price, trade.quantity, trade.timestamp);
+        }
+        self.candles.take_changed();
+        if let Some(last) = trades.last() {
+            self.feed.resume_trade_ids(last.trade_id);
+        }
+        println!("replayed {} trades from {}", trades.len(), path.display());
+
+        self.journal = Some(TradeJournal::open(path)?);
+        Ok(())
+    }
+}
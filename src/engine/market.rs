@@ -0,0 +1,69 @@
+use serde::{Serialize, Deserialize};
+
+use super::allocation::{AllocationStrategy, MatchingAlgorithm};
+use super::volatility::VolatilityControls;
+
+pub const MARKET_SYMBOL: &str = "BTC-USDC";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketState {
+    /// Orders match as they arrive.
+    Continuous,
+    /// Nothing is accepted, not even cancels. The book is frozen as it is.
+    Halted,
+    /// Only cancels are accepted.
+    CancelOnly,
+    /// New orders are accepted only if they would rest without taking liquidity.
+    PostOnly,
+    /// Orders rest without matching until the auction is ended and the book uncrossed.
+    Auction,
+}
+
+impl MarketState {
+    pub fn check_new_order(&self) -> Result<(), String> {
+        match self {
+            MarketState::Halted => Err("market is halted".into()),
+            MarketState::CancelOnly => Err("market is cancel-only".into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn check_cancel(&self) -> Result<(), String> {
+        match self {
+            MarketState::Halted => Err("market is halted".into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether resting pegged orders follow the book in this state.
+    pub fn reprices_pegs(&self) -> bool {
+        matches!(self, MarketState::Continuous | MarketState::PostOnly)
+    }
+}
+
+/// Per-market settings. The engine runs a single BTC-USDC market today.
+pub struct Market {
+    pub symbol: String,
+    pub state: MarketState,
+    pub matching: MatchingAlgorithm,
+    pub allocator: Box<dyn AllocationStrategy + Send>,
+    pub volatility: VolatilityControls,
+}
+
+impl Market {
+    pub fn new() -> Self {
+        let matching = MatchingAlgorithm::Fifo;
+        Self {
+            symbol: MARKET_SYMBOL.to_string(),
+            state: MarketState::Continuous,
+            matching,
+            allocator: matching.strategy(),
+            volatility: VolatilityControls::new(),
+        }
+    }
+
+    pub fn set_matching(&mut self, matching: MatchingAlgorithm) {
+        self.matching = matching;
+        self.allocator = matching.strategy();
+    }
+}
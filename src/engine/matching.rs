@@ -0,0 +1,178 @@
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::{Engine, calculate_cost_usdc_micro, now_millis};
+use super::balance::Balances;
+use super::feed::L3Event;
+use super::orderbook::Side;
+use super::records::{OrderRecord, OrderStatus};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Trade {
+    pub taker_order_id: Uuid,
+    pub maker_order_id: Uuid,
+    pub maker_user_id: Uuid,
+    pub taker_user_id: Uuid,
+    pub taker_side: Side,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+impl Engine {
+    /// Walks the opposite side of the book for an incoming order and settles every fill.
+    /// Within a price level the fills are split by the market's allocation strategy, and the
+    /// sweep stops at a level that falls outside the price band or trips the circuit breaker.
+    ///
+    /// `limit: None` is a market order. Limit bids must already have USDC locked at `limit`,
+    /// asks must already have their BTC locked; market bids pay from available USDC as they
+    /// fill and stop once the buyer can't afford the next sat. Returns the unfilled quantity.
+    pub(super) fn match_incoming(&mut self, order_id: Uuid, user_id: Uuid, side: Side, limit: Option<u64>, quantity: u64) -> (u64, Vec<Trade>) {
+        let now = now_millis();
+        let mut remaining = quantity;
+        let mut trades = Vec::new();
+
+        while remaining > 0 {
+            let level_price = match side {
+                Side::Bid => self.orderbook.best_ask(),
+                Side::Ask => self.orderbook.best_bid(),
+            };
+            let level_price = match (level_price, limit) {
+                (None, _) => break,
+                (Some(p), None) => p,
+                (Some(p), Some(l)) if side == Side::Bid && p <= l => p,
+                (Some(p), Some(l)) if side == Side::Ask && p >= l => p,
+                _ => break,
+            };
+
+            if !self.may_trade_at(level_price, now) {
+                break;
+            }
+
+            let book_side = match side {
+                Side::Bid => &mut self.orderbook.asks,
+                Side::Ask => &mut self.orderbook.bids,
+            };
+            let queue = book_side.get_mut(&level_price).unwrap();
+
+            let level_qty: u64 = queue.iter().map(|o| o.quantity).sum();
+            let wanted = remaining.min(level_qty);
+            let market_bid = side == Side::Bid && limit.is_none();
+
+            let take = if market_bid {
+                let usdc = &self.balances.users[&user_id].assets["USDC"];
+                wanted.min(max_affordable_sats(level_price, usdc.available))
+            } else {
+                wanted
+            };
+            if take == 0 {
+                break;
+            }
+
+            for (i, trade_qty) in self.market.allocator.allocate(queue, take) {
+                let maker = &mut queue[i];
+                let trade_cost = calculate_cost_usdc_micro(level_price, trade_qty).unwrap();
+
+                if market_bid {
+                    let usdc = self.balances.users.get_mut(&user_id).unwrap().assets.get_mut("USDC").unwrap();
+                    usdc.available -= trade_cost;
+                    usdc.locked += trade_cost;
+                }
+
+                let (buyer, seller) = match side {
+                    Side::Bid => (user_id, maker.user_id),
+                    Side::Ask => (maker.user_id, user_id),
+                };
+                settle_trade(&mut self.balances, buyer, seller, trade_qty, trade_cost);
+
+                self.order_records.record_fill(maker.id, trade_qty, level_price, now);
+                self.order_records.record_fill(order_id, trade_qty, level_price, now);
+
+                trades.push(Trade {
+                    taker_order_id: order_id,
+                    maker_order_id: maker.id,
+                    maker_user_id: maker.user_id,
+                    taker_user_id: user_id,
+                    taker_side: side,
+                    price: level_price,
+                    quantity: trade_qty,
+                });
+
+                self.risk.track_open(maker.user_id, maker.side, level_price, maker.quantity, maker.quantity - trade_qty);
+                maker.quantity -= trade_qty;
+                self.feed.publish_l3(L3Event::Execute {
+                    order_id: maker.id,
+                    side: maker.side,
+                    price: level_price,
+                    executed: trade_qty,
+                    remaining: maker.quantity,
+                }, now);
+            }
+            remaining -= take;
+
+            let order_index = &mut self.order_index;
+            queue.retain(|o| {
+                if o.quantity == 0 {
+                    order_index.remove(&o.id);
+                }
+                o.quantity > 0
+            });
+            if queue.is_empty() {
+                book_side.remove(&level_price);
+            }
+            self.record_trade_price(level_price, now);
+
+            // a market bid that couldn't afford the whole level has run out of funds
+            if take < wanted {
+                break;
+            }
+        }
+
+        (remaining, trades)
+    }
+
+    /// Runs an immediate-or-cancel market order for whatever the user can fund right now.
+    /// Any quantity that can't be filled is dropped rather than rested and the order expires.
+    pub(super) fn execute_market_order(&mut self, order_id: Uuid, user_id: Uuid, side: Side, quantity: u64) -> Vec<Trade> {
+        self.order_records.insert(OrderRecord::new(order_id, user_id, side, None, quantity, now_millis()));
+
+        let trades = match side {
+            Side::Bid => self.match_incoming(order_id, user_id, side, None, quantity).1,
+            Side::Ask => {
+                let btc = self.balances.users.get_mut(&user_id).unwrap().assets.get_mut("BTC").unwrap();
+                let sell_qty = quantity.min(btc.available);
+                btc.available -= sell_qty;
+                btc.locked += sell_qty;
+
+                let (remaining, trades) = self.match_incoming(order_id, user_id, side, None, sell_qty);
+
+                let btc = self.balances.users.get_mut(&user_id).unwrap().assets.get_mut("BTC").unwrap();
+                btc.locked -= remaining;
+                btc.available += remaining;
+                trades
+            }
+        };
+
+        self.order_records.finish(order_id, OrderStatus::Expired, now_millis());
+        trades
+    }
+}
+
+/// Moves BTC from the seller's locked balance to the buyer and USDC from the buyer's
+/// locked balance to the seller. Both users are looked up separately so self-trades are safe.
+pub(super) fn settle_trade(balances: &mut Balances, buyer: Uuid, seller: Uuid, qty: u64, cost: u64) {
+    let buyer_assets = &mut balances.users.get_mut(&buyer).unwrap().assets;
+    buyer_assets.get_mut("BTC").unwrap().available += qty;
+    buyer_assets.get_mut("USDC").unwrap().locked -= cost;
+
+    let seller_assets = &mut balances.users.get_mut(&seller).unwrap().assets;
+    seller_assets.get_mut("BTC").unwrap().locked -= qty;
+    seller_assets.get_mut("USDC").unwrap().available += cost;
+}
+
+fn max_affordable_sats(price_micro: u64, usdc_micro: u64) -> u64 {
+    if price_micro == 0 {
+        return u64::MAX;
+    }
+    let sats = (usdc_micro as u128 * 100_000_000u128) / price_micro as u128;
+    u64::try_from(sats).unwrap_or(u64::MAX)
+}
@@ -1,381 +1,725 @@
-use std::sync::mpsc::Receiver;
+use std::path::PathBuf;
 use uuid::Uuid;
-use std::collections::HashMap;
-use tokio::sync::oneshot;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, oneshot};
 use serde::{Serialize, Deserialize};
 use crate::math;
 
+pub mod allocation;
+pub mod auction;
 pub mod balance;
+pub mod batch;
+pub mod candles;
+pub mod client_orders;
+pub mod drop_copy;
+pub mod feed;
+pub mod journal;
+pub mod market;
+pub mod matching;
 pub mod orderbook;
+pub mod peg;
+pub mod queue;
+pub mod records;
+pub mod risk;
+pub mod stops;
+#[cfg(test)]
+mod testing;
+pub mod ticker;
+pub mod user_feed;
+pub mod volatility;
 
+use allocation::MatchingAlgorithm;
+use auction::AuctionInfo;
 use balance::{AssetBalance, UserBalance, Balances};
+use batch::BatchOrder;
+use candles::{Candle, CandleInterval, CandleStore};
+use client_orders::{ClientOrderCheck, ClientOrders, OrderKey};
+use drop_copy::{DropCopy, DropCopyRecord, DropCopyReplay};
+use feed::{Bbo, FeedEvent, L3Event, L3Replay, L3Snapshot, MarketFeed};
+use journal::{JournaledTrade, TradeJournal};
+use market::{Market, MarketState};
+use matching::Trade;
 use orderbook::{OrderBook, Side, Order};
+use peg::Peg;
+use queue::CommandReceiver;
+use records::{OrderRecord, OrderRecords, OrderStatus};
+use risk::{RiskLimits, RiskState};
+use stops::{TrailAmount, TrailingStop};
+use ticker::{RollingStats, Ticker};
+use user_feed::{UserFeed, UserSnapshot, UserUpdates};
+use volatility::{PriceWindow, VolatilityControls};
 
 pub enum EngineCommand {
     InitializeUser { tx_oneshot: oneshot::Sender<String> },
     Deposit { user_id: Uuid, asset: String, amount: u64, tx_oneshot: oneshot::Sender<String> },
     GetBalances { user_id: Uuid, tx_oneshot: oneshot::Sender<Option<UserBalance>> },
-    CreateOrder { user_id: Uuid, side: Side, price: u64, quantity: u64, tx_oneshot: oneshot::Sender<String> },
-    CancelOrder { user_id: Uuid, order_id: Uuid, tx_oneshot: oneshot::Sender<String> },
+    /// Replies with the new order's id or why it was rejected.
+    CreateOrder { user_id: Uuid, side: Side, price: u64, quantity: u64, client_order_id: Option<String>, tx_oneshot: oneshot::Sender<Result<String, String>> },
+    CreatePeggedOrder { user_id: Uuid, side: Side, quantity: u64, peg: Peg, client_order_id: Option<String>, tx_oneshot: oneshot::Sender<Result<String, String>> },
+    CreateTrailingStop { user_id: Uuid, side: Side, quantity: u64, trail: TrailAmount, tx_oneshot: oneshot::Sender<Result<String, String>> },
+    CancelOrder { user_id: Uuid, order: OrderKey, tx_oneshot: oneshot::Sender<Result<String, String>> },
+    /// Cancels `order` and enters a new limit order in the same step. Errors only if the
+    /// cancel fails, leaving the original untouched; otherwise replies with the new order's
+    /// id or why it was rejected.
+    ReplaceOrder { user_id: Uuid, order: OrderKey, side: Side, price: u64, quantity: u64, client_order_id: Option<String>, tx_oneshot: oneshot::Sender<Result<Result<String, String>, String>> },
+    /// Places the orders one after another in a single engine step.
+    BatchCreateOrders { user_id: Uuid, orders: Vec<BatchOrder>, tx_oneshot: oneshot::Sender<Vec<Result<String, String>>> },
+    BatchCancelOrders { user_id: Uuid, orders: Vec<OrderKey>, tx_oneshot: oneshot::Sender<Vec<Result<String, String>>> },
     GetUserOrders { user_id: Uuid, tx_oneshot: oneshot::Sender<Vec<Order>> },
-    GetDepth { tx_oneshot: oneshot::Sender<DepthResponse> },
+    GetOrder { user_id: Uuid, order: OrderKey, tx_oneshot: oneshot::Sender<Option<OrderRecord>> },
+    GetUserTrailingStops { user_id: Uuid, tx_oneshot: oneshot::Sender<Vec<TrailingStop>> },
+    GetDepth { levels: Option<usize>, bucket: Option<u64>, tx_oneshot: oneshot::Sender<DepthResponse> },
+    GetMatchingAlgorithm { tx_oneshot: oneshot::Sender<MatchingAlgorithm> },
+    SetMatchingAlgorithm { matching: MatchingAlgorithm, tx_oneshot: oneshot::Sender<String> },
+    SetMarketState { state: MarketState, tx_oneshot: oneshot::Sender<String> },
+    GetVolatilityControls { tx_oneshot: oneshot::Sender<VolatilityControls> },
+    SetVolatilityControls { controls: VolatilityControls, tx_oneshot: oneshot::Sender<String> },
+    GetRiskLimits { user_id: Uuid, tx_oneshot: oneshot::Sender<RiskLimits> },
+    SetRiskLimits { user_id: Uuid, limits: RiskLimits, tx_oneshot: oneshot::Sender<String> },
+    StartAuction { tx_oneshot: oneshot::Sender<String> },
+    EndAuction { tx_oneshot: oneshot::Sender<String> },
+    GetAuctionInfo { tx_oneshot: oneshot::Sender<AuctionInfo> },
+    GetL3Snapshot { tx_oneshot: oneshot::Sender<L3Snapshot> },
+    GetL3Updates { after: u64, tx_oneshot: oneshot::Sender<L3Replay> },
+    GetTicker { tx_oneshot: oneshot::Sender<Ticker> },
+    GetBbo { tx_oneshot: oneshot::Sender<Bbo> },
+    GetCandles { interval: CandleInterval, start: u64, end: u64, tx_oneshot: oneshot::Sender<Vec<Candle>> },
+    GetUserSnapshot { user_id: Uuid, tx_oneshot: oneshot::Sender<Option<UserSnapshot>> },
+    GetDropCopy { after: Option<u64>, tx_oneshot: oneshot::Sender<DropCopyReplay> },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)] 
 pub struct DepthLevel {
     pub price: String,
     pub quantity: String,
+    pub orders: usize,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct DepthResponse {
+    /// Depth update sequence the snapshot is consistent with.
+    pub sequence: u64,
+    /// `OrderBook::checksum` of the whole book at `sequence`, whatever levels were asked for.
+    pub checksum: u32,
     pub bids: Vec<DepthLevel>,
     pub asks: Vec<DepthLevel>,
 }
 
-pub fn run(rx: Receiver<EngineCommand>) {
-    println!("engine thread has started...");
+struct Engine {
+    market: Market,
+    balances: Balances,
+    orderbook: OrderBook,
+    order_index: HashMap<Uuid, (Side, u64)>,
+    last_trade_price: Option<u64>,
+    trailing_stops: Vec<TrailingStop>,
+    pegged_orders: HashSet<Uuid>,
+    order_records: OrderRecords,
+    client_orders: ClientOrders,
+    recent_prices: PriceWindow,
+    risk: RiskState,
+    feed: MarketFeed,
+    user_feed: UserFeed,
+    drop_copy: DropCopy,
+    candles: CandleStore,
+    day_stats: RollingStats,
+    journal: Option<TradeJournal>,
+}
 
-    let mut balances = Balances::new();
-    let mut orderbook = OrderBook::new();
-    let mut order_index: HashMap<Uuid, (Side, u64)> = HashMap::new();
-
-    for cmd in rx {
-        match cmd {
-            EngineCommand::InitializeUser { tx_oneshot} => {
-                let user_id = Uuid::new_v4();
-                println!("initializing balances for {user_id}");
-                balances.users.insert(
-                    user_id,
-                    UserBalance {
-                        assets: HashMap::from([
-                            ("BTC".to_string(), AssetBalance { available: 0, locked: 0 } ),
-                            ("USDC".to_string(), AssetBalance { available: 0, locked: 0 } ),
-                        ])
-                });
-
-                let _ = tx_oneshot.send(user_id.to_string());
+impl Engine {
+    fn new(feed: MarketFeed, user_feed: UserFeed, drop_copy: DropCopy) -> Self {
+        Self {
+            market: Market::new(),
+            balances: Balances::new(),
+            orderbook: OrderBook::new(),
+            order_index: HashMap::new(),
+            last_trade_price: None,
+            trailing_stops: Vec::new(),
+            pegged_orders: HashSet::new(),
+            order_records: OrderRecords::new(),
+            client_orders: ClientOrders::new(),
+            recent_prices: PriceWindow::new(),
+            risk: RiskState::new(),
+            feed,
+            user_feed,
+            drop_copy,
+            candles: CandleStore::new(),
+            day_stats: RollingStats::new(),
+            journal: None,
+        }
+    }
+
+    /// Places an order through `place`, deduplicating on the client order id if one was given.
+    /// `fingerprint` identifies the request so a retry can be told apart from a reused id.
+    fn submit_order(&mut self, user_id: Uuid, client_order_id: Option<String>, fingerprint: String, place: impl FnOnce(&mut Self, Uuid) -> Result<String, String>) -> Result<String, String> {
+        let now = now_millis();
+
+        if let Some(client_order_id) = &client_order_id {
+            match self.client_orders.check(user_id, client_order_id, &fingerprint, now, &self.order_records) {
+                ClientOrderCheck::Replay(reply) => return reply,
+                ClientOrderCheck::Duplicate => return Err("duplicate client order id".into()),
+                ClientOrderCheck::New => {}
             }
-            EngineCommand::Deposit {user_id, asset, amount, tx_oneshot} => {
-                if let Some(user) = balances.users.get_mut(&user_id) {
-                    if let Some(entry) = user.assets.get_mut(&asset) {
-                        entry.available = entry.available
-                            .checked_add(amount)
-                            .expect("overflow in deposit");
-
-                        let _ = tx_oneshot.send(format!("deposited {} {} for user {}", amount, asset, user_id));
-                    } else {
-                        let _ = tx_oneshot.send(format!("unknown asset!"));
+        }
+
+        let order_id = Uuid::new_v4();
+        let reply = place(self, order_id);
+
+        if let Some(client_order_id) = client_order_id
+            && self.order_records.get(&order_id).is_some()
+        {
+            self.order_records.set_client_order_id(order_id, client_order_id.clone());
+            self.client_orders.remember(user_id, client_order_id, order_id, fingerprint, reply.clone(), now);
+        }
+        reply
+    }
+
+    fn submit_limit_order(&mut self, user_id: Uuid, side: Side, price: u64, quantity: u64, client_order_id: Option<String>) -> Result<String, String> {
+        let fingerprint = format!("limit:{:?}:{}:{}", side, price, quantity);
+        self.submit_order(user_id, client_order_id, fingerprint, |engine, order_id| {
+            engine.create_order(order_id, user_id, side, price, quantity)
+        })
+    }
+
+    fn resolve_order_key(&self, user_id: Uuid, order: &OrderKey) -> Option<Uuid> {
+        match order {
+            OrderKey::OrderId(order_id) => Some(*order_id),
+            OrderKey::ClientOrderId(client_order_id) => self.client_orders.order_id(user_id, client_order_id),
+        }
+    }
+
+    fn create_order(&mut self, order_id: Uuid, user_id: Uuid, side: Side, price: u64, quantity: u64) -> Result<String, String> {
+        let record = OrderRecord::new(order_id, user_id, side, Some(price), quantity, now_millis());
+
+        if !self.balances.users.contains_key(&user_id) {
+            return Err("user not found".into());
+        }
+        if quantity == 0 {
+            self.order_records.reject(record, "invalid quantity");
+            return Err("quantity must be positive".into());
+        }
+        if price == 0 {
+            self.order_records.reject(record, "invalid price");
+            return Err("price must be positive".into());
+        }
+
+        if let Err(e) = self.market.state.check_new_order() {
+            self.order_records.reject(record, &e);
+            return Err(e);
+        }
+        if self.market.state == MarketState::PostOnly {
+            let crosses = match side {
+                Side::Bid => self.orderbook.best_ask().is_some_and(|ask| ask <= price),
+                Side::Ask => self.orderbook.best_bid().is_some_and(|bid| bid >= price),
+            };
+            if crosses {
+                self.order_records.reject(record, "post-only");
+                return Err("market is post-only and the order would take liquidity".into());
+            }
+        }
+        if self.market.state != MarketState::Auction && !self.market.volatility.in_band(self.last_trade_price, price) {
+            self.order_records.reject(record, "price outside band");
+            return Err("price is outside the allowed price band".into());
+        }
+        if let Err(reject) = self.check_risk(user_id, side, price, quantity, now_millis()) {
+            self.order_records.reject(record, reject.code());
+            return Err(reject.message());
+        }
+
+        let user = self.balances.users.get_mut(&user_id).unwrap();
+
+        match side {
+            Side::Bid => {
+                let cost_micro = match calculate_cost_usdc_micro(price, quantity) {
+                    Some(c) => c,
+                    None => {
+                        self.order_records.reject(record, "cost overflow");
+                        return Err("cost overflow - invalid order".into());
                     }
-                } else {
-                    let _ = tx_oneshot.send(format!("user id: {} not found", user_id));
+                };
+
+                let buyer_usdc = user.assets.get_mut("USDC").unwrap();
+                if buyer_usdc.available < cost_micro {
+                    self.order_records.reject(record, "insufficient USDC funds");
+                    return Err("insufficient USDC funds".into());
                 }
+
+                buyer_usdc.available -= cost_micro;
+                buyer_usdc.locked += cost_micro;
             }
-            EngineCommand::GetBalances {user_id, tx_oneshot} => {
-                println!("fetching user balances");
-                
-                if let Some(user_balance) = balances.users.get(&user_id) {
-                    let _ = tx_oneshot.send(Some(user_balance.clone()));
-                } else {
-                    let _ = tx_oneshot.send(None);
+            Side::Ask => {
+                let seller_btc = user.assets.get_mut("BTC").unwrap();
+                if seller_btc.available < quantity {
+                    self.order_records.reject(record, "insufficient BTC funds");
+                    return Err("insufficient BTC funds".into());
                 }
+
+                seller_btc.available -= quantity;
+                seller_btc.locked += quantity;
             }
-            EngineCommand::CreateOrder { user_id, side, price, quantity, tx_oneshot } => {
-                let user = if let Some(u) = balances.users.get_mut(&user_id) {
-                    u
-                } else {
-                    let _ = tx_oneshot.send("user not found".into());
-                    continue;
-                };
-            
-                match side {
-                    Side::Bid => {
-                        let cost_micro = match calculate_cost_usdc_micro(price, quantity) {
-                            Some(c) => c,
-                            None => {
-                                let _ = tx_oneshot.send("cost overflow - invalid order".into());
-                                continue;
-                            }
-                        };
-            
-                        let (buyer_btc, buyer_usdc) = {
-                            let assets = &mut user.assets;
-                            let btc_ptr = assets.get_mut("BTC").unwrap() as *mut AssetBalance;
-                            let usdc_ptr = assets.get_mut("USDC").unwrap() as *mut AssetBalance;
-                            unsafe { (&mut *btc_ptr, &mut *usdc_ptr) }
-                        };
-            
-                        if buyer_usdc.available < cost_micro {
-                            let _ = tx_oneshot.send("insufficient USDC funds".into());
-                            continue;
-                        }
-            
-                        buyer_usdc.available -= cost_micro;
-                        buyer_usdc.locked += cost_micro;
-            
-                        let mut remaining = quantity;
-            
-                        let ask_prices: Vec<u64> = orderbook
-                            .asks
-                            .keys()
-                            .cloned()
-                            .filter(|p| *p <= price)
-                            .collect();
-            
-                        for ask_price in ask_prices {
-                            let queue = orderbook.asks.get_mut(&ask_price).unwrap();
-            
-                            while let Some(mut ask_order) = queue.pop_front() {
-                                let seller = balances.users.get_mut(&ask_order.user_id).unwrap();
-            
-                                let (seller_btc, seller_usdc) = {
-                                    let assets = &mut seller.assets;
-                                    let btc_ptr = assets.get_mut("BTC").unwrap() as *mut AssetBalance;
-                                    let usdc_ptr = assets.get_mut("USDC").unwrap() as *mut AssetBalance;
-                                    unsafe { (&mut *btc_ptr, &mut *usdc_ptr) }
-                                };
-            
-                                let trade_qty = remaining.min(ask_order.quantity);
-                                let trade_cost = calculate_cost_usdc_micro(ask_price, trade_qty).unwrap();
-            
-                                buyer_btc.available += trade_qty;
-                                buyer_usdc.locked -= trade_cost;
-            
-                                seller_btc.locked -= trade_qty;
-                                seller_usdc.available += trade_cost;
-            
-                                ask_order.quantity -= trade_qty;
-                                remaining -= trade_qty;
-            
-                                if ask_order.quantity > 0 {
-                                    queue.push_front(ask_order);
-                                    break;
-                                }
-            
-                                if remaining == 0 {
-                                    break;
-                                }
-                            }
-            
-                            if queue.is_empty() {
-                                orderbook.asks.remove(&ask_price);
-                            }
-            
-                            if remaining == 0 {
-                                break;
-                            }
-                        }
-            
-                        if remaining > 0 {
-                            let order_id = Uuid::new_v4();
-                            order_index.insert(order_id, (Side::Bid, price));
-            
-                            let resting_order = Order {
-                                id: order_id,
-                                user_id,
-                                side: Side::Bid,
-                                price,
-                                quantity: remaining,
-                            };
-            
-                            orderbook.add_order(resting_order);
-                            let _ = tx_oneshot.send(order_id.to_string());
-                        } else {
-                            let _ = tx_oneshot.send("filled".into());
-                        }
-                    }
-            
-                    Side::Ask => {
-                        let (seller_btc, seller_usdc) = {
-                            let assets = &mut user.assets;
-                            let btc_ptr = assets.get_mut("BTC").unwrap() as *mut AssetBalance;
-                            let usdc_ptr = assets.get_mut("USDC").unwrap() as *mut AssetBalance;
-                            unsafe { (&mut *btc_ptr, &mut *usdc_ptr) }
-                        };
-            
-                        if seller_btc.available < quantity {
-                            let _ = tx_oneshot.send("insufficient BTC funds".into());
-                            continue;
-                        }
-            
-                        seller_btc.available -= quantity;
-                        seller_btc.locked += quantity;
-            
-                        let mut remaining = quantity;
-            
-                        let bid_prices: Vec<u64> = orderbook
-                            .bids
-                            .keys()
-                            .cloned()
-                            .filter(|p| *p >= price)
-                            .collect();
-            
-                        for bid_price in bid_prices {
-                            let queue = orderbook.bids.get_mut(&bid_price).unwrap();
-            
-                            while let Some(mut bid_order) = queue.pop_front() {
-                                let buyer = balances.users.get_mut(&bid_order.user_id).unwrap();
-            
-                                let (buyer_btc, buyer_usdc) = {
-                                    let assets = &mut buyer.assets;
-                                    let btc_ptr = assets.get_mut("BTC").unwrap() as *mut AssetBalance;
-                                    let usdc_ptr = assets.get_mut("USDC").unwrap() as *mut AssetBalance;
-                                    unsafe { (&mut *btc_ptr, &mut *usdc_ptr) }
-                                };
-            
-                                let trade_qty = remaining.min(bid_order.quantity);
-                                let trade_cost = calculate_cost_usdc_micro(bid_price, trade_qty).unwrap();
-            
-                                seller_btc.locked -= trade_qty;
-                                seller_usdc.available += trade_cost;
-            
-                                buyer_btc.available += trade_qty;
-                                buyer_usdc.locked -= trade_cost;
-            
-                                bid_order.quantity -= trade_qty;
-                                remaining -= trade_qty;
-            
-                                if bid_order.quantity > 0 {
-                                    queue.push_front(bid_order);
-                                    break;
-                                }
-            
-                                if remaining == 0 {
-                                    break;
-                                }
-                            }
-            
-                            if queue.is_empty() {
-                                orderbook.bids.remove(&bid_price);
-                            }
-            
-                            if remaining == 0 {
-                                break;
-                            }
-                        }
-            
-                        if remaining > 0 {
-                            let order_id = Uuid::new_v4();
-                            order_index.insert(order_id, (Side::Ask, price));
-            
-                            let resting_order = Order {
-                                id: order_id,
-                                user_id,
-                                side: Side::Ask,
-                                price,
-                                quantity: remaining,
-                            };
-            
-                            orderbook.add_order(resting_order);
-                            let _ = tx_oneshot.send(order_id.to_string());
-                        } else {
-                            let _ = tx_oneshot.send("filled".into());
-                        }
-                    }
+        }
+
+        self.order_records.insert(record);
+        let (remaining, trades) = if self.market.state == MarketState::Auction {
+            (quantity, Vec::new())
+        } else {
+            self.match_incoming(order_id, user_id, side, Some(price), quantity)
+        };
+
+        let crosses = match side {
+            Side::Bid => self.orderbook.best_ask().is_some_and(|ask| ask <= price),
+            Side::Ask => self.orderbook.best_bid().is_some_and(|bid| bid >= price),
+        };
+        let may_cross = matches!(self.market.state, MarketState::Auction | MarketState::Halted);
+
+        if remaining > 0 && crosses && !may_cross {
+            // matching stopped at the price band; resting the rest would cross the book
+            let assets = &mut self.balances.users.get_mut(&user_id).unwrap().assets;
+            match side {
+                Side::Bid => {
+                    let cost_micro = calculate_cost_usdc_micro(price, remaining).unwrap();
+                    let usdc = assets.get_mut("USDC").unwrap();
+                    usdc.locked -= cost_micro;
+                    usdc.available += cost_micro;
                 }
-            }                        
-            EngineCommand::CancelOrder {user_id, order_id, tx_oneshot} => {
-                let (side, price) = match order_index.remove(&order_id) {
-                    Some(v) => v,
-                    None => {
-                        let _ = tx_oneshot.send("order not found".into());
-                        continue;
-                    }
-                };
+                Side::Ask => {
+                    let btc = assets.get_mut("BTC").unwrap();
+                    btc.locked -= remaining;
+                    btc.available += remaining;
+                }
+            }
+            self.order_records.finish(order_id, OrderStatus::Expired, now_millis());
+        } else if remaining > 0 {
+            self.rest_order(Order {
+                id: order_id,
+                user_id,
+                side,
+                price,
+                quantity: remaining,
+                peg: None,
+            });
+        }
 
-                let side = match side {
-                    Side::Ask => &mut orderbook.asks,
-                    Side::Bid => &mut orderbook.bids,
-                };
+        self.on_trades(trades);
+        self.reprice_pegged_orders();
+        Ok(order_id.to_string())
+    }
 
-                let queue = match side.get_mut(&price) {
-                    Some(q) => q,
-                    None => {
-                        let _ = tx_oneshot.send("order missing from orderbook".into());
-                        continue;
-                    }
-                };
+    /// Puts an order at the back of its price level and publishes it to the L3 feed.
+    fn rest_order(&mut self, order: Order) {
+        self.order_index.insert(order.id, (order.side, order.price));
+        self.risk.track_open(order.user_id, order.side, order.price, 0, order.quantity);
+        self.feed.publish_l3(L3Event::Add {
+            order_id: order.id,
+            side: order.side,
+            price: order.price,
+            quantity: order.quantity,
+        }, now_millis());
+        self.orderbook.add_order(order);
+    }
 
-                let pos = queue.iter().position(|o| o.id == order_id);
+    /// Cancels a resting order or trailing stop, returning the confirmation or why it failed.
+    fn cancel_order(&mut self, user_id: Uuid, order: &OrderKey) -> Result<String, String> {
+        self.market.state.check_cancel()?;
+        let order_id = match self.resolve_order_key(user_id, order) {
+            Some(id) => id,
+            None => return Err("order not found".into()),
+        };
 
-                let removed_order = match pos {
-                    Some(p) => queue.remove(p).unwrap(),
-                    None => {
-                        let _ = tx_oneshot.send("order not found in price level".into());
-                        continue;
-                    }
-                };
+        if let Some(pos) = self.trailing_stops.iter().position(|s| s.id == order_id && s.user_id == user_id) {
+            self.trailing_stops.remove(pos);
+            return Ok(format!("trailing stop:{} has been cancelled!", order_id));
+        }
 
-                if queue.is_empty() {
-                    side.remove(&price);
-                }
+        let (side, price) = match self.order_index.get(&order_id) {
+            Some(&v) => v,
+            None => return Err("order not found".into()),
+        };
 
-                let user = balances.users.get_mut(&user_id).unwrap();
+        let side = match side {
+            Side::Ask => &mut self.orderbook.asks,
+            Side::Bid => &mut self.orderbook.bids,
+        };
 
-                match removed_order.side {
-                    Side::Bid => {
-                        let cost_micro = calculate_cost_usdc_micro(price, removed_order.quantity).unwrap();
-                        let usdc = user.assets.get_mut("USDC").unwrap();
-                        usdc.locked -= cost_micro;
-                        usdc.available += cost_micro;
-                    }
-                    Side::Ask => {
-                        let btc = user.assets.get_mut("BTC").unwrap();
-                        btc.locked -= removed_order.quantity;
-                        btc.available += removed_order.quantity;
-                    }
-                }
+        let queue = match side.get_mut(&price) {
+            Some(q) => q,
+            None => return Err("order missing from orderbook".into()),
+        };
 
-                let _ = tx_oneshot.send(format!("order:{} has been cancelled!", order_id));
+        let pos = match queue.iter().position(|o| o.id == order_id) {
+            Some(p) => p,
+            None => return Err("order not found in price level".into()),
+        };
+        // order ids are public on the L3 feed, so anyone can name someone else's order
+        if queue[pos].user_id != user_id {
+            return Err("order not found".into());
+        }
+        let removed_order = queue.remove(pos).unwrap();
+
+        if queue.is_empty() {
+            side.remove(&price);
+        }
+        self.order_index.remove(&order_id);
+        self.risk.track_open(removed_order.user_id, removed_order.side, price, removed_order.quantity, 0);
+        self.feed.publish_l3(L3Event::Delete {
+            order_id,
+            side: removed_order.side,
+            price,
+        }, now_millis());
+
+        let user = self.balances.users.get_mut(&removed_order.user_id).unwrap();
+
+        match removed_order.side {
+            Side::Bid => {
+                let cost_micro = calculate_cost_usdc_micro(price, removed_order.quantity).unwrap();
+                let usdc = user.assets.get_mut("USDC").unwrap();
+                usdc.locked -= cost_micro;
+                usdc.available += cost_micro;
+            }
+            Side::Ask => {
+                let btc = user.assets.get_mut("BTC").unwrap();
+                btc.locked -= removed_order.quantity;
+                btc.available += removed_order.quantity;
             }
-            EngineCommand::GetUserOrders { user_id, tx_oneshot } => {
-                let mut user_orders = Vec::new();
-                for (_price, queue) in &orderbook.bids {
-                    for order in queue {
-                        if order.user_id == user_id {
-                            user_orders.push(order.clone());
-                        }
+        }
+
+        self.pegged_orders.remove(&order_id);
+        self.order_records.finish(order_id, OrderStatus::Cancelled, now_millis());
+        self.reprice_pegged_orders();
+
+        Ok(format!("order:{} has been cancelled!", order_id))
+    }
+
+    fn create_trailing_stop(&mut self, user_id: Uuid, side: Side, quantity: u64, trail: TrailAmount) -> Result<String, String> {
+        if !self.balances.users.contains_key(&user_id) {
+            return Err("user not found".into());
+        }
+        if quantity == 0 {
+            return Err("quantity must be positive".into());
+        }
+        self.market.state.check_new_order()?;
+        let last_price = match self.last_trade_price {
+            Some(p) => p,
+            None => return Err("no last trade price to trail".into()),
+        };
+        if let Err(reject) = self.check_risk(user_id, side, last_price, quantity, now_millis()) {
+            return Err(reject.message());
+        }
+
+        let stop = TrailingStop::new(user_id, side, quantity, trail, last_price);
+        let stop_id = stop.id;
+        self.trailing_stops.push(stop);
+        Ok(stop_id.to_string())
+    }
+
+    /// Feeds printed trades to the trailing stops. Triggered stops become market orders,
+    /// whose own trades are fed back in until nothing else fires. Stops only fire while the
+    /// market is continuous; trades printed in any other state just move the last price.
+    fn on_trades(&mut self, trades: Vec<Trade>) {
+        let mut pending = trades;
+
+        while !pending.is_empty() {
+            let mut triggered = Vec::new();
+
+            for trade in &pending {
+                let now = now_millis();
+                let trade_id = self.feed.publish_trade(trade, now);
+                self.user_feed.record_fills(trade, trade_id);
+                self.candles.record(trade.price, trade.quantity, now);
+                self.day_stats.record(trade.price, trade.quantity, now);
+                if let Some(journal) = &mut self.journal {
+                    let entry = JournaledTrade {
+                        trade_id,
+                        timestamp: now,
+                        price: trade.price,
+                        quantity: trade.quantity,
+                        taker_side: trade.taker_side,
+                    };
+                    if let Err(e) = journal.append(&entry) {
+                        println!("failed to journal trade {trade_id}: {e}");
                     }
                 }
-                for (_price, queue) in &orderbook.asks {
-                    for order in queue {
-                        if order.user_id == user_id {
-                            user_orders.push(order.clone());
-                        }
+                self.last_trade_price = Some(trade.price);
+                if self.market.state != MarketState::Continuous {
+                    continue;
+                }
+
+                let mut i = 0;
+                while i < self.trailing_stops.len() {
+                    if self.trailing_stops[i].on_trade_price(trade.price) {
+                        triggered.push(self.trailing_stops.remove(i));
+                    } else {
+                        i += 1;
                     }
                 }
-                let _ = tx_oneshot.send(user_orders);
             }
-            EngineCommand::GetDepth { tx_oneshot } => {
-                let mut bids_out = Vec::new();
-                let mut asks_out = Vec::new();
 
-                for (price, queue) in orderbook.bids.iter().rev().take(10) {
-                    let total_qty_bids: u64 = queue.iter().map(|o| o.quantity).sum();
+            pending.clear();
+            for stop in triggered {
+                println!("trailing stop {} triggered at {}", stop.id, stop.trigger_price);
+                pending.extend(self.execute_market_order(stop.id, stop.user_id, stop.side, stop.quantity));
+            }
+        }
+    }
+
+    /// Moves the market to `state`. Going from a crossed book (left by an auction) to any
+    /// state other than `Auction` or `Halted` uncrosses it first, so the book is never
+    /// crossed while orders can match or rest freely.
+    fn set_market_state(&mut self, state: MarketState) -> String {
+        let previous = self.market.state;
+        if previous == state {
+            return format!("{} is already {:?}", self.market.symbol, state);
+        }
+
+        let mut reply = format!("{} moved from {:?} to {:?}", self.market.symbol, previous, state);
+        println!("{reply}");
 
-                    bids_out.push(DepthLevel {
-                        price: math::micro_to_price_string(*price),
-                        quantity: math::sats_to_btc_string(total_qty_bids),
-                    })
-                }
+        let trades = if state != MarketState::Auction && state != MarketState::Halted && self.orderbook.is_crossed() {
+            self.uncross_book()
+        } else {
+            Vec::new()
+        };
+        if let Some(price) = trades.first().map(|t| t.price) {
+            self.recent_prices = PriceWindow::new();
+            self.record_trade_price(price, now_millis());
+            let volume: u64 = trades.iter().map(|t| t.quantity).sum();
+            reply = format!("{}, uncrossed {} at {}", reply, math::sats_to_btc_string(volume), math::micro_to_price_string(price));
+        }
+
+        self.market.state = state;
+        self.on_trades(trades);
+        self.reprice_pegged_orders();
+        reply
+    }
+}
+
+pub fn run(
+    rx: CommandReceiver,
+    feed_tx: broadcast::Sender<FeedEvent>,
+    user_updates: UserUpdates,
+    drop_copy_tx: broadcast::Sender<DropCopyRecord>,
+    journal_path: Option<PathBuf>,
+) {
+    println!("engine thread has started...");
+
+    let mut engine = Engine::new(MarketFeed::new(feed_tx), UserFeed::new(user_updates), DropCopy::new(drop_copy_tx));
+    if let Some(path) = journal_path {
+        engine.open_journal(&path).expect("failed to open trade journal");
+    }
+
+    while let Some(cmd) = rx.recv() {
+        for record in engine.order_records.prune(now_millis()) {
+            if let Some(client_order_id) = &record.client_order_id {
+                engine.client_orders.forget(record.user_id, client_order_id, record.id);
+            }
+        }
+
+        handle_command(&mut engine, cmd);
+        engine.flush_feed();
+        engine.flush_user_feed();
+    }
+}
 
-                for (price, queue) in orderbook.asks.iter().take(10) {
-                    let total_qty_asks: u64 = queue.iter().map(|o| o.quantity).sum();
+fn handle_command(engine: &mut Engine, cmd: EngineCommand) {
+    match cmd {
+        EngineCommand::InitializeUser { tx_oneshot} => {
+            let user_id = Uuid::new_v4();
+            println!("initializing balances for {user_id}");
+            engine.balances.users.insert(
+                user_id,
+                UserBalance {
+                    assets: HashMap::from([
+                        ("BTC".to_string(), AssetBalance { available: 0, locked: 0 } ),
+                        ("USDC".to_string(), AssetBalance { available: 0, locked: 0 } ),
+                    ])
+            });
+
+            let _ = tx_oneshot.send(user_id.to_string());
+        }
+        EngineCommand::Deposit {user_id, asset, amount, tx_oneshot} => {
+            if let Some(user) = engine.balances.users.get_mut(&user_id) {
+                if let Some(entry) = user.assets.get_mut(&asset) {
+                    entry.available = entry.available
+                        .checked_add(amount)
+                        .expect("overflow in deposit");
+                    engine.user_feed.touch(user_id);
 
-                    asks_out.push(DepthLevel {
-                        price: math::micro_to_price_string(*price),
-                        quantity: math::sats_to_btc_string(total_qty_asks),
-                    });
+                    let _ = tx_oneshot.send(format!("deposited {} {} for user {}", amount, asset, user_id));
+                } else {
+                    let _ = tx_oneshot.send("unknown asset!".to_string());
                 }
-                let _ = tx_oneshot.send(DepthResponse {
-                    bids: bids_out,
-                    asks: asks_out
-                });
+            } else {
+                let _ = tx_oneshot.send(format!("user id: {} not found", user_id));
             }
         }
+        EngineCommand::GetBalances {user_id, tx_oneshot} => {
+            println!("fetching user balances");
+            
+            if let Some(user_balance) = engine.balances.users.get(&user_id) {
+                let _ = tx_oneshot.send(Some(user_balance.clone()));
+            } else {
+                let _ = tx_oneshot.send(None);
+            }
+        }
+        EngineCommand::CreateOrder { user_id, side, price, quantity, client_order_id, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.submit_limit_order(user_id, side, price, quantity, client_order_id));
+        }
+        EngineCommand::CreatePeggedOrder { user_id, side, quantity, peg, client_order_id, tx_oneshot } => {
+            let fingerprint = format!("pegged:{:?}:{}:{:?}", side, quantity, peg);
+            let reply = engine.submit_order(user_id, client_order_id, fingerprint, |engine, order_id| {
+                engine.create_pegged_order(order_id, user_id, side, quantity, peg)
+            });
+            let _ = tx_oneshot.send(reply);
+        }
+        EngineCommand::CreateTrailingStop { user_id, side, quantity, trail, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.create_trailing_stop(user_id, side, quantity, trail));
+        }
+        EngineCommand::CancelOrder {user_id, order, tx_oneshot} => {
+            let _ = tx_oneshot.send(engine.cancel_order(user_id, &order));
+        }
+        EngineCommand::ReplaceOrder { user_id, order, side, price, quantity, client_order_id, tx_oneshot } => {
+            let reply = engine.cancel_order(user_id, &order)
+                .map(|_| engine.submit_limit_order(user_id, side, price, quantity, client_order_id));
+            let _ = tx_oneshot.send(reply);
+        }
+        EngineCommand::BatchCreateOrders { user_id, orders, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.batch_create_orders(user_id, orders));
+        }
+        EngineCommand::BatchCancelOrders { user_id, orders, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.batch_cancel_orders(user_id, orders));
+        }
+        EngineCommand::GetUserOrders { user_id, tx_oneshot } => {
+            let mut user_orders = Vec::new();
+            for queue in engine.orderbook.bids.values() {
+                for order in queue {
+                    if order.user_id == user_id {
+                        user_orders.push(order.clone());
+                    }
+                }
+            }
+            for queue in engine.orderbook.asks.values() {
+                for order in queue {
+                    if order.user_id == user_id {
+                        user_orders.push(order.clone());
+                    }
+                }
+            }
+            let _ = tx_oneshot.send(user_orders);
+        }
+        EngineCommand::GetOrder { user_id, order, tx_oneshot } => {
+            let record = engine.resolve_order_key(user_id, &order)
+                .and_then(|order_id| engine.order_records.get(&order_id))
+                .filter(|r| r.user_id == user_id)
+                .cloned();
+            let _ = tx_oneshot.send(record);
+        }
+        EngineCommand::GetUserTrailingStops { user_id, tx_oneshot } => {
+            let stops = engine.trailing_stops
+                .iter()
+                .filter(|s| s.user_id == user_id)
+                .cloned()
+                .collect();
+            let _ = tx_oneshot.send(stops);
+        }
+        EngineCommand::GetDepth { levels, bucket, tx_oneshot } => {
+            let to_depth = |side| {
+                engine.orderbook
+                    .levels(side, levels, bucket)
+                    .into_iter()
+                    .map(|(price, quantity, orders)| DepthLevel {
+                        price: math::micro_to_price_string(price),
+                        quantity: math::sats_to_btc_string(quantity),
+                        orders,
+                    })
+                    .collect()
+            };
+
+            let _ = tx_oneshot.send(DepthResponse {
+                sequence: engine.feed.depth_sequence(),
+                checksum: engine.orderbook.checksum(),
+                bids: to_depth(Side::Bid),
+                asks: to_depth(Side::Ask),
+            });
+        }
+        EngineCommand::GetMatchingAlgorithm { tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.market.matching);
+        }
+        EngineCommand::SetMatchingAlgorithm { matching, tx_oneshot } => {
+            println!("{} matching algorithm set to {:?}", engine.market.symbol, matching);
+            engine.market.set_matching(matching);
+            let _ = tx_oneshot.send(format!("{} now matches with {:?}", engine.market.symbol, matching));
+        }
+        EngineCommand::SetMarketState { state, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.set_market_state(state));
+        }
+        EngineCommand::GetVolatilityControls { tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.market.volatility);
+        }
+        EngineCommand::SetVolatilityControls { controls, tx_oneshot } => {
+            println!("{} volatility controls set to {:?}", engine.market.symbol, controls);
+            engine.market.volatility = controls;
+            let _ = tx_oneshot.send(format!("{} volatility controls updated", engine.market.symbol));
+        }
+        EngineCommand::GetRiskLimits { user_id, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.risk.limits(&user_id));
+        }
+        EngineCommand::SetRiskLimits { user_id, limits, tx_oneshot } => {
+            if !engine.balances.users.contains_key(&user_id) {
+                let _ = tx_oneshot.send(format!("user id: {} not found", user_id));
+                return;
+            }
+            println!("risk limits for {} set to {:?}", user_id, limits);
+            engine.risk.set_limits(user_id, limits);
+            let _ = tx_oneshot.send(format!("risk limits updated for user {}", user_id));
+        }
+        EngineCommand::StartAuction { tx_oneshot } => {
+            if engine.market.state == MarketState::Auction {
+                let _ = tx_oneshot.send("auction already running".into());
+                return;
+            }
+            let _ = tx_oneshot.send(engine.set_market_state(MarketState::Auction));
+        }
+        EngineCommand::EndAuction { tx_oneshot } => {
+            if engine.market.state != MarketState::Auction {
+                let _ = tx_oneshot.send("no auction running".into());
+                return;
+            }
+            let _ = tx_oneshot.send(engine.set_market_state(MarketState::Continuous));
+        }
+        EngineCommand::GetAuctionInfo { tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.auction_info());
+        }
+        EngineCommand::GetL3Snapshot { tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.l3_snapshot());
+        }
+        EngineCommand::GetL3Updates { after, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.feed.l3_since(after));
+        }
+        EngineCommand::GetTicker { tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.ticker(now_millis()));
+        }
+        EngineCommand::GetBbo { tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.bbo());
+        }
+        EngineCommand::GetCandles { interval, start, end, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.candles.range(interval, start, end));
+        }
+        EngineCommand::GetUserSnapshot { user_id, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.user_snapshot(user_id));
+        }
+        EngineCommand::GetDropCopy { after, tx_oneshot } => {
+            let _ = tx_oneshot.send(engine.drop_copy.since(after));
+        }
     }
 }
 
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
 fn calculate_cost_usdc_micro(price_micro: u64, qty_sats: u64) -> Option<u64> {
     let p = price_micro as u128;
     let q = qty_sats as u128;
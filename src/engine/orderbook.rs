@@ -2,7 +2,13 @@ use std::collections::{BTreeMap, VecDeque};
 use uuid::Uuid;
 use serde::{Serialize, Deserialize};
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+use super::peg::{Peg, PegReferences};
+use crate::math;
+
+/// Price levels per side covered by `OrderBook::checksum`.
+pub const CHECKSUM_LEVELS: usize = 25;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Side {
     Ask, 
     Bid
@@ -14,7 +20,8 @@ pub struct Order {
     pub user_id: Uuid,
     pub side: Side,
     pub price: u64,
-    pub quantity: u64
+    pub quantity: u64,
+    pub peg: Option<Peg>,
 }
 
 #[derive(Debug, Clone)]
@@ -43,4 +50,122 @@ impl OrderBook {
             .or_insert_with(VecDeque::new)
             .push_back(order);
     }
+
+    pub fn best_bid(&self) -> Option<u64> {
+        self.bids.keys().next_back().copied()
+    }
+
+    pub fn best_ask(&self) -> Option<u64> {
+        self.asks.keys().next().copied()
+    }
+
+    pub fn is_crossed(&self) -> bool {
+        match (self.best_bid(), self.best_ask()) {
+            (Some(bid), Some(ask)) => bid >= ask,
+            _ => false,
+        }
+    }
+
+    /// Price levels from the top of `side`, as `(price, quantity, order count)`.
+    ///
+    /// With a `bucket` (in micro USDC) levels are grouped into buckets of that width: bids
+    /// are rounded down to the bucket below and asks up to the bucket above, so an aggregated
+    /// level never looks better than the orders in it. `max_levels: None` returns the full side.
+    pub fn levels(&self, side: Side, max_levels: Option<usize>, bucket: Option<u64>) -> Vec<(u64, u64, usize)> {
+        let book_side: Box<dyn Iterator<Item = (&u64, &VecDeque<Order>)>> = match side {
+            Side::Bid => Box::new(self.bids.iter().rev()),
+            Side::Ask => Box::new(self.asks.iter()),
+        };
+        let max_levels = max_levels.unwrap_or(usize::MAX);
+
+        let mut out: Vec<(u64, u64, usize)> = Vec::new();
+        for (price, queue) in book_side {
+            let key = match (bucket, side) {
+                (Some(b), Side::Bid) if b > 1 => price / b * b,
+                (Some(b), Side::Ask) if b > 1 => price.div_ceil(b) * b,
+                _ => *price,
+            };
+            let quantity: u64 = queue.iter().map(|o| o.quantity).sum();
+
+            match out.last_mut() {
+                Some(level) if level.0 == key => {
+                    level.1 += quantity;
+                    level.2 += queue.len();
+                }
+                _ => {
+                    if out.len() == max_levels {
+                        break;
+                    }
+                    out.push((key, quantity, queue.len()));
+                }
+            }
+        }
+        out
+    }
+
+    /// CRC32 of the top `CHECKSUM_LEVELS` unaggregated levels on each side, so a client
+    /// keeping its own copy of the book can check it against the feed.
+    ///
+    /// Levels are written as `price:quantity` using the same decimal strings as the depth
+    /// feed, best first and joined with `,`; the bid side comes first and the sides are
+    /// separated by `|`. An empty book is just `|`. For example `100.5:1.2,100:0.3|101:2`.
+    pub fn checksum(&self) -> u32 {
+        let side = |side| {
+            self.levels(side, Some(CHECKSUM_LEVELS), None)
+                .into_iter()
+                .map(|(price, quantity, _)| format!("{}:{}", math::micro_to_price_string(price), math::sats_to_btc_string(quantity)))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        crc32fast::hash(format!("{}|{}", side(Side::Bid), side(Side::Ask)).as_bytes())
+    }
+
+    pub fn peg_references(&self) -> PegReferences {
+        let has_unpegged = |queue: &VecDeque<Order>| queue.iter().any(|o| o.peg.is_none());
+
+        PegReferences {
+            best_bid: self.bids.iter().rev().find(|(_, q)| has_unpegged(q)).map(|(p, _)| *p),
+            best_ask: self.asks.iter().find(|(_, q)| has_unpegged(q)).map(|(p, _)| *p),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(side: Side, price: u64, quantity: u64) -> Order {
+        Order { id: Uuid::new_v4(), user_id: Uuid::new_v4(), side, price, quantity, peg: None }
+    }
+
+    // the expected values are the CRC32 of the strings in the comments, so a change to the
+    // format breaks clients and has to be deliberate
+
+    #[test]
+    fn checksum_of_an_empty_book() {
+        // "|"
+        assert_eq!(OrderBook::new().checksum(), 2_343_686_810);
+    }
+
+    #[test]
+    fn checksum_covers_each_level_in_feed_format() {
+        let mut book = OrderBook::new();
+        book.add_order(order(Side::Bid, 100_000_000, 10_000_000));
+        book.add_order(order(Side::Bid, 100_500_000, 120_000_000));
+        book.add_order(order(Side::Bid, 100_000_000, 20_000_000));
+        book.add_order(order(Side::Ask, 101_000_000, 200_000_000));
+        // "100.5:1.2,100:0.3|101:2"
+        assert_eq!(book.checksum(), 1_847_047_539);
+    }
+
+    #[test]
+    fn checksum_stops_at_the_checksum_depth() {
+        let mut book = OrderBook::new();
+        for i in 0..=CHECKSUM_LEVELS as u64 {
+            book.add_order(order(Side::Bid, (200 - i) * 1_000_000, 10_000_000));
+            book.add_order(order(Side::Ask, (201 + i) * 1_000_000, 10_000_000));
+        }
+        // "200:0.1,199:0.1,...,176:0.1|201:0.1,202:0.1,...,225:0.1"
+        assert_eq!(book.checksum(), 1_185_627_007);
+    }
 }
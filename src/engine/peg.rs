@@ -0,0 +1,372 @@
+use std::collections::VecDeque;
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::{Engine, calculate_cost_usdc_micro, now_millis};
+use super::feed::L3Event;
+use super::market::MarketState;
+use super::orderbook::{Order, Side};
+use super::records::OrderRecord;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PegReference {
+    /// Same side of the book: bids follow the best bid, asks the best ask.
+    Primary,
+    /// Opposite side of the book: bids follow the best ask, asks the best bid.
+    Market,
+    /// Midpoint between best bid and best ask, rounded down.
+    Mid,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Peg {
+    pub reference: PegReference,
+    /// Signed distance from the reference price, in micro USDC.
+    pub offset: i64,
+    /// Worst price the order may be repriced to: a ceiling for bids, a floor for asks.
+    pub limit: Option<u64>,
+}
+
+/// Top of book computed from non-pegged orders only, so pegs never chase each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PegReferences {
+    pub best_bid: Option<u64>,
+    pub best_ask: Option<u64>,
+}
+
+impl Peg {
+    /// Price the order should rest at for the given references, before crossing checks.
+    pub fn target_price(&self, side: Side, refs: &PegReferences) -> Option<u64> {
+        let reference = match (self.reference, side) {
+            (PegReference::Primary, Side::Bid) | (PegReference::Market, Side::Ask) => refs.best_bid?,
+            (PegReference::Primary, Side::Ask) | (PegReference::Market, Side::Bid) => refs.best_ask?,
+            (PegReference::Mid, _) => ((refs.best_bid? as u128 + refs.best_ask? as u128) / 2) as u64,
+        };
+
+        let price = reference.checked_add_signed(self.offset)?;
+        let price = match (side, self.limit) {
+            (Side::Bid, Some(limit)) => price.min(limit),
+            (Side::Ask, Some(limit)) => price.max(limit),
+            (_, None) => price,
+        };
+
+        if price == 0 { None } else { Some(price) }
+    }
+}
+
+impl Engine {
+    pub(super) fn create_pegged_order(&mut self, order_id: Uuid, user_id: Uuid, side: Side, quantity: u64, peg: Peg) -> Result<String, String> {
+        if !self.balances.users.contains_key(&user_id) {
+            return Err("user not found".into());
+        }
+        if quantity == 0 {
+            return Err("quantity must be positive".into());
+        }
+        self.market.state.check_new_order()?;
+        if self.market.state == MarketState::Auction {
+            return Err("pegged orders are not accepted during an auction".into());
+        }
+
+        let price = match self.passive_peg_price(side, &peg, &self.orderbook.peg_references()) {
+            Some(p) => p,
+            None => return Err("no reference price to peg to".into()),
+        };
+        if let Err(reject) = self.check_risk(user_id, side, price, quantity, now_millis()) {
+            return Err(reject.message());
+        }
+
+        let assets = &mut self.balances.users.get_mut(&user_id).unwrap().assets;
+        match side {
+            Side::Bid => {
+                let cost_micro = match calculate_cost_usdc_micro(price, quantity) {
+                    Some(c) => c,
+                    None => return Err("cost overflow - invalid order".into()),
+                };
+                let usdc = assets.get_mut("USDC").unwrap();
+                if usdc.available < cost_micro {
+                    return Err("insufficient USDC funds".into());
+                }
+                usdc.available -= cost_micro;
+                usdc.locked += cost_micro;
+            }
+            Side::Ask => {
+                let btc = assets.get_mut("BTC").unwrap();
+                if btc.available < quantity {
+                    return Err("insufficient BTC funds".into());
+                }
+                btc.available -= quantity;
+                btc.locked += quantity;
+            }
+        }
+
+        self.order_records.insert(OrderRecord::new(order_id, user_id, side, Some(price), quantity, now_millis()));
+        self.pegged_orders.insert(order_id);
+        self.rest_order(Order {
+            id: order_id,
+            user_id,
+            side,
+            price,
+            quantity,
+            peg: Some(peg),
+        });
+
+        self.reprice_pegged_orders();
+        Ok(order_id.to_string())
+    }
+
+    /// Target price pulled back one tick inside the opposite side, so a peg never takes
+    /// liquidity. `None` if there's no room inside it.
+    fn passive_peg_price(&self, side: Side, peg: &Peg, refs: &PegReferences) -> Option<u64> {
+        let target = peg.target_price(side, refs)?;
+        let target = match side {
+            Side::Bid => match self.orderbook.best_ask() {
+                Some(ask) => target.min(ask.saturating_sub(1)),
+                None => target,
+            },
+            Side::Ask => match self.orderbook.best_bid() {
+                Some(bid) => target.max(bid.checked_add(1)?),
+                None => target,
+            },
+        };
+        if target == 0 { None } else { Some(target) }
+    }
+
+    /// Moves every pegged order to its current target price.
+    ///
+    /// Orders are visited in book priority (best price first, then time). An order whose
+    /// price is unchanged keeps its place in the queue; a repriced order goes to the back of
+    /// its new level. A bid that can't fund a higher price stays where it is.
+    pub(super) fn reprice_pegged_orders(&mut self) {
+        let order_index = &self.order_index;
+        self.pegged_orders.retain(|id| order_index.contains_key(id));
+        if self.pegged_orders.is_empty() || !self.market.state.reprices_pegs() {
+            return;
+        }
+
+        let refs = self.orderbook.peg_references();
+
+        for side in [Side::Bid, Side::Ask] {
+            let book_side = match side {
+                Side::Bid => &self.orderbook.bids,
+                Side::Ask => &self.orderbook.asks,
+            };
+            let levels: Vec<&VecDeque<Order>> = match side {
+                Side::Bid => book_side.values().rev().collect(),
+                Side::Ask => book_side.values().collect(),
+            };
+            let pegged: Vec<(Uuid, Peg)> = levels
+                .into_iter()
+                .flatten()
+                .filter_map(|o| o.peg.map(|peg| (o.id, peg)))
+                .collect();
+
+            for (order_id, peg) in pegged {
+                let target = match self.passive_peg_price(side, &peg, &refs) {
+                    Some(p) => p,
+                    None => continue,
+                };
+                let current = self.order_index[&order_id].1;
+                if target == current {
+                    continue;
+                }
+
+                let book_side = match side {
+                    Side::Bid => &mut self.orderbook.bids,
+                    Side::Ask => &mut self.orderbook.asks,
+                };
+                let queue = book_side.get_mut(&current).unwrap();
+                let pos = queue.iter().position(|o| o.id == order_id).unwrap();
+
+                if side == Side::Bid {
+                    let order = &queue[pos];
+                    let old_cost = calculate_cost_usdc_micro(current, order.quantity).unwrap();
+                    let new_cost = match calculate_cost_usdc_micro(target, order.quantity) {
+                        Some(c) => c,
+                        None => continue,
+                    };
+                    let usdc = self.balances.users.get_mut(&order.user_id).unwrap().assets.get_mut("USDC").unwrap();
+                    if new_cost > old_cost {
+                        if usdc.available < new_cost - old_cost {
+                            continue;
+                        }
+                        usdc.available -= new_cost - old_cost;
+                        usdc.locked += new_cost - old_cost;
+                    } else {
+                        usdc.locked -= old_cost - new_cost;
+                        usdc.available += old_cost - new_cost;
+                    }
+                }
+
+                let mut order = queue.remove(pos).unwrap();
+                if queue.is_empty() {
+                    book_side.remove(&current);
+                }
+
+                order.price = target;
+                self.order_index.insert(order_id, (side, target));
+                self.risk.track_open(order.user_id, side, current, order.quantity, 0);
+                self.risk.track_open(order.user_id, side, target, 0, order.quantity);
+                self.order_records.set_price(order_id, target, now_millis());
+                self.feed.publish_l3(L3Event::Modify {
+                    order_id,
+                    side,
+                    old_price: current,
+                    price: target,
+                    quantity: order.quantity,
+                }, now_millis());
+                self.orderbook.add_order(order);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::client_orders::OrderKey;
+    use crate::engine::testing::{BTC, USDC, add_user, balance, engine, place, resting};
+
+    fn peg(reference: PegReference, offset: i64, limit: Option<u64>) -> Peg {
+        Peg { reference, offset, limit }
+    }
+
+    fn refs(best_bid: u64, best_ask: u64) -> PegReferences {
+        PegReferences { best_bid: Some(best_bid), best_ask: Some(best_ask) }
+    }
+
+    fn place_pegged(engine: &mut Engine, user_id: Uuid, side: Side, quantity: u64, peg: Peg) -> Uuid {
+        let order_id = Uuid::new_v4();
+        engine.create_pegged_order(order_id, user_id, side, quantity, peg).unwrap();
+        order_id
+    }
+
+    fn queue(engine: &Engine, side: Side, price: u64) -> Vec<Uuid> {
+        let book_side = match side {
+            Side::Bid => &engine.orderbook.bids,
+            Side::Ask => &engine.orderbook.asks,
+        };
+        book_side[&price].iter().map(|o| o.id).collect()
+    }
+
+    #[test]
+    fn target_price_follows_the_reference() {
+        let refs = refs(100, 110);
+        let wide = PegReferences { best_ask: Some(111), ..refs };
+        assert_eq!(peg(PegReference::Primary, 2, None).target_price(Side::Bid, &refs), Some(102));
+        assert_eq!(peg(PegReference::Primary, -2, None).target_price(Side::Ask, &refs), Some(108));
+        assert_eq!(peg(PegReference::Market, -1, None).target_price(Side::Bid, &refs), Some(109));
+        assert_eq!(peg(PegReference::Market, 1, None).target_price(Side::Ask, &refs), Some(101));
+        assert_eq!(peg(PegReference::Mid, 0, None).target_price(Side::Bid, &wide), Some(105));
+    }
+
+    #[test]
+    fn target_price_is_capped_by_the_limit() {
+        let refs = refs(100, 110);
+        assert_eq!(peg(PegReference::Primary, 5, Some(103)).target_price(Side::Bid, &refs), Some(103));
+        assert_eq!(peg(PegReference::Primary, -5, Some(107)).target_price(Side::Ask, &refs), Some(107));
+        assert_eq!(peg(PegReference::Primary, 0, Some(103)).target_price(Side::Bid, &refs), Some(100));
+    }
+
+    #[test]
+    fn target_price_needs_a_reference_and_a_positive_price() {
+        let one_sided = PegReferences { best_bid: Some(100), best_ask: None };
+        assert_eq!(peg(PegReference::Mid, 0, None).target_price(Side::Bid, &one_sided), None);
+        assert_eq!(peg(PegReference::Market, 0, None).target_price(Side::Bid, &one_sided), None);
+        assert_eq!(peg(PegReference::Primary, -100, None).target_price(Side::Bid, &one_sided), None);
+        assert_eq!(peg(PegReference::Primary, -101, None).target_price(Side::Bid, &one_sided), None);
+    }
+
+    #[test]
+    fn target_price_handles_prices_at_the_extremes() {
+        let refs = refs(u64::MAX - 2, u64::MAX);
+        assert_eq!(peg(PegReference::Mid, 0, None).target_price(Side::Bid, &refs), Some(u64::MAX - 1));
+        assert_eq!(peg(PegReference::Mid, 2, None).target_price(Side::Ask, &refs), None);
+        assert_eq!(peg(PegReference::Market, 0, None).target_price(Side::Ask, &refs), Some(u64::MAX - 2));
+        assert_eq!(peg(PegReference::Mid, i64::MIN, None).target_price(Side::Bid, &refs), Some(u64::MAX - 1 - (1 << 63)));
+    }
+
+    #[test]
+    fn pegs_rest_one_tick_inside_the_opposite_side() {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, BTC, 1_000 * USDC);
+        let pegger = add_user(&mut engine, BTC, 1_000 * USDC);
+        place(&mut engine, maker, Side::Bid, 100 * USDC, BTC / 10);
+        place(&mut engine, maker, Side::Ask, 101 * USDC, BTC / 10);
+
+        place_pegged(&mut engine, pegger, Side::Bid, BTC / 10, peg(PegReference::Market, 0, None));
+        assert_eq!(resting(&engine, Side::Bid)[0], (101 * USDC - 1, BTC / 10));
+
+        // the pegged bid is now the best bid, so the ask can't go below 101
+        let ask = place_pegged(&mut engine, pegger, Side::Ask, BTC / 10, peg(PegReference::Market, 0, None));
+        assert_eq!(engine.order_index[&ask], (Side::Ask, 101 * USDC));
+        assert_eq!(engine.last_trade_price, None);
+    }
+
+    #[test]
+    fn pegged_ask_against_a_bid_at_the_top_of_the_price_range_is_refused() {
+        let mut engine = engine();
+        let whale = add_user(&mut engine, 0, u64::MAX);
+        let pegger = add_user(&mut engine, BTC, 0);
+        place(&mut engine, whale, Side::Bid, u64::MAX, 1);
+
+        let reply = engine.create_pegged_order(Uuid::new_v4(), pegger, Side::Ask, BTC, peg(PegReference::Market, 0, None));
+        assert_eq!(reply, Err("no reference price to peg to".to_string()));
+        assert_eq!(balance(&engine, pegger, "BTC"), (BTC, 0));
+    }
+
+    #[test]
+    fn repricing_keeps_book_priority_between_pegs() {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, 0, 1_000 * USDC);
+        let pegger = add_user(&mut engine, 0, 1_000 * USDC);
+        place(&mut engine, maker, Side::Bid, 100 * USDC, BTC / 10);
+        let first = place_pegged(&mut engine, pegger, Side::Bid, BTC / 10, peg(PegReference::Primary, 0, None));
+        let second = place_pegged(&mut engine, pegger, Side::Bid, BTC / 10, peg(PegReference::Primary, 0, None));
+
+        let improver = place(&mut engine, maker, Side::Bid, 102 * USDC, BTC / 10);
+        assert_eq!(queue(&engine, Side::Bid, 102 * USDC), vec![improver, first, second]);
+        assert_eq!(engine.order_index[&first], (Side::Bid, 102 * USDC));
+        assert_eq!(engine.order_records.get(&second).unwrap().price, Some(102 * USDC));
+    }
+
+    #[test]
+    fn repricing_stops_at_the_limit() {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, 0, 1_000 * USDC);
+        let pegger = add_user(&mut engine, 0, 1_000 * USDC);
+        place(&mut engine, maker, Side::Bid, 100 * USDC, BTC / 10);
+        let pegged = place_pegged(&mut engine, pegger, Side::Bid, BTC / 10, peg(PegReference::Primary, 0, Some(101 * USDC)));
+
+        place(&mut engine, maker, Side::Bid, 105 * USDC, BTC / 10);
+        assert_eq!(engine.order_index[&pegged], (Side::Bid, 101 * USDC));
+    }
+
+    #[test]
+    fn repricing_a_bid_moves_its_locked_usdc() {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, 0, 1_000 * USDC);
+        let pegger = add_user(&mut engine, 0, 20 * USDC);
+        place(&mut engine, maker, Side::Bid, 100 * USDC, BTC / 10);
+        place_pegged(&mut engine, pegger, Side::Bid, BTC / 10, peg(PegReference::Primary, 0, None));
+        assert_eq!(balance(&engine, pegger, "USDC"), (10 * USDC, 10 * USDC));
+
+        let improver = place(&mut engine, maker, Side::Bid, 150 * USDC, BTC / 10);
+        assert_eq!(balance(&engine, pegger, "USDC"), (5 * USDC, 15 * USDC));
+
+        engine.cancel_order(maker, &OrderKey::OrderId(improver)).unwrap();
+        assert_eq!(balance(&engine, pegger, "USDC"), (10 * USDC, 10 * USDC));
+    }
+
+    #[test]
+    fn a_bid_that_cant_fund_the_new_price_stays_put() {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, 0, 1_000 * USDC);
+        let pegger = add_user(&mut engine, 0, 12 * USDC);
+        place(&mut engine, maker, Side::Bid, 100 * USDC, BTC / 10);
+        let pegged = place_pegged(&mut engine, pegger, Side::Bid, BTC / 10, peg(PegReference::Primary, 0, None));
+
+        place(&mut engine, maker, Side::Bid, 150 * USDC, BTC / 10);
+        assert_eq!(engine.order_index[&pegged], (Side::Bid, 100 * USDC));
+        assert_eq!(balance(&engine, pegger, "USDC"), (2 * USDC, 10 * USDC));
+    }
+}
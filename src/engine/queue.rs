@@ -0,0 +1,98 @@
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, TrySendError};
+
+use super::EngineCommand;
+
+/// Default number of commands that may wait for the engine before new ones are refused.
+pub const DEFAULT_QUEUE_CAPACITY: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The queue is at capacity; the caller should back off and retry.
+    Full,
+    /// The engine thread has exited, so nothing will ever read the command.
+    EngineStopped,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Full => f.write_str("engine is overloaded, try again shortly"),
+            SendError::EngineStopped => f.write_str("engine is not running"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueStats {
+    depth: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+/// Sending half of the bounded command queue. Never blocks: a full queue is reported
+/// instead of waiting for room.
+#[derive(Debug, Clone)]
+pub struct CommandSender {
+    tx: mpsc::SyncSender<EngineCommand>,
+    capacity: usize,
+    stats: Arc<QueueStats>,
+}
+
+pub struct CommandReceiver {
+    rx: mpsc::Receiver<EngineCommand>,
+    stats: Arc<QueueStats>,
+}
+
+pub fn command_queue(capacity: usize) -> (CommandSender, CommandReceiver) {
+    let (tx, rx) = mpsc::sync_channel(capacity);
+    let stats = Arc::new(QueueStats::default());
+    (
+        CommandSender { tx, capacity, stats: stats.clone() },
+        CommandReceiver { rx, stats },
+    )
+}
+
+impl CommandSender {
+    pub fn send(&self, cmd: EngineCommand) -> Result<(), SendError> {
+        // counted before sending so the engine can never take it below zero
+        self.stats.depth.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(cmd) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+                match e {
+                    TrySendError::Full(_) => {
+                        self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                        Err(SendError::Full)
+                    }
+                    TrySendError::Disconnected(_) => Err(SendError::EngineStopped),
+                }
+            }
+        }
+    }
+
+    /// Commands waiting for the engine.
+    pub fn depth(&self) -> usize {
+        self.stats.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Commands refused because the queue was full, since startup.
+    pub fn rejected(&self) -> u64 {
+        self.stats.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl CommandReceiver {
+    /// Blocks for the next command; `None` once every sender is gone.
+    pub fn recv(&self) -> Option<EngineCommand> {
+        let cmd = self.rx.recv().ok()?;
+        self.stats.depth.fetch_sub(1, Ordering::Relaxed);
+        Some(cmd)
+    }
+}
@@ -0,0 +1,261 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::orderbook::Side;
+
+/// How long filled, cancelled, expired and rejected orders stay queryable.
+pub const TERMINAL_ORDER_RETENTION_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderStatus {
+    New,
+    PartiallyFilled,
+    Filled,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+impl OrderStatus {
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, OrderStatus::Filled | OrderStatus::Cancelled | OrderStatus::Expired | OrderStatus::Rejected)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OrderRecord {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub side: Side,
+    /// Limit (or current pegged) price in micro USDC; `None` for market orders.
+    pub price: Option<u64>,
+    pub original_quantity: u64,
+    pub filled_quantity: u64,
+    pub remaining_quantity: u64,
+    /// Volume weighted fill price in micro USDC.
+    pub average_fill_price: Option<u64>,
+    pub status: OrderStatus,
+    pub client_order_id: Option<String>,
+    pub reject_reason: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    #[serde(skip)]
+    filled_notional: u128,
+}
+
+impl OrderRecord {
+    pub fn new(id: Uuid, user_id: Uuid, side: Side, price: Option<u64>, quantity: u64, now: u64) -> Self {
+        Self {
+            id,
+            user_id,
+            side,
+            price,
+            original_quantity: quantity,
+            filled_quantity: 0,
+            remaining_quantity: quantity,
+            average_fill_price: None,
+            status: OrderStatus::New,
+            client_order_id: None,
+            reject_reason: None,
+            created_at: now,
+            updated_at: now,
+            filled_notional: 0,
+        }
+    }
+}
+
+/// Every order the engine has accepted or rejected, keyed by id. Open orders live here for
+/// as long as they rest; terminal ones are dropped after `TERMINAL_ORDER_RETENTION_MS`.
+#[derive(Debug, Clone)]
+pub struct OrderRecords {
+    by_id: HashMap<Uuid, OrderRecord>,
+    terminal: VecDeque<(u64, Uuid)>,
+    changed: Vec<Uuid>,
+}
+
+impl OrderRecords {
+    pub fn new() -> Self {
+        Self {
+            by_id: HashMap::new(),
+            terminal: VecDeque::new(),
+            changed: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, order_id: &Uuid) -> Option<&OrderRecord> {
+        self.by_id.get(order_id)
+    }
+
+    pub fn insert(&mut self, record: OrderRecord) {
+        if record.status.is_terminal() {
+            self.terminal.push_back((record.updated_at, record.id));
+        }
+        self.changed.push(record.id);
+        self.by_id.insert(record.id, record);
+    }
+
+    pub fn reject(&mut self, mut record: OrderRecord, reason: &str) {
+        record.status = OrderStatus::Rejected;
+        record.reject_reason = Some(reason.to_string());
+        self.insert(record);
+    }
+
+    pub fn record_fill(&mut self, order_id: Uuid, quantity: u64, price: u64, now: u64) {
+        let record = match self.by_id.get_mut(&order_id) {
+            Some(r) => r,
+            None => return,
+        };
+
+        record.filled_quantity += quantity;
+        record.remaining_quantity -= quantity;
+        record.filled_notional += price as u128 * quantity as u128;
+        record.average_fill_price = Some((record.filled_notional / record.filled_quantity as u128) as u64);
+        record.updated_at = now;
+        self.changed.push(order_id);
+
+        if record.remaining_quantity == 0 {
+            record.status = OrderStatus::Filled;
+            self.terminal.push_back((now, order_id));
+        } else {
+            record.status = OrderStatus::PartiallyFilled;
+        }
+    }
+
+    pub fn set_client_order_id(&mut self, order_id: Uuid, client_order_id: String) {
+        if let Some(record) = self.by_id.get_mut(&order_id) {
+            record.client_order_id = Some(client_order_id);
+        }
+    }
+
+    pub fn set_price(&mut self, order_id: Uuid, price: u64, now: u64) {
+        if let Some(record) = self.by_id.get_mut(&order_id) {
+            record.price = Some(price);
+            record.updated_at = now;
+            self.changed.push(order_id);
+        }
+    }
+
+    /// Closes an open order with `Cancelled` or `Expired`, leaving `remaining_quantity` as the
+    /// amount that never traded.
+    pub fn finish(&mut self, order_id: Uuid, status: OrderStatus, now: u64) {
+        let record = match self.by_id.get_mut(&order_id) {
+            Some(r) if !r.status.is_terminal() => r,
+            _ => return,
+        };
+
+        record.status = status;
+        record.updated_at = now;
+        self.terminal.push_back((now, order_id));
+        self.changed.push(order_id);
+    }
+
+    /// Records changed since the last call, each once, in the order they first changed.
+    pub fn take_changed(&mut self) -> Vec<OrderRecord> {
+        let mut seen = HashSet::new();
+        std::mem::take(&mut self.changed)
+            .into_iter()
+            .filter(|id| seen.insert(*id))
+            .filter_map(|id| self.by_id.get(&id).cloned())
+            .collect()
+    }
+
+    pub fn open_orders(&self, user_id: Uuid) -> Vec<OrderRecord> {
+        let mut open: Vec<OrderRecord> = self.by_id
+            .values()
+            .filter(|r| r.user_id == user_id && !r.status.is_terminal())
+            .cloned()
+            .collect();
+        open.sort_by_key(|r| r.created_at);
+        open
+    }
+
+    /// Drops terminal orders past the retention window and returns them.
+    pub fn prune(&mut self, now: u64) -> Vec<OrderRecord> {
+        let mut pruned = Vec::new();
+        while let Some((finished_at, order_id)) = self.terminal.front().copied() {
+            if now.saturating_sub(finished_at) < TERMINAL_ORDER_RETENTION_MS {
+                break;
+            }
+            self.terminal.pop_front();
+            if let Some(record) = self.by_id.remove(&order_id) {
+                pruned.push(record);
+            }
+        }
+        pruned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::client_orders::OrderKey;
+    use crate::engine::testing::{BTC, USDC, add_user, balance, engine, place};
+
+    #[test]
+    fn records_follow_an_order_through_fills() {
+        let mut engine = engine();
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let seller = add_user(&mut engine, 2 * BTC, 0);
+        let bid = place(&mut engine, buyer, Side::Bid, 100 * USDC, BTC / 2);
+        assert_eq!(engine.order_records.get(&bid).unwrap().status, OrderStatus::New);
+
+        place(&mut engine, seller, Side::Ask, 100 * USDC, BTC / 5);
+        let record = engine.order_records.get(&bid).unwrap();
+        assert_eq!(record.status, OrderStatus::PartiallyFilled);
+        assert_eq!((record.filled_quantity, record.remaining_quantity), (BTC / 5, 3 * BTC / 10));
+        assert_eq!(record.average_fill_price, Some(100 * USDC));
+
+        place(&mut engine, seller, Side::Ask, 90 * USDC, BTC);
+        let record = engine.order_records.get(&bid).unwrap();
+        assert_eq!(record.status, OrderStatus::Filled);
+        assert_eq!(record.remaining_quantity, 0);
+        assert!(engine.order_records.open_orders(buyer).is_empty());
+    }
+
+    #[test]
+    fn cancelled_orders_keep_what_never_traded() {
+        let mut engine = engine();
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let bid = place(&mut engine, buyer, Side::Bid, 100 * USDC, BTC);
+        engine.cancel_order(buyer, &OrderKey::OrderId(bid)).unwrap();
+
+        let record = engine.order_records.get(&bid).unwrap();
+        assert_eq!(record.status, OrderStatus::Cancelled);
+        assert_eq!(record.remaining_quantity, BTC);
+    }
+
+    #[test]
+    fn zero_quantity_and_zero_price_are_rejected() {
+        let mut engine = engine();
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+
+        let order_id = Uuid::new_v4();
+        assert_eq!(engine.create_order(order_id, buyer, Side::Bid, 100 * USDC, 0), Err("quantity must be positive".to_string()));
+        let record = engine.order_records.get(&order_id).unwrap();
+        assert_eq!((record.status, record.reject_reason.as_deref()), (OrderStatus::Rejected, Some("invalid quantity")));
+
+        let order_id = Uuid::new_v4();
+        assert_eq!(engine.create_order(order_id, buyer, Side::Bid, 0, BTC), Err("price must be positive".to_string()));
+        assert_eq!(engine.order_records.get(&order_id).unwrap().reject_reason.as_deref(), Some("invalid price"));
+
+        assert!(engine.order_records.open_orders(buyer).is_empty());
+        assert_eq!(balance(&engine, buyer, "USDC"), (1_000 * USDC, 0));
+    }
+
+    #[test]
+    fn terminal_records_are_pruned_after_the_retention_window() {
+        let mut records = OrderRecords::new();
+        let user_id = Uuid::new_v4();
+        let open = OrderRecord::new(Uuid::new_v4(), user_id, Side::Bid, Some(1), 1, 0);
+        let rejected = OrderRecord::new(Uuid::new_v4(), user_id, Side::Bid, Some(1), 1, 0);
+        let (open_id, rejected_id) = (open.id, rejected.id);
+        records.insert(open);
+        records.reject(rejected, "invalid quantity");
+
+        assert!(records.prune(TERMINAL_ORDER_RETENTION_MS - 1).is_empty());
+        let pruned: Vec<Uuid> = records.prune(TERMINAL_ORDER_RETENTION_MS).iter().map(|r| r.id).collect();
+        assert_eq!(pruned, vec![rejected_id]);
+        assert!(records.get(&open_id).is_some());
+    }
+}
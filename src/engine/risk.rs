@@ -0,0 +1,332 @@
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::{Engine, calculate_cost_usdc_micro};
+use super::orderbook::Side;
+
+const ORDER_RATE_WINDOW_MS: u64 = 1000;
+
+/// Per-user pre-trade limits. `None` means unlimited. Notionals are in micro USDC,
+/// quantities in sats.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RiskLimits {
+    pub max_order_quantity: Option<u64>,
+    pub max_order_notional: Option<u64>,
+    pub max_open_orders: Option<usize>,
+    pub max_open_bid_notional: Option<u64>,
+    pub max_open_ask_notional: Option<u64>,
+    pub max_orders_per_second: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RiskReject {
+    OrderQuantity,
+    OrderNotional,
+    OpenOrders,
+    OpenNotional,
+    OrderRate,
+}
+
+impl RiskReject {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RiskReject::OrderQuantity => "RISK_MAX_ORDER_QUANTITY",
+            RiskReject::OrderNotional => "RISK_MAX_ORDER_NOTIONAL",
+            RiskReject::OpenOrders => "RISK_MAX_OPEN_ORDERS",
+            RiskReject::OpenNotional => "RISK_MAX_OPEN_NOTIONAL",
+            RiskReject::OrderRate => "RISK_MAX_ORDER_RATE",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        let reason = match self {
+            RiskReject::OrderQuantity => "order quantity is above the user's limit",
+            RiskReject::OrderNotional => "order notional is above the user's limit",
+            RiskReject::OpenOrders => "user already has the maximum number of open orders",
+            RiskReject::OpenNotional => "order would take open notional on this side above the user's limit",
+            RiskReject::OrderRate => "user is sending orders faster than allowed",
+        };
+        format!("rejected: {} - {}", self.code(), reason)
+    }
+}
+
+/// What one user has resting on the book.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct OpenExposure {
+    orders: usize,
+    /// Sum of each resting order's cost, in micro USDC.
+    bid_notional: u128,
+    ask_notional: u128,
+}
+
+#[derive(Debug, Clone)]
+pub struct RiskState {
+    limits: HashMap<Uuid, RiskLimits>,
+    recent_orders: HashMap<Uuid, VecDeque<u64>>,
+    /// Kept up to date as orders rest, fill, reprice and cancel, so the open order limits
+    /// don't have to scan the book.
+    open: HashMap<Uuid, OpenExposure>,
+}
+
+impl RiskState {
+    pub fn new() -> Self {
+        Self {
+            limits: HashMap::new(),
+            recent_orders: HashMap::new(),
+            open: HashMap::new(),
+        }
+    }
+
+    pub fn limits(&self, user_id: &Uuid) -> RiskLimits {
+        self.limits.get(user_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set_limits(&mut self, user_id: Uuid, limits: RiskLimits) {
+        self.limits.insert(user_id, limits);
+    }
+
+    /// Counts an order against the user's rate limit, refusing it if the window is full.
+    fn admit(&mut self, user_id: Uuid, max_per_second: u32, now: u64) -> bool {
+        let times = self.recent_orders.entry(user_id).or_default();
+        while times.front().is_some_and(|t| now.saturating_sub(*t) >= ORDER_RATE_WINDOW_MS) {
+            times.pop_front();
+        }
+        if times.len() >= max_per_second as usize {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+
+    /// Moves a resting order at `price` from `before` to `after` sats. `before` is 0 for an
+    /// order that has just rested and `after` is 0 once it has left the book.
+    pub fn track_open(&mut self, user_id: Uuid, side: Side, price: u64, before: u64, after: u64) {
+        let open = self.open.entry(user_id).or_default();
+        let notional = match side {
+            Side::Bid => &mut open.bid_notional,
+            Side::Ask => &mut open.ask_notional,
+        };
+        *notional = *notional + open_notional(price, after) - open_notional(price, before);
+        match (before, after) {
+            (0, 0) => {}
+            (0, _) => open.orders += 1,
+            (_, 0) => open.orders -= 1,
+            _ => {}
+        }
+        if open.orders == 0 {
+            self.open.remove(&user_id);
+        }
+    }
+}
+
+/// What a resting order counts for against the open notional limits: its cost, rounded down
+/// the same way as the USDC it locks.
+fn open_notional(price: u64, quantity: u64) -> u128 {
+    price as u128 * quantity as u128 / 100_000_000
+}
+
+impl Engine {
+    /// Runs the user's pre-trade limits against a new order. `price` is the limit price, or
+    /// the best estimate of it for orders that don't carry one.
+    pub(super) fn check_risk(&mut self, user_id: Uuid, side: Side, price: u64, quantity: u64, now: u64) -> Result<(), RiskReject> {
+        let limits = match self.risk.limits.get(&user_id) {
+            Some(l) => l.clone(),
+            None => return Ok(()),
+        };
+
+        if limits.max_order_quantity.is_some_and(|max| quantity > max) {
+            return Err(RiskReject::OrderQuantity);
+        }
+
+        let notional = calculate_cost_usdc_micro(price, quantity).unwrap_or(u64::MAX);
+        if limits.max_order_notional.is_some_and(|max| notional > max) {
+            return Err(RiskReject::OrderNotional);
+        }
+
+        let side_limit = match side {
+            Side::Bid => limits.max_open_bid_notional,
+            Side::Ask => limits.max_open_ask_notional,
+        };
+        let open = self.risk.open.get(&user_id).copied().unwrap_or_default();
+        if limits.max_open_orders.is_some_and(|max| open.orders >= max) {
+            return Err(RiskReject::OpenOrders);
+        }
+        let open_on_side = match side {
+            Side::Bid => open.bid_notional,
+            Side::Ask => open.ask_notional,
+        };
+        if side_limit.is_some_and(|max| open_on_side + notional as u128 > max as u128) {
+            return Err(RiskReject::OpenNotional);
+        }
+
+        if let Some(max) = limits.max_orders_per_second
+            && !self.risk.admit(user_id, max, now)
+        {
+            return Err(RiskReject::OrderRate);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::client_orders::OrderKey;
+    use crate::engine::peg::{Peg, PegReference};
+    use crate::engine::testing::{BTC, USDC, add_user, engine, place};
+
+    fn reject(engine: &mut Engine, user_id: Uuid, side: Side, price: u64, quantity: u64) -> Option<RiskReject> {
+        engine.check_risk(user_id, side, price, quantity, 0).err()
+    }
+
+    /// What the user has resting, counted straight off the book.
+    fn scanned(engine: &Engine, user_id: Uuid) -> OpenExposure {
+        let mut open = OpenExposure::default();
+        for order in engine.orderbook.bids.values().chain(engine.orderbook.asks.values()).flatten() {
+            if order.user_id != user_id {
+                continue;
+            }
+            open.orders += 1;
+            match order.side {
+                Side::Bid => open.bid_notional += open_notional(order.price, order.quantity),
+                Side::Ask => open.ask_notional += open_notional(order.price, order.quantity),
+            }
+        }
+        open
+    }
+
+    fn tracked(engine: &Engine, user_id: Uuid) -> OpenExposure {
+        engine.risk.open.get(&user_id).copied().unwrap_or_default()
+    }
+
+    #[test]
+    fn users_without_limits_are_never_checked() {
+        let mut engine = engine();
+        let user = add_user(&mut engine, 0, 0);
+        assert_eq!(reject(&mut engine, user, Side::Bid, u64::MAX, u64::MAX), None);
+    }
+
+    #[test]
+    fn single_order_limits() {
+        let mut engine = engine();
+        let user = add_user(&mut engine, 0, 0);
+        engine.risk.set_limits(user, RiskLimits {
+            max_order_quantity: Some(BTC),
+            max_order_notional: Some(50_000 * USDC),
+            ..RiskLimits::default()
+        });
+
+        assert_eq!(reject(&mut engine, user, Side::Bid, USDC, BTC + 1), Some(RiskReject::OrderQuantity));
+        assert_eq!(reject(&mut engine, user, Side::Ask, 50_001 * USDC, BTC), Some(RiskReject::OrderNotional));
+        assert_eq!(reject(&mut engine, user, Side::Ask, 50_000 * USDC, BTC), None);
+        // a notional too big to compute is over any limit
+        assert_eq!(reject(&mut engine, user, Side::Bid, u64::MAX, BTC), Some(RiskReject::OrderNotional));
+    }
+
+    #[test]
+    fn open_orders_count_until_they_leave_the_book() {
+        let mut engine = engine();
+        let user = add_user(&mut engine, BTC, 1_000 * USDC);
+        engine.risk.set_limits(user, RiskLimits { max_open_orders: Some(2), ..RiskLimits::default() });
+
+        let bid = place(&mut engine, user, Side::Bid, 90 * USDC, BTC / 10);
+        place(&mut engine, user, Side::Ask, 110 * USDC, BTC / 10);
+        assert_eq!(reject(&mut engine, user, Side::Bid, 90 * USDC, BTC / 10), Some(RiskReject::OpenOrders));
+        assert_eq!(
+            engine.create_order(Uuid::new_v4(), user, Side::Bid, 90 * USDC, BTC / 10),
+            Err(RiskReject::OpenOrders.message())
+        );
+
+        engine.cancel_order(user, &OrderKey::OrderId(bid)).unwrap();
+        assert_eq!(reject(&mut engine, user, Side::Bid, 90 * USDC, BTC / 10), None);
+
+        // a partial fill still leaves the ask open; a full one frees the slot
+        place(&mut engine, user, Side::Bid, 90 * USDC, BTC / 10);
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        place(&mut engine, buyer, Side::Bid, 110 * USDC, BTC / 20);
+        assert_eq!(reject(&mut engine, user, Side::Bid, 90 * USDC, BTC / 10), Some(RiskReject::OpenOrders));
+        place(&mut engine, buyer, Side::Bid, 110 * USDC, BTC / 20);
+        assert_eq!(reject(&mut engine, user, Side::Bid, 90 * USDC, BTC / 10), None);
+    }
+
+    #[test]
+    fn open_notional_is_limited_per_side() {
+        let mut engine = engine();
+        let user = add_user(&mut engine, BTC, 1_000 * USDC);
+        engine.risk.set_limits(user, RiskLimits {
+            max_open_bid_notional: Some(100 * USDC),
+            max_open_ask_notional: Some(20 * USDC),
+            ..RiskLimits::default()
+        });
+
+        place(&mut engine, user, Side::Bid, 100 * USDC, BTC / 2);
+        assert_eq!(reject(&mut engine, user, Side::Bid, 100 * USDC, BTC / 2), None);
+        assert_eq!(reject(&mut engine, user, Side::Bid, 100 * USDC, BTC / 2 + 1), Some(RiskReject::OpenNotional));
+        // resting bids don't count against the ask limit
+        assert_eq!(reject(&mut engine, user, Side::Ask, 200 * USDC, BTC / 10), None);
+        assert_eq!(reject(&mut engine, user, Side::Ask, 200 * USDC, BTC / 10 + 1), Some(RiskReject::OpenNotional));
+    }
+
+    #[test]
+    fn order_rate_is_limited_per_second() {
+        let mut engine = engine();
+        let user = add_user(&mut engine, 0, 0);
+        engine.risk.set_limits(user, RiskLimits { max_orders_per_second: Some(2), ..RiskLimits::default() });
+
+        assert_eq!(engine.check_risk(user, Side::Bid, USDC, 1, 1_000), Ok(()));
+        assert_eq!(engine.check_risk(user, Side::Bid, USDC, 1, 1_500), Ok(()));
+        assert_eq!(engine.check_risk(user, Side::Bid, USDC, 1, 1_999), Err(RiskReject::OrderRate));
+        assert_eq!(engine.check_risk(user, Side::Bid, USDC, 1, 2_000), Ok(()));
+        // orders refused by an earlier limit don't use up the rate
+        engine.risk.set_limits(user, RiskLimits {
+            max_order_quantity: Some(1),
+            max_orders_per_second: Some(2),
+            ..RiskLimits::default()
+        });
+        assert_eq!(engine.check_risk(user, Side::Bid, USDC, 2, 2_600), Err(RiskReject::OrderQuantity));
+        assert_eq!(engine.check_risk(user, Side::Bid, USDC, 1, 2_600), Ok(()));
+    }
+
+    #[test]
+    fn tracked_exposure_matches_the_book() {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, 10 * BTC, 10_000 * USDC);
+        let taker = add_user(&mut engine, 10 * BTC, 10_000 * USDC);
+
+        let bid = place(&mut engine, maker, Side::Bid, 99_999_999, 33_333_333);
+        place(&mut engine, maker, Side::Bid, 98 * USDC, BTC);
+        place(&mut engine, maker, Side::Ask, 101 * USDC, BTC);
+        place(&mut engine, maker, Side::Ask, 103 * USDC, BTC);
+        engine.create_pegged_order(Uuid::new_v4(), maker, Side::Bid, BTC / 3, Peg {
+            reference: PegReference::Primary,
+            offset: 0,
+            limit: None,
+        }).unwrap();
+        assert_eq!(tracked(&engine, maker), scanned(&engine, maker));
+
+        // partial and full fills, a reprice of the peg, and a cancel
+        place(&mut engine, taker, Side::Ask, 98 * USDC, BTC / 2);
+        assert_eq!(tracked(&engine, maker), scanned(&engine, maker));
+        place(&mut engine, taker, Side::Bid, 103 * USDC, 3 * BTC / 2);
+        assert_eq!(tracked(&engine, maker), scanned(&engine, maker));
+        if engine.order_index.contains_key(&bid) {
+            engine.cancel_order(maker, &OrderKey::OrderId(bid)).unwrap();
+        }
+        assert_eq!(tracked(&engine, maker), scanned(&engine, maker));
+        assert_eq!(tracked(&engine, taker), scanned(&engine, taker));
+
+        // an empty book leaves nothing behind
+        for user_id in [maker, taker] {
+            let ids: Vec<_> = engine.orderbook.bids.values().chain(engine.orderbook.asks.values())
+                .flatten()
+                .filter(|o| o.user_id == user_id)
+                .map(|o| o.id)
+                .collect();
+            for id in ids {
+                engine.cancel_order(user_id, &OrderKey::OrderId(id)).unwrap();
+            }
+        }
+        assert!(engine.risk.open.is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::orderbook::Side;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum TrailAmount {
+    /// Fixed distance from the watermark, in micro USDC.
+    Offset(u64),
+    /// Distance as a fraction of the watermark, in basis points.
+    Percent(u32),
+}
+
+/// A stop that follows the last trade price and fires a market order once the price
+/// comes back by the trail amount. Sell stops (`Side::Ask`) trail below the highest price
+/// seen since placement, buy stops (`Side::Bid`) trail above the lowest.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrailingStop {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub side: Side,
+    pub quantity: u64,
+    pub trail: TrailAmount,
+    pub watermark: u64,
+    pub trigger_price: u64,
+}
+
+impl TrailingStop {
+    pub fn new(user_id: Uuid, side: Side, quantity: u64, trail: TrailAmount, last_price: u64) -> Self {
+        let mut stop = Self {
+            id: Uuid::new_v4(),
+            user_id,
+            side,
+            quantity,
+            trail,
+            watermark: last_price,
+            trigger_price: 0,
+        };
+        stop.trigger_price = stop.trigger_for(last_price);
+        stop
+    }
+
+    fn trigger_for(&self, watermark: u64) -> u64 {
+        let distance = match self.trail {
+            TrailAmount::Offset(offset) => offset,
+            TrailAmount::Percent(bps) => ((watermark as u128 * bps as u128) / 10_000) as u64,
+        };
+        match self.side {
+            Side::Ask => watermark.saturating_sub(distance),
+            Side::Bid => watermark.saturating_add(distance),
+        }
+    }
+
+    /// Moves the watermark with a new trade price and reports whether the stop fired.
+    pub fn on_trade_price(&mut self, price: u64) -> bool {
+        let improved = match self.side {
+            Side::Ask => price > self.watermark,
+            Side::Bid => price < self.watermark,
+        };
+        if improved {
+            self.watermark = price;
+            self.trigger_price = self.trigger_for(price);
+        }
+
+        match self.side {
+            Side::Ask => price <= self.trigger_price,
+            Side::Bid => price >= self.trigger_price,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::Engine;
+    use crate::engine::market::MarketState;
+    use crate::engine::matching::Trade;
+    use crate::engine::testing::{BTC, USDC, add_user, balance, engine, place, resting};
+
+    fn new_stop(side: Side, trail: TrailAmount, last_price: u64) -> TrailingStop {
+        TrailingStop::new(Uuid::new_v4(), side, BTC, trail, last_price)
+    }
+
+    #[test]
+    fn sell_stop_trails_below_the_high() {
+        let mut stop = new_stop(Side::Ask, TrailAmount::Offset(5), 100);
+        assert_eq!(stop.trigger_price, 95);
+
+        assert!(!stop.on_trade_price(110));
+        assert_eq!((stop.watermark, stop.trigger_price), (110, 105));
+        // a lower price doesn't pull the trigger back down
+        assert!(!stop.on_trade_price(106));
+        assert_eq!((stop.watermark, stop.trigger_price), (110, 105));
+        assert!(stop.on_trade_price(105));
+    }
+
+    #[test]
+    fn buy_stop_trails_above_the_low() {
+        let mut stop = new_stop(Side::Bid, TrailAmount::Offset(5), 100);
+        assert_eq!(stop.trigger_price, 105);
+
+        assert!(!stop.on_trade_price(90));
+        assert_eq!((stop.watermark, stop.trigger_price), (90, 95));
+        assert!(!stop.on_trade_price(94));
+        assert_eq!(stop.trigger_price, 95);
+        assert!(stop.on_trade_price(96));
+    }
+
+    #[test]
+    fn percent_trail_scales_with_the_watermark() {
+        let mut stop = new_stop(Side::Ask, TrailAmount::Percent(1_000), 100 * USDC);
+        assert_eq!(stop.trigger_price, 90 * USDC);
+        assert!(!stop.on_trade_price(200 * USDC));
+        assert_eq!(stop.trigger_price, 180 * USDC);
+
+        let mut stop = new_stop(Side::Bid, TrailAmount::Percent(250), 100 * USDC);
+        assert!(!stop.on_trade_price(80 * USDC));
+        assert_eq!(stop.trigger_price, 82 * USDC);
+    }
+
+    #[test]
+    fn triggers_saturate_at_the_ends_of_the_price_range() {
+        let stop = new_stop(Side::Ask, TrailAmount::Offset(500), 100);
+        assert_eq!(stop.trigger_price, 0);
+        let stop = new_stop(Side::Bid, TrailAmount::Offset(u64::MAX), 100);
+        assert_eq!(stop.trigger_price, u64::MAX);
+    }
+
+    /// An engine with a trade printed at 100 USDC and bids resting at 97, 94 and 90.
+    fn engine_with_bids() -> Engine {
+        let mut engine = engine();
+        let maker = add_user(&mut engine, BTC, 1_000 * USDC);
+        place(&mut engine, maker, Side::Bid, 100 * USDC, BTC / 10);
+        place(&mut engine, maker, Side::Ask, 100 * USDC, BTC / 10);
+        place(&mut engine, maker, Side::Bid, 97 * USDC, BTC / 10);
+        place(&mut engine, maker, Side::Bid, 94 * USDC, BTC / 10);
+        place(&mut engine, maker, Side::Bid, 90 * USDC, BTC);
+        assert_eq!(engine.last_trade_price, Some(100 * USDC));
+        engine
+    }
+
+    #[test]
+    fn triggered_stops_cascade() {
+        let mut engine = engine_with_bids();
+        let stopper = add_user(&mut engine, BTC, 0);
+        engine.create_trailing_stop(stopper, Side::Ask, BTC / 10, TrailAmount::Offset(2 * USDC)).unwrap();
+        engine.create_trailing_stop(stopper, Side::Ask, BTC / 10, TrailAmount::Offset(5 * USDC)).unwrap();
+
+        // a sale at 97 fires the first stop, whose sale at 94 fires the second
+        let seller = add_user(&mut engine, BTC, 0);
+        place(&mut engine, seller, Side::Ask, 97 * USDC, BTC / 20);
+
+        assert!(engine.trailing_stops.is_empty());
+        assert_eq!(engine.last_trade_price, Some(90 * USDC));
+        assert_eq!(resting(&engine, Side::Bid), vec![(90 * USDC, 19 * BTC / 20)]);
+        assert_eq!(balance(&engine, stopper, "BTC"), (8 * BTC / 10, 0));
+    }
+
+    #[test]
+    fn stops_that_dont_trigger_keep_trailing() {
+        let mut engine = engine_with_bids();
+        let stopper = add_user(&mut engine, BTC, 0);
+        engine.create_trailing_stop(stopper, Side::Ask, BTC / 10, TrailAmount::Offset(5 * USDC)).unwrap();
+
+        let seller = add_user(&mut engine, BTC, 0);
+        place(&mut engine, seller, Side::Ask, 97 * USDC, BTC / 20);
+        assert_eq!(engine.trailing_stops.len(), 1);
+        assert_eq!(engine.trailing_stops[0].trigger_price, 95 * USDC);
+    }
+
+    #[test]
+    fn stops_only_fire_while_the_market_is_continuous() {
+        let mut engine = engine_with_bids();
+        let stopper = add_user(&mut engine, BTC, 0);
+        engine.create_trailing_stop(stopper, Side::Ask, BTC / 10, TrailAmount::Offset(2 * USDC)).unwrap();
+
+        engine.market.state = MarketState::Halted;
+        engine.on_trades(vec![Trade {
+            taker_order_id: Uuid::new_v4(),
+            maker_order_id: Uuid::new_v4(),
+            maker_user_id: stopper,
+            taker_user_id: stopper,
+            taker_side: Side::Ask,
+            price: 50 * USDC,
+            quantity: 1,
+        }]);
+        assert_eq!(engine.last_trade_price, Some(50 * USDC));
+        assert_eq!(engine.trailing_stops.len(), 1);
+        assert_eq!(engine.trailing_stops[0].watermark, 100 * USDC);
+    }
+}
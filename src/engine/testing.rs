@@ -0,0 +1,58 @@
+//! Helpers for driving an `Engine` directly in unit tests.
+
+use std::collections::HashMap;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use super::Engine;
+use super::balance::{AssetBalance, UserBalance};
+use super::drop_copy::DropCopy;
+use super::feed::MarketFeed;
+use super::orderbook::Side;
+use super::user_feed::{UserFeed, UserUpdates};
+
+/// One USDC in micro USDC.
+pub const USDC: u64 = 1_000_000;
+/// One BTC in sats.
+pub const BTC: u64 = 100_000_000;
+
+/// An engine whose feeds go nowhere.
+pub fn engine() -> Engine {
+    let (feed_tx, _) = broadcast::channel(16);
+    let (drop_copy_tx, _) = broadcast::channel(16);
+    Engine::new(MarketFeed::new(feed_tx), UserFeed::new(UserUpdates::new(16)), DropCopy::new(drop_copy_tx))
+}
+
+/// Adds a user holding `btc` sats and `usdc` micro USDC.
+pub fn add_user(engine: &mut Engine, btc: u64, usdc: u64) -> Uuid {
+    let user_id = Uuid::new_v4();
+    engine.balances.users.insert(user_id, UserBalance {
+        assets: HashMap::from([
+            ("BTC".to_string(), AssetBalance { available: btc, locked: 0 }),
+            ("USDC".to_string(), AssetBalance { available: usdc, locked: 0 }),
+        ]),
+    });
+    user_id
+}
+
+/// `(available, locked)` of one of the user's assets.
+pub fn balance(engine: &Engine, user_id: Uuid, asset: &str) -> (u64, u64) {
+    let balance = &engine.balances.users[&user_id].assets[asset];
+    (balance.available, balance.locked)
+}
+
+/// Places a limit order that must be accepted, returning its id.
+pub fn place(engine: &mut Engine, user_id: Uuid, side: Side, price: u64, quantity: u64) -> Uuid {
+    let order_id = Uuid::new_v4();
+    engine.create_order(order_id, user_id, side, price, quantity).unwrap();
+    order_id
+}
+
+/// Resting orders as `(price, quantity)`, best first, in queue order within a level.
+pub fn resting(engine: &Engine, side: Side) -> Vec<(u64, u64)> {
+    let levels: Vec<_> = match side {
+        Side::Bid => engine.orderbook.bids.values().rev().collect(),
+        Side::Ask => engine.orderbook.asks.values().collect(),
+    };
+    levels.into_iter().flatten().map(|o| (o.price, o.quantity)).collect()
+}
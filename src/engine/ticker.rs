@@ -0,0 +1,146 @@
+use std::collections::VecDeque;
+use rust_decimal::Decimal;
+use serde::{Serialize, Deserialize};
+
+use super::{Engine, calculate_cost_usdc_micro};
+use super::orderbook::Side;
+use crate::math;
+
+/// Length of the rolling window behind the 24h statistics.
+pub const TICKER_WINDOW_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// Top of book and rolling 24h statistics, in the same decimal strings as the depth feed.
+/// The 24h fields are `None` when nothing traded in the window.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Ticker {
+    pub market: String,
+    pub last_price: Option<String>,
+    pub best_bid: Option<String>,
+    pub best_bid_size: Option<String>,
+    pub best_ask: Option<String>,
+    pub best_ask_size: Option<String>,
+    pub open_24h: Option<String>,
+    pub high_24h: Option<String>,
+    pub low_24h: Option<String>,
+    pub base_volume_24h: String,
+    pub quote_volume_24h: String,
+    /// Change from `open_24h` to `last_price`, in percent to two decimals.
+    pub price_change_percent_24h: Option<String>,
+    pub vwap_24h: Option<String>,
+}
+
+/// Trades in the last `TICKER_WINDOW_MS`, with running volume totals and monotonic queues
+/// for the high and low, so recording a trade or expiring old ones is amortised O(1).
+#[derive(Debug, Clone)]
+pub struct RollingStats {
+    /// `(timestamp, price, quantity, quote)` per trade, oldest first.
+    trades: VecDeque<(u64, u64, u64, u64)>,
+    highs: VecDeque<(u64, u64)>,
+    lows: VecDeque<(u64, u64)>,
+    base_volume: u128,
+    quote_volume: u128,
+}
+
+impl RollingStats {
+    pub fn new() -> Self {
+        Self {
+            trades: VecDeque::new(),
+            highs: VecDeque::new(),
+            lows: VecDeque::new(),
+            base_volume: 0,
+            quote_volume: 0,
+        }
+    }
+
+    pub fn record(&mut self, price: u64, quantity: u64, timestamp: u64) {
+        let quote = calculate_cost_usdc_micro(price, quantity).unwrap_or(u64::MAX);
+        self.trades.push_back((timestamp, price, quantity, quote));
+        self.base_volume += quantity as u128;
+        self.quote_volume += quote as u128;
+
+        while self.highs.back().is_some_and(|(_, p)| *p <= price) {
+            self.highs.pop_back();
+        }
+        self.highs.push_back((timestamp, price));
+        while self.lows.back().is_some_and(|(_, p)| *p >= price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((timestamp, price));
+    }
+
+    pub fn prune(&mut self, now: u64) {
+        let expired = |at: &u64| now.saturating_sub(*at) >= TICKER_WINDOW_MS;
+
+        while let Some((_, _, quantity, quote)) = self.trades.front().copied().filter(|(at, ..)| expired(at)) {
+            self.trades.pop_front();
+            self.base_volume -= quantity as u128;
+            self.quote_volume -= quote as u128;
+        }
+        while self.highs.front().is_some_and(|(at, _)| expired(at)) {
+            self.highs.pop_front();
+        }
+        while self.lows.front().is_some_and(|(at, _)| expired(at)) {
+            self.lows.pop_front();
+        }
+    }
+
+    pub fn open(&self) -> Option<u64> {
+        self.trades.front().map(|(_, p, ..)| *p)
+    }
+
+    pub fn last(&self) -> Option<u64> {
+        self.trades.back().map(|(_, p, ..)| *p)
+    }
+
+    pub fn high(&self) -> Option<u64> {
+        self.highs.front().map(|(_, p)| *p)
+    }
+
+    pub fn low(&self) -> Option<u64> {
+        self.lows.front().map(|(_, p)| *p)
+    }
+
+    /// Volume weighted average price in micro USDC.
+    pub fn vwap(&self) -> Option<u64> {
+        if self.base_volume == 0 {
+            return None;
+        }
+        u64::try_from(self.quote_volume * 100_000_000 / self.base_volume).ok()
+    }
+}
+
+impl Engine {
+    /// Current ticker. Trades that have aged out of the 24h window are dropped first.
+    pub(super) fn ticker(&mut self, now: u64) -> Ticker {
+        self.day_stats.prune(now);
+        let stats = &self.day_stats;
+
+        let top = |side| self.orderbook.levels(side, Some(1), None).first().copied();
+        let best_bid = top(Side::Bid);
+        let best_ask = top(Side::Ask);
+
+        let change = match (stats.open(), stats.last()) {
+            (Some(open), Some(last)) if open > 0 => {
+                let change = (Decimal::from(last) - Decimal::from(open)) * Decimal::from(100) / Decimal::from(open);
+                Some(change.round_dp(2).normalize().to_string())
+            }
+            _ => None,
+        };
+
+        Ticker {
+            market: self.market.symbol.clone(),
+            last_price: self.last_trade_price.map(math::micro_to_price_string),
+            best_bid: best_bid.map(|(p, _, _)| math::micro_to_price_string(p)),
+            best_bid_size: best_bid.map(|(_, q, _)| math::sats_to_btc_string(q)),
+            best_ask: best_ask.map(|(p, _, _)| math::micro_to_price_string(p)),
+            best_ask_size: best_ask.map(|(_, q, _)| math::sats_to_btc_string(q)),
+            open_24h: stats.open().map(math::micro_to_price_string),
+            high_24h: stats.high().map(math::micro_to_price_string),
+            low_24h: stats.low().map(math::micro_to_price_string),
+            base_volume_24h: math::sats_to_btc_string(u64::try_from(stats.base_volume).unwrap_or(u64::MAX)),
+            quote_volume_24h: math::micro_to_price_string(u64::try_from(stats.quote_volume).unwrap_or(u64::MAX)),
+            price_change_percent_24h: change,
+            vwap_24h: stats.vwap().map(math::micro_to_price_string),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+use serde::{Serialize, Deserialize};
+
+use super::{Engine, now_millis};
+use super::balance::UserBalance;
+use super::matching::Trade;
+use super::orderbook::Side;
+use super::records::OrderRecord;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+/// One side of a trade as seen by the user who owns the order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fill {
+    pub order_id: Uuid,
+    /// Same id as the trade on the public trades channel.
+    pub trade_id: u64,
+    pub side: Side,
+    pub price: u64,
+    pub quantity: u64,
+    pub liquidity: Liquidity,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UserEvent {
+    Order(OrderRecord),
+    Fill(Fill),
+    Balance { asset: String, available: u64, locked: u64 },
+}
+
+/// Private update for one user. Sequences are per user and start at 1.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserUpdate {
+    pub user_id: Uuid,
+    pub sequence: u64,
+    pub timestamp: u64,
+    #[serde(flatten)]
+    pub event: UserEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UserSnapshot {
+    /// Sequence of the last update already reflected in the snapshot.
+    pub sequence: u64,
+    pub balances: UserBalance,
+    pub open_orders: Vec<OrderRecord>,
+}
+
+/// Private updates routed to a channel per user, so a session only ever queues its own
+/// user's updates and a busy account can't make anyone else's sessions fall behind.
+#[derive(Debug, Clone)]
+pub struct UserUpdates {
+    capacity: usize,
+    channels: Arc<Mutex<HashMap<Uuid, broadcast::Sender<UserUpdate>>>>,
+}
+
+impl UserUpdates {
+    /// `capacity` is how many updates each user's subscribers may fall behind by.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            channels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn subscribe(&self, user_id: Uuid) -> broadcast::Receiver<UserUpdate> {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(user_id)
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe()
+    }
+
+    /// Sends to the update's user, if anyone is listening. A channel whose subscribers have
+    /// all gone is dropped the next time its user has an update.
+    fn send(&self, update: UserUpdate) {
+        let mut channels = self.channels.lock().unwrap();
+        let user_id = update.user_id;
+        if let Some(tx) = channels.get(&user_id)
+            && tx.send(update).is_err()
+        {
+            channels.remove(&user_id);
+        }
+    }
+}
+
+/// Collects what happened to each user during a command and publishes it afterwards: fills
+/// first, then the orders they touched, then any balance that moved.
+pub struct UserFeed {
+    sequences: HashMap<Uuid, u64>,
+    pending_fills: Vec<(Uuid, Fill)>,
+    touched_users: HashSet<Uuid>,
+    published_balances: HashMap<Uuid, UserBalance>,
+    tx: UserUpdates,
+}
+
+impl UserFeed {
+    pub fn new(tx: UserUpdates) -> Self {
+        Self {
+            sequences: HashMap::new(),
+            pending_fills: Vec::new(),
+            touched_users: HashSet::new(),
+            published_balances: HashMap::new(),
+            tx,
+        }
+    }
+
+    pub fn sequence(&self, user_id: &Uuid) -> u64 {
+        self.sequences.get(user_id).copied().unwrap_or(0)
+    }
+
+    pub fn record_fills(&mut self, trade: &Trade, trade_id: u64) {
+        let maker_side = match trade.taker_side {
+            Side::Bid => Side::Ask,
+            Side::Ask => Side::Bid,
+        };
+        let fills = [
+            (trade.maker_user_id, trade.maker_order_id, maker_side, Liquidity::Maker),
+            (trade.taker_user_id, trade.taker_order_id, trade.taker_side, Liquidity::Taker),
+        ];
+        for (user_id, order_id, side, liquidity) in fills {
+            self.pending_fills.push((user_id, Fill {
+                order_id,
+                trade_id,
+                side,
+                price: trade.price,
+                quantity: trade.quantity,
+                liquidity,
+            }));
+        }
+    }
+
+    /// Marks a user whose balance may have changed outside of an order, e.g. a deposit.
+    pub fn touch(&mut self, user_id: Uuid) {
+        self.touched_users.insert(user_id);
+    }
+
+    fn publish(&mut self, user_id: Uuid, event: UserEvent, now: u64) {
+        let sequence = self.sequences.entry(user_id).or_insert(0);
+        *sequence += 1;
+        self.tx.send(UserUpdate {
+            user_id,
+            sequence: *sequence,
+            timestamp: now,
+            event,
+        });
+    }
+}
+
+impl Engine {
+    /// Publishes what the command just handled did to each user, and copies the fills and
+    /// order changes to the drop copy.
+    pub(super) fn flush_user_feed(&mut self) {
+        let now = now_millis();
+
+        for (user_id, fill) in std::mem::take(&mut self.user_feed.pending_fills) {
+            self.user_feed.touched_users.insert(user_id);
+            self.drop_copy.publish(user_id, UserEvent::Fill(fill.clone()), now);
+            self.user_feed.publish(user_id, UserEvent::Fill(fill), now);
+        }
+        for record in self.order_records.take_changed() {
+            self.user_feed.touched_users.insert(record.user_id);
+            self.drop_copy.publish(record.user_id, UserEvent::Order(record.clone()), now);
+            self.user_feed.publish(record.user_id, UserEvent::Order(record), now);
+        }
+
+        for user_id in std::mem::take(&mut self.user_feed.touched_users) {
+            let current = match self.balances.users.get(&user_id) {
+                Some(b) => b,
+                None => continue,
+            };
+            let previous = self.user_feed.published_balances.remove(&user_id);
+
+            let mut assets: Vec<_> = current.assets.iter().collect();
+            assets.sort_by_key(|(asset, _)| asset.as_str());
+            for (asset, balance) in assets {
+                let unchanged = previous
+                    .as_ref()
+                    .and_then(|p| p.assets.get(asset))
+                    .is_some_and(|b| b.available == balance.available && b.locked == balance.locked);
+                if !unchanged {
+                    self.user_feed.publish(user_id, UserEvent::Balance {
+                        asset: asset.clone(),
+                        available: balance.available,
+                        locked: balance.locked,
+                    }, now);
+                }
+            }
+            self.user_feed.published_balances.insert(user_id, current.clone());
+        }
+    }
+
+    pub(super) fn user_snapshot(&self, user_id: Uuid) -> Option<UserSnapshot> {
+        Some(UserSnapshot {
+            sequence: self.user_feed.sequence(&user_id),
+            balances: self.balances.users.get(&user_id)?.clone(),
+            open_orders: self.order_records.open_orders(user_id),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(user_id: Uuid, sequence: u64) -> UserUpdate {
+        UserUpdate {
+            user_id,
+            sequence,
+            timestamp: 0,
+            event: UserEvent::Balance { asset: "USDC".into(), available: 0, locked: 0 },
+        }
+    }
+
+    #[test]
+    fn updates_only_reach_their_own_users_subscribers() {
+        let updates = UserUpdates::new(2);
+        let (busy, idle) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut busy_rx = updates.subscribe(busy);
+        let mut idle_rx = updates.subscribe(idle);
+
+        // more than the capacity for the busy user doesn't touch the idle one
+        for sequence in 1..=5 {
+            updates.send(update(busy, sequence));
+        }
+        updates.send(update(idle, 1));
+        assert!(matches!(busy_rx.try_recv(), Err(broadcast::error::TryRecvError::Lagged(3))));
+        assert_eq!(idle_rx.try_recv().unwrap().sequence, 1);
+        assert!(idle_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn channels_without_subscribers_are_dropped() {
+        let updates = UserUpdates::new(2);
+        let user_id = Uuid::new_v4();
+        // nobody listening yet, so there's nothing to keep
+        updates.send(update(user_id, 1));
+        assert!(updates.channels.lock().unwrap().is_empty());
+
+        drop(updates.subscribe(user_id));
+        updates.send(update(user_id, 2));
+        assert!(updates.channels.lock().unwrap().is_empty());
+    }
+}
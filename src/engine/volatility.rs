@@ -0,0 +1,232 @@
+use std::collections::VecDeque;
+use serde::{Serialize, Deserialize};
+
+use super::Engine;
+use super::market::MarketState;
+
+/// Price band and circuit breaker settings for a market. Both are off by default.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct VolatilityControls {
+    /// Width of the band around the last trade price, in basis points either side.
+    /// Limit orders priced outside it are rejected and matching stops at its edge.
+    pub band_bps: Option<u32>,
+    /// Move, in basis points, from the extreme of the recent trade window that trips the breaker.
+    pub breaker_bps: Option<u32>,
+    pub breaker_window_ms: u64,
+    /// State the market is moved into when the breaker trips: `Auction` or `Halted`.
+    pub breaker_action: MarketState,
+}
+
+impl VolatilityControls {
+    pub fn new() -> Self {
+        Self {
+            band_bps: None,
+            breaker_bps: None,
+            breaker_window_ms: 60_000,
+            breaker_action: MarketState::Auction,
+        }
+    }
+
+    /// Lowest and highest prices allowed to trade around `reference`.
+    pub fn band(&self, reference: u64) -> Option<(u64, u64)> {
+        let bps = self.band_bps? as u128;
+        let width = (reference as u128 * bps / 10_000) as u64;
+        Some((reference.saturating_sub(width), reference.saturating_add(width)))
+    }
+
+    pub fn in_band(&self, reference: Option<u64>, price: u64) -> bool {
+        match reference.and_then(|r| self.band(r)) {
+            Some((low, high)) => price >= low && price <= high,
+            None => true,
+        }
+    }
+}
+
+/// Trade prices printed within the breaker window, oldest first.
+#[derive(Debug, Clone)]
+pub struct PriceWindow {
+    prices: VecDeque<(u64, u64)>,
+}
+
+impl PriceWindow {
+    pub fn new() -> Self {
+        Self {
+            prices: VecDeque::new(),
+        }
+    }
+
+    pub fn record(&mut self, now: u64, price: u64) {
+        self.prices.push_back((now, price));
+    }
+
+    pub fn prune(&mut self, now: u64, window_ms: u64) {
+        while let Some((at, _)) = self.prices.front() {
+            if now.saturating_sub(*at) <= window_ms {
+                break;
+            }
+            self.prices.pop_front();
+        }
+    }
+
+    /// Whether trading at `price` would move more than `bps` away from the lowest or
+    /// highest price in the window.
+    pub fn breaches(&self, price: u64, bps: u32) -> bool {
+        let low = self.prices.iter().map(|(_, p)| *p).min();
+        let high = self.prices.iter().map(|(_, p)| *p).max();
+        let (low, high) = match (low, high) {
+            (Some(l), Some(h)) => (l as u128, h as u128),
+            _ => return false,
+        };
+
+        let price = price as u128;
+        let bps = bps as u128;
+        price * 10_000 > low * (10_000 + bps) || price * 10_000 < high * (10_000 - bps.min(10_000))
+    }
+}
+
+impl Engine {
+    /// Checks a price the matcher is about to trade at. Outside the band the sweep just stops;
+    /// a move big enough to trip the breaker also switches the market to the breaker action.
+    pub(super) fn may_trade_at(&mut self, price: u64, now: u64) -> bool {
+        let controls = self.market.volatility;
+        if !controls.in_band(self.last_trade_price, price) {
+            return false;
+        }
+
+        if let Some(bps) = controls.breaker_bps {
+            self.recent_prices.prune(now, controls.breaker_window_ms);
+            if self.recent_prices.breaches(price, bps) {
+                println!("{} circuit breaker tripped at {}", self.market.symbol, price);
+                // neither breaker action uncrosses or reprices, so this is safe mid-sweep
+                self.set_market_state(controls.breaker_action);
+                return false;
+            }
+        }
+        true
+    }
+
+    pub(super) fn record_trade_price(&mut self, price: u64, now: u64) {
+        self.recent_prices.prune(now, self.market.volatility.breaker_window_ms);
+        self.recent_prices.record(now, price);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::engine::now_millis;
+    use crate::engine::orderbook::Side;
+    use crate::engine::records::OrderStatus;
+    use crate::engine::testing::{BTC, USDC, add_user, balance, engine, place, resting};
+
+    fn controls(band_bps: Option<u32>, breaker_bps: Option<u32>) -> VolatilityControls {
+        VolatilityControls { band_bps, breaker_bps, ..VolatilityControls::new() }
+    }
+
+    #[test]
+    fn band_is_symmetric_around_the_reference() {
+        let controls = controls(Some(500), None);
+        assert_eq!(controls.band(100 * USDC), Some((95 * USDC, 105 * USDC)));
+        assert!(controls.in_band(Some(100 * USDC), 105 * USDC));
+        assert!(!controls.in_band(Some(100 * USDC), 105 * USDC + 1));
+        assert!(!controls.in_band(Some(100 * USDC), 95 * USDC - 1));
+        // no band without a reference or a width
+        assert!(controls.in_band(None, 1));
+        assert!(VolatilityControls::new().in_band(Some(100 * USDC), 1));
+        assert_eq!(controls.band(u64::MAX), Some((u64::MAX - u64::MAX / 20, u64::MAX)));
+    }
+
+    #[test]
+    fn breaches_measure_from_the_window_extremes() {
+        let mut window = PriceWindow::new();
+        assert!(!window.breaches(1_000, 100));
+        window.record(0, 100);
+        window.record(10, 110);
+
+        // 10% from the low of 100 or the high of 110
+        assert!(!window.breaches(110, 1_000));
+        assert!(window.breaches(111, 1_000));
+        assert!(!window.breaches(99, 1_000));
+        assert!(window.breaches(98, 1_000));
+
+        // only 110 is left in the window
+        window.prune(1_010, 1_000);
+        assert!(!window.breaches(121, 1_000));
+        assert!(window.breaches(122, 1_000));
+        assert!(!window.breaches(99, 1_000));
+    }
+
+    /// An engine with a trade printed at 100 USDC, and a user with funds to trade.
+    fn engine_at_100() -> (Engine, Uuid) {
+        let mut engine = engine();
+        let trader = add_user(&mut engine, 10 * BTC, 10_000 * USDC);
+        place(&mut engine, trader, Side::Ask, 100 * USDC, BTC / 10);
+        place(&mut engine, trader, Side::Bid, 100 * USDC, BTC / 10);
+        assert_eq!(engine.last_trade_price, Some(100 * USDC));
+        (engine, trader)
+    }
+
+    #[test]
+    fn orders_priced_outside_the_band_are_rejected() {
+        let (mut engine, trader) = engine_at_100();
+        engine.market.volatility = controls(Some(500), None);
+        assert_eq!(
+            engine.create_order(Uuid::new_v4(), trader, Side::Bid, 106 * USDC, BTC),
+            Err("price is outside the allowed price band".to_string())
+        );
+        place(&mut engine, trader, Side::Bid, 105 * USDC, BTC);
+    }
+
+    #[test]
+    fn the_remainder_of_an_order_stopped_at_the_band_expires() {
+        let (mut engine, _) = engine_at_100();
+        // rested before the band was set; it is now below it
+        let seller = add_user(&mut engine, BTC, 0);
+        place(&mut engine, seller, Side::Ask, 90 * USDC, BTC / 10);
+        engine.market.volatility = controls(Some(500), None);
+
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let bid = place(&mut engine, buyer, Side::Bid, 100 * USDC, BTC / 10);
+        assert_eq!(engine.order_records.get(&bid).unwrap().status, OrderStatus::Expired);
+        assert_eq!(balance(&engine, buyer, "USDC"), (1_000 * USDC, 0));
+        assert_eq!(resting(&engine, Side::Ask), vec![(90 * USDC, BTC / 10)]);
+        assert!(resting(&engine, Side::Bid).is_empty());
+    }
+
+    #[test]
+    fn the_breaker_trips_mid_sweep_into_the_breaker_action() {
+        let (mut engine, trader) = engine_at_100();
+        place(&mut engine, trader, Side::Ask, 101 * USDC, BTC / 10);
+        place(&mut engine, trader, Side::Ask, 110 * USDC, BTC / 10);
+        engine.market.volatility = controls(None, Some(500));
+
+        let buyer = add_user(&mut engine, 0, 1_000 * USDC);
+        let bid = place(&mut engine, buyer, Side::Bid, 111 * USDC, BTC / 5);
+
+        // 101 traded; 110 is more than 5% above the window's low of 100
+        assert_eq!(engine.market.state, MarketState::Auction);
+        assert_eq!(engine.last_trade_price, Some(101 * USDC));
+        assert_eq!(engine.order_records.get(&bid).unwrap().remaining_quantity, BTC / 10);
+        assert_eq!(resting(&engine, Side::Bid), vec![(111 * USDC, BTC / 10)]);
+
+        engine.set_market_state(MarketState::Continuous);
+        assert_eq!(engine.last_trade_price, Some(110 * USDC));
+        assert_eq!(engine.order_records.get(&bid).unwrap().status, OrderStatus::Filled);
+        assert!(!engine.orderbook.is_crossed());
+    }
+
+    #[test]
+    fn the_breaker_can_halt_the_market() {
+        let (mut engine, trader) = engine_at_100();
+        place(&mut engine, trader, Side::Bid, 90 * USDC, BTC / 10);
+        engine.market.volatility = VolatilityControls { breaker_action: MarketState::Halted, ..controls(None, Some(500)) };
+
+        let seller = add_user(&mut engine, BTC, 0);
+        place(&mut engine, seller, Side::Ask, 90 * USDC, BTC / 10);
+        assert_eq!(engine.market.state, MarketState::Halted);
+        assert_eq!(engine.last_trade_price, Some(100 * USDC));
+        assert!(!engine.may_trade_at(90 * USDC, now_millis()));
+    }
+}
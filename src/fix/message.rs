@@ -0,0 +1,427 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const BEGIN_STRING: &str = "FIX.4.4";
+const SOH: u8 = 0x01;
+/// Largest body accepted; anything bigger is treated as garbage.
+const MAX_BODY_LEN: usize = 64 * 1024;
+
+pub mod tags {
+    pub const AVG_PX: u32 = 6;
+    pub const BEGIN_SEQ_NO: u32 = 7;
+    pub const BEGIN_STRING: u32 = 8;
+    pub const BODY_LENGTH: u32 = 9;
+    pub const CHECK_SUM: u32 = 10;
+    pub const CL_ORD_ID: u32 = 11;
+    pub const CUM_QTY: u32 = 14;
+    pub const END_SEQ_NO: u32 = 16;
+    pub const EXEC_ID: u32 = 17;
+    pub const LAST_PX: u32 = 31;
+    pub const LAST_QTY: u32 = 32;
+    pub const MSG_SEQ_NUM: u32 = 34;
+    pub const MSG_TYPE: u32 = 35;
+    pub const NEW_SEQ_NO: u32 = 36;
+    pub const ORDER_ID: u32 = 37;
+    pub const ORDER_QTY: u32 = 38;
+    pub const ORD_STATUS: u32 = 39;
+    pub const ORD_TYPE: u32 = 40;
+    pub const ORIG_CL_ORD_ID: u32 = 41;
+    pub const POSS_DUP_FLAG: u32 = 43;
+    pub const PRICE: u32 = 44;
+    pub const REF_SEQ_NUM: u32 = 45;
+    pub const SENDER_COMP_ID: u32 = 49;
+    pub const SENDING_TIME: u32 = 52;
+    pub const SIDE: u32 = 54;
+    pub const SYMBOL: u32 = 55;
+    pub const TARGET_COMP_ID: u32 = 56;
+    pub const TEXT: u32 = 58;
+    pub const TIME_IN_FORCE: u32 = 59;
+    pub const TRANSACT_TIME: u32 = 60;
+    pub const RAW_DATA: u32 = 96;
+    pub const ENCRYPT_METHOD: u32 = 98;
+    pub const CXL_REJ_REASON: u32 = 102;
+    pub const ORD_REJ_REASON: u32 = 103;
+    pub const HEART_BT_INT: u32 = 108;
+    pub const TEST_REQ_ID: u32 = 112;
+    pub const ORIG_SENDING_TIME: u32 = 122;
+    pub const GAP_FILL_FLAG: u32 = 123;
+    pub const RESET_SEQ_NUM_FLAG: u32 = 141;
+    pub const EXEC_TYPE: u32 = 150;
+    pub const LEAVES_QTY: u32 = 151;
+    pub const REF_TAG_ID: u32 = 371;
+    pub const REF_MSG_TYPE: u32 = 372;
+    pub const SESSION_REJECT_REASON: u32 = 373;
+    pub const BUSINESS_REJECT_REASON: u32 = 380;
+    pub const CXL_REJ_RESPONSE_TO: u32 = 434;
+    pub const USERNAME: u32 = 553;
+    pub const PASSWORD: u32 = 554;
+    pub const TRD_MATCH_ID: u32 = 880;
+}
+
+pub mod msg_type {
+    pub const HEARTBEAT: &str = "0";
+    pub const TEST_REQUEST: &str = "1";
+    pub const RESEND_REQUEST: &str = "2";
+    pub const REJECT: &str = "3";
+    pub const SEQUENCE_RESET: &str = "4";
+    pub const LOGOUT: &str = "5";
+    pub const EXECUTION_REPORT: &str = "8";
+    pub const ORDER_CANCEL_REJECT: &str = "9";
+    pub const LOGON: &str = "A";
+    pub const NEW_ORDER_SINGLE: &str = "D";
+    pub const ORDER_CANCEL_REQUEST: &str = "F";
+    pub const ORDER_CANCEL_REPLACE_REQUEST: &str = "G";
+    pub const BUSINESS_MESSAGE_REJECT: &str = "j";
+
+    /// Session-level messages, which are gap-filled rather than resent.
+    pub fn is_admin(msg_type: &str) -> bool {
+        matches!(msg_type, HEARTBEAT | TEST_REQUEST | RESEND_REQUEST | REJECT | SEQUENCE_RESET | LOGOUT | LOGON)
+    }
+}
+
+/// A received message, fields in wire order.
+#[derive(Debug, Clone)]
+pub struct Message {
+    fields: Vec<(u32, String)>,
+}
+
+impl Message {
+    pub fn get(&self, tag: u32) -> Option<&str> {
+        self.fields.iter().find(|(t, _)| *t == tag).map(|(_, v)| v.as_str())
+    }
+
+    pub fn msg_type(&self) -> &str {
+        self.get(tags::MSG_TYPE).unwrap_or("")
+    }
+
+    pub fn seq_num(&self) -> Option<u64> {
+        self.get(tags::MSG_SEQ_NUM)?.parse().ok()
+    }
+
+    pub fn flag(&self, tag: u32) -> bool {
+        self.get(tag) == Some("Y")
+    }
+}
+
+/// Takes the next complete message off the front of `buf`. `None` means more bytes are
+/// needed; `Some(Err(..))` is a message that failed its length or checksum check, which
+/// has been dropped (such messages are ignored, not rejected, per the spec).
+pub fn take_message(buf: &mut Vec<u8>) -> Option<Result<Message, String>> {
+    const START: &[u8] = b"8=FIX";
+
+    // skip anything before the next BeginString
+    match buf.windows(START.len()).position(|w| w == START) {
+        Some(0) => {}
+        Some(at) => {
+            buf.drain(..at);
+        }
+        None => {
+            let keep = buf.len().min(START.len() - 1);
+            buf.drain(..buf.len() - keep);
+            return None;
+        }
+    }
+
+    let begin_end = buf.iter().position(|b| *b == SOH)?;
+    let length_end = begin_end + 1 + buf[begin_end + 1..].iter().position(|b| *b == SOH)?;
+    let body_len = std::str::from_utf8(&buf[begin_end + 1..length_end])
+        .ok()
+        .and_then(|f| f.strip_prefix("9="))
+        .and_then(|len| len.parse::<usize>().ok())
+        .filter(|len| *len <= MAX_BODY_LEN);
+    let Some(body_len) = body_len else {
+        buf.drain(..length_end + 1);
+        return Some(Err("invalid BodyLength".into()));
+    };
+
+    let checksum_start = length_end + 1 + body_len;
+    let total = checksum_start + "10=000\x01".len();
+    if buf.len() < total {
+        return None;
+    }
+    let frame: Vec<u8> = buf.drain(..total).collect();
+
+    let expected = checksum(&frame[..checksum_start]);
+    let trailer = &frame[checksum_start..];
+    if !trailer.starts_with(b"10=") || trailer[trailer.len() - 1] != SOH {
+        return Some(Err("BodyLength doesn't match the message".into()));
+    }
+    if trailer[3..6] != *format!("{expected:03}").as_bytes() {
+        return Some(Err("invalid CheckSum".into()));
+    }
+
+    let text = match std::str::from_utf8(&frame[..checksum_start]) {
+        Ok(t) => t,
+        Err(_) => return Some(Err("message is not valid UTF-8".into())),
+    };
+    let mut fields = Vec::new();
+    for field in text.split('\x01').filter(|f| !f.is_empty()) {
+        match field.split_once('=').and_then(|(tag, value)| Some((tag.parse().ok()?, value))) {
+            Some((tag, value)) => fields.push((tag, value.to_string())),
+            None => return Some(Err(format!("malformed field {field:?}"))),
+        }
+    }
+    Some(Ok(Message { fields }))
+}
+
+/// A message to send, without the standard header and trailer.
+#[derive(Debug, Clone)]
+pub struct OutMessage {
+    pub msg_type: &'static str,
+    fields: Vec<(u32, String)>,
+}
+
+impl OutMessage {
+    pub fn new(msg_type: &'static str) -> Self {
+        Self {
+            msg_type,
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn field(mut self, tag: u32, value: impl ToString) -> Self {
+        self.fields.push((tag, value.to_string()));
+        self
+    }
+
+    pub fn encode(&self, header: &Header) -> Vec<u8> {
+        let mut body = String::new();
+        let mut push = |tag: u32, value: &str| {
+            let _ = write!(body, "{tag}={value}\x01");
+        };
+        push(tags::MSG_TYPE, self.msg_type);
+        push(tags::SENDER_COMP_ID, header.sender_comp_id);
+        push(tags::TARGET_COMP_ID, header.target_comp_id);
+        push(tags::MSG_SEQ_NUM, &header.seq_num.to_string());
+        push(tags::SENDING_TIME, header.sending_time);
+        if let Some(orig_sending_time) = header.orig_sending_time {
+            push(tags::POSS_DUP_FLAG, "Y");
+            push(tags::ORIG_SENDING_TIME, orig_sending_time);
+        }
+        for (tag, value) in &self.fields {
+            push(*tag, value);
+        }
+
+        let mut out = format!("{}={BEGIN_STRING}\x01{}={}\x01{body}", tags::BEGIN_STRING, tags::BODY_LENGTH, body.len()).into_bytes();
+        let sum = checksum(&out);
+        out.extend_from_slice(format!("{}={sum:03}\x01", tags::CHECK_SUM).as_bytes());
+        out
+    }
+}
+
+pub struct Header<'a> {
+    pub sender_comp_id: &'a str,
+    pub target_comp_id: &'a str,
+    pub seq_num: u64,
+    pub sending_time: &'a str,
+    /// Set when resending: marks the message PossDup with the time it was first sent.
+    pub orig_sending_time: Option<&'a str>,
+}
+
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().map(|b| *b as u32).sum::<u32>() % 256
+}
+
+/// Formats epoch milliseconds as a FIX UTCTimestamp, `YYYYMMDD-HH:MM:SS.sss`.
+pub fn utc_timestamp(millis: u64) -> String {
+    let days = (millis / 86_400_000) as i64;
+    let ms_of_day = millis % 86_400_000;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}:{:02}:{:02}.{:03}",
+        ms_of_day / 3_600_000,
+        ms_of_day / 60_000 % 60,
+        ms_of_day / 1000 % 60,
+        ms_of_day % 1000,
+    )
+}
+
+/// The current time as a FIX UTCTimestamp.
+pub fn now() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    utc_timestamp(millis)
+}
+
+/// Parses a FIX UTCTimestamp, with or without milliseconds, into epoch milliseconds.
+pub fn parse_utc_timestamp(s: &str) -> Option<u64> {
+    let (date, time) = s.split_once('-')?;
+    if date.len() != 8 || !date.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let year: i64 = date[..4].parse().ok()?;
+    let month: u32 = date[4..6].parse().ok()?;
+    let day: u32 = date[6..].parse().ok()?;
+
+    let (hms, millis) = match time.split_once('.') {
+        Some((hms, frac)) if frac.len() == 3 => (hms, frac.parse::<u64>().ok()?),
+        Some(_) => return None,
+        None => (time, 0),
+    };
+    let mut parts = hms.split(':').map(|p| p.parse::<u64>().ok());
+    let (hour, minute, second) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+
+    let days = u64::try_from(days_from_civil(year, month, day)).ok()?;
+    Some(days * 86_400_000 + hour * 3_600_000 + minute * 60_000 + second * 1000 + millis)
+}
+
+// Howard Hinnant's civil calendar conversions.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(seq_num: u64) -> Header<'static> {
+        Header {
+            sender_comp_id: "EXCHANGE",
+            target_comp_id: "CLIENT",
+            seq_num,
+            sending_time: "20240229-12:34:56.789",
+            orig_sending_time: None,
+        }
+    }
+
+    fn heartbeat() -> Vec<u8> {
+        OutMessage::new(msg_type::HEARTBEAT).field(tags::TEST_REQ_ID, "ping").encode(&header(7))
+    }
+
+    #[test]
+    fn encode_writes_body_length_and_checksum() {
+        let text = String::from_utf8(heartbeat()).unwrap();
+        let body = "35=0\x0149=EXCHANGE\x0156=CLIENT\x0134=7\x0152=20240229-12:34:56.789\x01112=ping\x01";
+        assert_eq!(text, format!("8=FIX.4.4\x019=66\x01{body}10=114\x01"));
+    }
+
+    #[test]
+    fn encode_marks_resends_as_poss_dup() {
+        let header = Header { orig_sending_time: Some("20240229-12:00:00.000"), ..header(3) };
+        let mut buf = OutMessage::new(msg_type::HEARTBEAT).encode(&header);
+        let message = take_message(&mut buf).unwrap().unwrap();
+        assert!(message.flag(tags::POSS_DUP_FLAG));
+        assert_eq!(message.get(tags::ORIG_SENDING_TIME), Some("20240229-12:00:00.000"));
+    }
+
+    #[test]
+    fn take_message_reads_what_encode_wrote() {
+        let mut buf = heartbeat();
+        let message = take_message(&mut buf).unwrap().unwrap();
+        assert_eq!(message.msg_type(), msg_type::HEARTBEAT);
+        assert_eq!(message.seq_num(), Some(7));
+        assert_eq!(message.get(tags::TEST_REQ_ID), Some("ping"));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn take_message_waits_for_the_whole_message() {
+        let full = heartbeat();
+        let mut buf = full[..full.len() - 1].to_vec();
+        assert!(take_message(&mut buf).is_none());
+        assert_eq!(buf.len(), full.len() - 1);
+
+        buf.push(full[full.len() - 1]);
+        assert!(take_message(&mut buf).unwrap().is_ok());
+    }
+
+    #[test]
+    fn take_message_skips_leading_garbage_and_leaves_the_next_message() {
+        let mut buf = b"junk".to_vec();
+        buf.extend(heartbeat());
+        buf.extend(heartbeat());
+        assert!(take_message(&mut buf).unwrap().is_ok());
+        assert_eq!(buf, heartbeat());
+    }
+
+    #[test]
+    fn take_message_keeps_a_possible_start_of_message() {
+        let mut buf = b"garbage8=FI".to_vec();
+        assert!(take_message(&mut buf).is_none());
+        assert_eq!(buf, b"8=FI");
+    }
+
+    #[test]
+    fn take_message_drops_a_bad_checksum() {
+        let mut buf = heartbeat();
+        let len = buf.len();
+        buf[len - 2] = if buf[len - 2] == b'9' { b'0' } else { buf[len - 2] + 1 };
+        buf.extend(heartbeat());
+        assert_eq!(take_message(&mut buf).unwrap().unwrap_err(), "invalid CheckSum");
+        assert!(take_message(&mut buf).unwrap().is_ok());
+    }
+
+    #[test]
+    fn take_message_drops_a_wrong_body_length() {
+        let text = String::from_utf8(heartbeat()).unwrap();
+        let (len, _) = text["8=FIX.4.4\x019=".len()..].split_once('\x01').unwrap();
+        let longer = (len.parse::<usize>().unwrap() + 1).to_string();
+        let mut buf = text.replacen(&format!("9={len}\x01"), &format!("9={longer}\x01"), 1).into_bytes();
+        buf.push(b'x');
+        assert_eq!(take_message(&mut buf).unwrap().unwrap_err(), "BodyLength doesn't match the message");
+    }
+
+    #[test]
+    fn take_message_rejects_an_oversized_body_length() {
+        let mut buf = format!("8=FIX.4.4\x019={}\x0135=0\x01", MAX_BODY_LEN + 1).into_bytes();
+        assert_eq!(take_message(&mut buf).unwrap().unwrap_err(), "invalid BodyLength");
+        assert_eq!(buf, b"35=0\x01");
+    }
+
+    #[test]
+    fn parse_utc_timestamp_converts_civil_dates() {
+        assert_eq!(parse_utc_timestamp("19700101-00:00:00"), Some(0));
+        assert_eq!(parse_utc_timestamp("20000301-00:00:00.000"), Some(951_868_800_000));
+        assert_eq!(parse_utc_timestamp("20240229-12:34:56.789"), Some(1_709_210_096_789));
+        assert_eq!(parse_utc_timestamp("20231231-23:59:60"), Some(1_704_067_200_000));
+    }
+
+    #[test]
+    fn parse_utc_timestamp_round_trips_utc_timestamp() {
+        for millis in [0, 951_782_400_000, 1_709_210_096_789, 4_102_444_799_999] {
+            assert_eq!(parse_utc_timestamp(&utc_timestamp(millis)), Some(millis));
+        }
+    }
+
+    #[test]
+    fn parse_utc_timestamp_rejects_malformed_input() {
+        for s in [
+            "20240229",
+            "2024022-12:00:00",
+            "20241301-12:00:00",
+            "20240100-12:00:00",
+            "20240229-24:00:00",
+            "20240229-12:60:00",
+            "20240229-12:00",
+            "20240229-12:00:00:00",
+            "20240229-12:00:00.5",
+            "19691231-23:59:59",
+        ] {
+            assert_eq!(parse_utc_timestamp(s), None, "{s}");
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use actix_web::web;
+use std::io;
+use tokio::net::TcpListener;
+
+use crate::auth::ApiKeys;
+use crate::engine::queue::CommandSender;
+use crate::engine::user_feed::UserUpdates;
+
+pub mod message;
+mod session;
+
+/// CompID the acceptor uses when `FIX_COMP_ID` isn't set.
+pub const DEFAULT_COMP_ID: &str = "EXCHANGE";
+
+/// What every FIX session needs from the rest of the server.
+#[derive(Clone)]
+pub struct Gateway {
+    /// Our CompID: clients send it as TargetCompID and get it back as SenderCompID.
+    pub comp_id: String,
+    pub tx: CommandSender,
+    pub user_updates: UserUpdates,
+    pub api_keys: web::Data<ApiKeys>,
+}
+
+/// FIX 4.4 order entry acceptor.
+///
+/// Clients log on with an API key that has the `trade` scope (see `Session::authenticate`)
+/// and can then send NewOrderSingle, OrderCancelRequest and OrderCancelReplaceRequest for
+/// limit orders on the one market. They get ExecutionReports for acks, fills, cancels and
+/// rejects of the orders entered on that connection, and OrderCancelReject when a cancel or
+/// replace fails. Sequence numbers start at 1 on every logon.
+pub async fn serve(listener: std::net::TcpListener, gateway: Gateway) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let _ = stream.set_nodelay(true);
+        actix_web::rt::spawn(session::run(stream, gateway.clone()));
+    }
+}
@@ -0,0 +1,774 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use super::Gateway;
+use super::message::{self, Header, Message, OutMessage, msg_type, tags};
+use crate::auth::Scope;
+use crate::engine::EngineCommand;
+use crate::engine::client_orders::OrderKey;
+use crate::engine::market::MARKET_SYMBOL;
+use crate::engine::orderbook::Side;
+use crate::engine::records::OrderStatus;
+use crate::engine::user_feed::{Fill, UserEvent, UserUpdate};
+use crate::math;
+
+/// How long a new connection has to send its Logon.
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+/// Sent application messages kept for resend requests.
+const MAX_STORED_MESSAGES: usize = 10_000;
+const MAX_HEARTBEAT_SECS: u64 = 300;
+const MAX_CL_ORD_ID_LEN: usize = 64;
+
+/// What the caller should do with the connection after a message.
+#[derive(Debug, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Close,
+}
+
+/// An order entered on this session, tracked so execution reports carry running totals.
+#[derive(Debug, Clone)]
+struct FixOrder {
+    order_id: Uuid,
+    cl_ord_id: String,
+    side: Side,
+    price: u64,
+    quantity: u64,
+    cum_qty: u64,
+    /// Sum of price × quantity over fills, for the average price.
+    notional: u128,
+}
+
+impl FixOrder {
+    fn leaves_qty(&self) -> u64 {
+        self.quantity.saturating_sub(self.cum_qty)
+    }
+
+    fn status(&self) -> &'static str {
+        match self.cum_qty {
+            0 => "0",
+            cum if cum < self.quantity => "1",
+            _ => "2",
+        }
+    }
+}
+
+/// A validated limit order from a NewOrderSingle or OrderCancelReplaceRequest.
+struct LimitOrder {
+    side: Side,
+    price: u64,
+    quantity: u64,
+}
+
+/// Runs one connection: waits for a Logon, then handles messages until either side logs
+/// out or the connection drops.
+pub async fn run(stream: TcpStream, gateway: Gateway) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let (mut reader, writer) = stream.into_split();
+    let mut buf = Vec::new();
+
+    let logon = match tokio::time::timeout(LOGON_TIMEOUT, read_message(&mut reader, &mut buf)).await {
+        Ok(Ok(Some(msg))) => msg,
+        _ => return,
+    };
+    let Some((mut session, updates)) = Session::logon(writer, gateway, &logon).await else {
+        println!("FIX logon from {peer} refused");
+        return;
+    };
+    println!("FIX session {} logged on from {peer}", session.target_comp_id);
+
+    if let Err(e) = session.run(reader, buf, updates).await {
+        println!("FIX session {} ended: {e}", session.target_comp_id);
+    }
+}
+
+async fn read_message(reader: &mut OwnedReadHalf, buf: &mut Vec<u8>) -> io::Result<Option<Message>> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        while let Some(msg) = message::take_message(buf) {
+            match msg {
+                Ok(msg) => return Ok(Some(msg)),
+                Err(e) => println!("dropping garbled FIX message: {e}"),
+            }
+        }
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+struct Session {
+    writer: OwnedWriteHalf,
+    gateway: Gateway,
+    /// The client's CompID, used as TargetCompID on everything sent.
+    target_comp_id: String,
+    user_id: Uuid,
+    heartbeat: Duration,
+    next_out: u64,
+    next_in: u64,
+    /// Application messages by sequence number, with their original SendingTime.
+    sent: BTreeMap<u64, (OutMessage, String)>,
+    last_sent: Instant,
+    last_received: Instant,
+    test_request: Option<(String, Instant)>,
+    /// Highest sequence number seen while a resend request is outstanding.
+    resend_until: Option<u64>,
+    orders: HashMap<Uuid, FixOrder>,
+    cl_ord_ids: HashMap<String, Uuid>,
+}
+
+impl Session {
+    /// Checks the Logon and its signature and answers it. Sequence numbers start over at 1
+    /// on every connection.
+    async fn logon(writer: OwnedWriteHalf, gateway: Gateway, msg: &Message) -> Option<(Self, broadcast::Receiver<UserUpdate>)> {
+        if msg.msg_type() != msg_type::LOGON {
+            return None;
+        }
+        let target_comp_id = msg.get(tags::SENDER_COMP_ID)?.to_string();
+        let now = Instant::now();
+        let mut session = Session {
+            writer,
+            target_comp_id,
+            user_id: Uuid::nil(),
+            heartbeat: Duration::from_secs(30),
+            next_out: 1,
+            next_in: 1,
+            sent: BTreeMap::new(),
+            last_sent: now,
+            last_received: now,
+            test_request: None,
+            resend_until: None,
+            orders: HashMap::new(),
+            cl_ord_ids: HashMap::new(),
+            gateway,
+        };
+
+        match session.authenticate(msg) {
+            Ok((user_id, heartbeat)) => {
+                session.user_id = user_id;
+                session.heartbeat = heartbeat;
+            }
+            Err(e) => {
+                let _ = session.send_logout(Some(e)).await;
+                return None;
+            }
+        }
+        let updates = session.gateway.user_updates.subscribe(session.user_id);
+
+        let mut reply = OutMessage::new(msg_type::LOGON)
+            .field(tags::ENCRYPT_METHOD, 0)
+            .field(tags::HEART_BT_INT, session.heartbeat.as_secs());
+        if msg.flag(tags::RESET_SEQ_NUM_FLAG) {
+            reply = reply.field(tags::RESET_SEQ_NUM_FLAG, "Y");
+        }
+        session.send(reply).await.ok()?;
+
+        // the Logon counts as an ordinary message for sequencing
+        let seq = msg.seq_num()?;
+        if seq > session.next_in {
+            session.request_resend(seq).await.ok()?;
+        } else {
+            session.next_in = seq + 1;
+        }
+        Some((session, updates))
+    }
+
+    /// The key's user and the agreed heartbeat interval. `Username` (553) is the API key,
+    /// `RawData` (96) a nonce, and `Password` (554) the hex HMAC-SHA256 of
+    /// `<SendingTime as epoch milliseconds> + nonce + "FIX/logon"` under the key's secret.
+    fn authenticate(&self, msg: &Message) -> Result<(Uuid, Duration), String> {
+        if msg.get(tags::TARGET_COMP_ID) != Some(self.gateway.comp_id.as_str()) {
+            return Err(format!("TargetCompID must be {}", self.gateway.comp_id));
+        }
+        if msg.get(tags::ENCRYPT_METHOD) != Some("0") {
+            return Err("EncryptMethod must be 0".into());
+        }
+        let heartbeat = msg
+            .get(tags::HEART_BT_INT)
+            .and_then(|h| h.parse::<u64>().ok())
+            .filter(|h| (1..=MAX_HEARTBEAT_SECS).contains(h))
+            .ok_or(format!("HeartBtInt must be 1 to {MAX_HEARTBEAT_SECS}"))?;
+
+        let (Some(api_key), Some(nonce), Some(signature)) = (msg.get(tags::USERNAME), msg.get(tags::RAW_DATA), msg.get(tags::PASSWORD)) else {
+            return Err("Username, RawData and Password are required".into());
+        };
+        let sending_time = msg
+            .get(tags::SENDING_TIME)
+            .and_then(message::parse_utc_timestamp)
+            .ok_or("invalid SendingTime")?;
+        let key = self.gateway.api_keys
+            .verify(api_key, &sending_time.to_string(), nonce, signature, b"FIX/logon")
+            .map_err(String::from)?;
+        if !key.scopes.contains(&Scope::Trade) {
+            return Err("api key lacks the trade scope".into());
+        }
+        Ok((key.user_id, Duration::from_secs(heartbeat)))
+    }
+
+    async fn run(&mut self, mut reader: OwnedReadHalf, mut buf: Vec<u8>, mut updates: broadcast::Receiver<UserUpdate>) -> io::Result<()> {
+        let mut chunk = [0u8; 4096];
+        let mut ticker = tokio::time::interval(Duration::from_secs(1));
+
+        loop {
+            while let Some(msg) = message::take_message(&mut buf) {
+                match msg {
+                    Ok(msg) => {
+                        if self.handle(msg).await? == Flow::Close {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => println!("dropping garbled FIX message from {}: {e}", self.target_comp_id),
+                }
+            }
+
+            tokio::select! {
+                // reports already published go out before the next request is looked at
+                biased;
+                update = updates.recv() => match update {
+                    Ok(update) => self.on_user_event(update.event).await?,
+                    Err(RecvError::Lagged(_)) => {
+                        self.send_logout(Some("execution reports fell behind, reconnect".into())).await?;
+                        return Ok(());
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                n = reader.read(&mut chunk) => {
+                    let n = n?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+                _ = ticker.tick() => {
+                    if self.check_heartbeat().await? == Flow::Close {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    async fn handle(&mut self, msg: Message) -> io::Result<Flow> {
+        self.last_received = Instant::now();
+        self.test_request = None;
+
+        let Some(seq) = msg.seq_num() else {
+            self.send_logout(Some("MsgSeqNum missing".into())).await?;
+            return Ok(Flow::Close);
+        };
+        if msg.get(tags::SENDER_COMP_ID) != Some(self.target_comp_id.as_str()) || msg.get(tags::TARGET_COMP_ID) != Some(self.gateway.comp_id.as_str()) {
+            self.send_reject(seq, 9, None, "CompID problem").await?;
+            self.send_logout(Some("CompID problem".into())).await?;
+            return Ok(Flow::Close);
+        }
+
+        // a SequenceReset in reset mode applies whatever its own sequence number
+        if msg.msg_type() == msg_type::SEQUENCE_RESET && !msg.flag(tags::GAP_FILL_FLAG) {
+            return self.on_sequence_reset(&msg, seq).await;
+        }
+
+        if seq > self.next_in {
+            self.request_resend(seq).await?;
+            // a logout or resend request is still answered while the gap is filled
+            return match msg.msg_type() {
+                msg_type::RESEND_REQUEST => self.on_resend_request(&msg, seq).await,
+                msg_type::LOGOUT => self.on_logout().await,
+                _ => Ok(Flow::Continue),
+            };
+        }
+        if seq < self.next_in {
+            if msg.flag(tags::POSS_DUP_FLAG) {
+                return Ok(Flow::Continue);
+            }
+            self.send_logout(Some(format!("MsgSeqNum too low, expecting {} but received {seq}", self.next_in))).await?;
+            return Ok(Flow::Close);
+        }
+
+        self.next_in += 1;
+        if self.resend_until.is_some_and(|until| self.next_in > until) {
+            self.resend_until = None;
+        }
+
+        match msg.msg_type() {
+            msg_type::HEARTBEAT | msg_type::REJECT => Ok(Flow::Continue),
+            msg_type::TEST_REQUEST => {
+                let Some(id) = msg.get(tags::TEST_REQ_ID) else {
+                    self.send_reject(seq, 1, Some(tags::TEST_REQ_ID), "TestReqID missing").await?;
+                    return Ok(Flow::Continue);
+                };
+                self.send(OutMessage::new(msg_type::HEARTBEAT).field(tags::TEST_REQ_ID, id)).await?;
+                Ok(Flow::Continue)
+            }
+            msg_type::RESEND_REQUEST => self.on_resend_request(&msg, seq).await,
+            msg_type::SEQUENCE_RESET => self.on_sequence_reset(&msg, seq).await,
+            msg_type::LOGOUT => self.on_logout().await,
+            msg_type::LOGON => {
+                self.send_reject(seq, 5, Some(tags::MSG_TYPE), "already logged on").await?;
+                Ok(Flow::Continue)
+            }
+            msg_type::NEW_ORDER_SINGLE => self.on_new_order(&msg, seq).await.map(|_| Flow::Continue),
+            msg_type::ORDER_CANCEL_REQUEST => self.on_cancel(&msg, seq).await.map(|_| Flow::Continue),
+            msg_type::ORDER_CANCEL_REPLACE_REQUEST => self.on_replace(&msg, seq).await.map(|_| Flow::Continue),
+            other => {
+                let reject = OutMessage::new(msg_type::BUSINESS_MESSAGE_REJECT)
+                    .field(tags::REF_SEQ_NUM, seq)
+                    .field(tags::REF_MSG_TYPE, other)
+                    .field(tags::BUSINESS_REJECT_REASON, 3)
+                    .field(tags::TEXT, "unsupported message type");
+                self.send(reject).await?;
+                Ok(Flow::Continue)
+            }
+        }
+    }
+
+    // ---- session level ----
+
+    async fn request_resend(&mut self, seen: u64) -> io::Result<()> {
+        if self.resend_until.is_none() {
+            let request = OutMessage::new(msg_type::RESEND_REQUEST)
+                .field(tags::BEGIN_SEQ_NO, self.next_in)
+                .field(tags::END_SEQ_NO, 0);
+            self.send(request).await?;
+        }
+        self.resend_until = Some(self.resend_until.unwrap_or(0).max(seen));
+        Ok(())
+    }
+
+    /// Resends stored application messages in the range as PossDup, and covers the
+    /// session-level ones (and anything no longer stored) with gap fills.
+    async fn on_resend_request(&mut self, msg: &Message, seq: u64) -> io::Result<Flow> {
+        let range = msg.get(tags::BEGIN_SEQ_NO).and_then(|b| b.parse::<u64>().ok())
+            .zip(msg.get(tags::END_SEQ_NO).and_then(|e| e.parse::<u64>().ok()));
+        let Some((begin, end)) = range else {
+            self.send_reject(seq, 1, Some(tags::BEGIN_SEQ_NO), "BeginSeqNo and EndSeqNo are required").await?;
+            return Ok(Flow::Continue);
+        };
+        let last = self.next_out - 1;
+        let end = if end == 0 || end > last { last } else { end };
+
+        let mut gap_start = None;
+        for resend_seq in begin.max(1)..=end {
+            match self.sent.get(&resend_seq).cloned() {
+                Some((msg, orig_sending_time)) => {
+                    if let Some(start) = gap_start.take() {
+                        self.send_gap_fill(start, resend_seq).await?;
+                    }
+                    self.write(&msg, resend_seq, Some(&orig_sending_time)).await?;
+                }
+                None => {
+                    gap_start.get_or_insert(resend_seq);
+                }
+            }
+        }
+        if let Some(start) = gap_start {
+            self.send_gap_fill(start, end + 1).await?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn send_gap_fill(&mut self, seq: u64, new_seq: u64) -> io::Result<()> {
+        let gap_fill = OutMessage::new(msg_type::SEQUENCE_RESET)
+            .field(tags::GAP_FILL_FLAG, "Y")
+            .field(tags::NEW_SEQ_NO, new_seq);
+        let now = message::now();
+        self.write(&gap_fill, seq, Some(&now)).await.map(|_| ())
+    }
+
+    async fn on_sequence_reset(&mut self, msg: &Message, seq: u64) -> io::Result<Flow> {
+        match msg.get(tags::NEW_SEQ_NO).and_then(|n| n.parse::<u64>().ok()) {
+            Some(new_seq) if new_seq >= self.next_in => {
+                self.next_in = new_seq;
+                if self.resend_until.is_some_and(|until| self.next_in > until) {
+                    self.resend_until = None;
+                }
+            }
+            Some(_) => self.send_reject(seq, 5, Some(tags::NEW_SEQ_NO), "NewSeqNo is lower than expected").await?,
+            None => self.send_reject(seq, 1, Some(tags::NEW_SEQ_NO), "NewSeqNo missing").await?,
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn on_logout(&mut self) -> io::Result<Flow> {
+        self.send_logout(None).await?;
+        Ok(Flow::Close)
+    }
+
+    /// Sends a Heartbeat when nothing else has gone out for an interval, and a TestRequest
+    /// when nothing has come in for a little longer. No answer to that closes the session.
+    async fn check_heartbeat(&mut self) -> io::Result<Flow> {
+        let now = Instant::now();
+        let grace = self.heartbeat + (self.heartbeat / 5).max(Duration::from_secs(1));
+
+        if now.duration_since(self.last_received) > grace {
+            match &self.test_request {
+                None => {
+                    let id = Uuid::new_v4().simple().to_string();
+                    self.send(OutMessage::new(msg_type::TEST_REQUEST).field(tags::TEST_REQ_ID, &id)).await?;
+                    self.test_request = Some((id, now));
+                }
+                Some((_, sent_at)) if now.duration_since(*sent_at) > grace => {
+                    self.send_logout(Some("no response to TestRequest".into())).await?;
+                    return Ok(Flow::Close);
+                }
+                Some(_) => {}
+            }
+        }
+        if now.duration_since(self.last_sent) >= self.heartbeat {
+            self.send(OutMessage::new(msg_type::HEARTBEAT)).await?;
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn send_reject(&mut self, ref_seq: u64, reason: u32, ref_tag: Option<u32>, text: &str) -> io::Result<()> {
+        let mut reject = OutMessage::new(msg_type::REJECT)
+            .field(tags::REF_SEQ_NUM, ref_seq)
+            .field(tags::SESSION_REJECT_REASON, reason)
+            .field(tags::TEXT, text);
+        if let Some(tag) = ref_tag {
+            reject = reject.field(tags::REF_TAG_ID, tag);
+        }
+        self.send(reject).await
+    }
+
+    async fn send_logout(&mut self, text: Option<String>) -> io::Result<()> {
+        let mut logout = OutMessage::new(msg_type::LOGOUT);
+        if let Some(text) = text {
+            logout = logout.field(tags::TEXT, text);
+        }
+        self.send(logout).await
+    }
+
+    /// Sends with the next sequence number, keeping application messages for resends.
+    async fn send(&mut self, msg: OutMessage) -> io::Result<()> {
+        let seq = self.next_out;
+        self.next_out += 1;
+        let sending_time = self.write(&msg, seq, None).await?;
+        if !msg_type::is_admin(msg.msg_type) {
+            self.sent.insert(seq, (msg, sending_time));
+            if self.sent.len() > MAX_STORED_MESSAGES {
+                self.sent.pop_first();
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, msg: &OutMessage, seq: u64, orig_sending_time: Option<&str>) -> io::Result<String> {
+        let sending_time = message::now();
+        let bytes = msg.encode(&Header {
+            sender_comp_id: &self.gateway.comp_id,
+            target_comp_id: &self.target_comp_id,
+            seq_num: seq,
+            sending_time: &sending_time,
+            orig_sending_time,
+        });
+        self.writer.write_all(&bytes).await?;
+        self.last_sent = Instant::now();
+        Ok(sending_time)
+    }
+
+    // ---- application level ----
+
+    async fn on_new_order(&mut self, msg: &Message, seq: u64) -> io::Result<()> {
+        let Some(cl_ord_id) = self.required(msg, seq, &[tags::CL_ORD_ID, tags::SYMBOL, tags::SIDE, tags::ORDER_QTY, tags::ORD_TYPE]).await? else {
+            return Ok(());
+        };
+        let order = match parse_limit_order(msg).and_then(|o| validate_cl_ord_id(&cl_ord_id).map(|_| o)) {
+            Ok(order) => order,
+            Err(e) => return self.send(order_reject(msg, &cl_ord_id, &e)).await,
+        };
+
+        let (tx_oneshot, rx) = oneshot::channel();
+        let reply = match self.gateway.tx.send(EngineCommand::CreateOrder {
+            user_id: self.user_id,
+            side: order.side,
+            price: order.price,
+            quantity: order.quantity,
+            client_order_id: Some(cl_ord_id.clone()),
+            tx_oneshot,
+        }) {
+            Ok(()) => rx.await.unwrap_or_else(|_| Err("engine failed to respond".into())),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match reply.and_then(|id| Uuid::parse_str(&id).map_err(|e| e.to_string())) {
+            Ok(order_id) => {
+                let order = self.track(order_id, cl_ord_id, order);
+                self.send(execution_report(&order, "0", "0")).await
+            }
+            Err(e) => self.send(order_reject(msg, &cl_ord_id, &e)).await,
+        }
+    }
+
+    async fn on_cancel(&mut self, msg: &Message, seq: u64) -> io::Result<()> {
+        let Some(cl_ord_id) = self.required(msg, seq, &[tags::CL_ORD_ID]).await? else {
+            return Ok(());
+        };
+        let Some(order_id) = self.find_order(msg) else {
+            return self.send(cancel_reject(msg, &cl_ord_id, None, "1", "unknown order")).await;
+        };
+
+        let (tx_oneshot, rx) = oneshot::channel();
+        let reply = match self.gateway.tx.send(EngineCommand::CancelOrder {
+            user_id: self.user_id,
+            order: OrderKey::OrderId(order_id),
+            tx_oneshot,
+        }) {
+            Ok(()) => rx.await.unwrap_or_else(|_| Err("engine failed to respond".into())),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match reply {
+            Ok(_) => {
+                let Some(mut order) = self.untrack(order_id) else {
+                    return Ok(());
+                };
+                let orig_cl_ord_id = std::mem::replace(&mut order.cl_ord_id, cl_ord_id);
+                let report = execution_report(&order, "4", "4")
+                    .field(tags::ORIG_CL_ORD_ID, orig_cl_ord_id);
+                self.send(report).await
+            }
+            Err(e) => {
+                let order = self.orders.get(&order_id).cloned();
+                self.send(cancel_reject(msg, &cl_ord_id, order.as_ref(), "1", &e)).await
+            }
+        }
+    }
+
+    /// A replace cancels the resting order and enters a new one for the full new quantity,
+    /// at the back of its price level. Fills on the original aren't carried over.
+    async fn on_replace(&mut self, msg: &Message, seq: u64) -> io::Result<()> {
+        let Some(cl_ord_id) = self.required(msg, seq, &[tags::CL_ORD_ID, tags::SYMBOL, tags::SIDE, tags::ORDER_QTY, tags::ORD_TYPE]).await? else {
+            return Ok(());
+        };
+        let Some(order_id) = self.find_order(msg) else {
+            return self.send(cancel_reject(msg, &cl_ord_id, None, "2", "unknown order")).await;
+        };
+        let new_order = match parse_limit_order(msg).and_then(|o| validate_cl_ord_id(&cl_ord_id).map(|_| o)) {
+            Ok(order) => order,
+            Err(e) => {
+                let order = self.orders.get(&order_id).cloned();
+                return self.send(cancel_reject(msg, &cl_ord_id, order.as_ref(), "2", &e)).await;
+            }
+        };
+
+        let (tx_oneshot, rx) = oneshot::channel();
+        let reply = match self.gateway.tx.send(EngineCommand::ReplaceOrder {
+            user_id: self.user_id,
+            order: OrderKey::OrderId(order_id),
+            side: new_order.side,
+            price: new_order.price,
+            quantity: new_order.quantity,
+            client_order_id: Some(cl_ord_id.clone()),
+            tx_oneshot,
+        }) {
+            Ok(()) => rx.await.unwrap_or_else(|_| Err("engine failed to respond".into())),
+            Err(e) => Err(e.to_string()),
+        };
+
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) => {
+                let order = self.orders.get(&order_id).cloned();
+                return self.send(cancel_reject(msg, &cl_ord_id, order.as_ref(), "2", &e)).await;
+            }
+        };
+        let Some(old) = self.untrack(order_id) else {
+            return Ok(());
+        };
+
+        match reply.and_then(|id| Uuid::parse_str(&id).map_err(|e| e.to_string())) {
+            Ok(new_order_id) => {
+                let order = self.track(new_order_id, cl_ord_id, new_order);
+                let report = execution_report(&order, "5", "0")
+                    .field(tags::ORIG_CL_ORD_ID, old.cl_ord_id);
+                self.send(report).await
+            }
+            Err(e) => {
+                // the original is gone either way
+                let cancelled = execution_report(&old, "4", "4")
+                    .field(tags::TEXT, format!("replacement rejected: {e}"));
+                self.send(cancelled).await?;
+                self.send(order_reject(msg, &cl_ord_id, &e)).await
+            }
+        }
+    }
+
+    /// Reports fills, and cancels or expiries that didn't come from this session, for the
+    /// orders it entered.
+    async fn on_user_event(&mut self, event: UserEvent) -> io::Result<()> {
+        let report = match event {
+            UserEvent::Fill(fill) => self.apply_fill(&fill),
+            UserEvent::Order(record) => match record.status {
+                OrderStatus::Cancelled => self.untrack(record.id).map(|o| execution_report(&o, "4", "4")),
+                OrderStatus::Expired => self.untrack(record.id).map(|o| execution_report(&o, "C", "C")),
+                _ => None,
+            },
+            UserEvent::Balance { .. } => None,
+        };
+        match report {
+            Some(report) => self.send(report).await,
+            None => Ok(()),
+        }
+    }
+
+    fn apply_fill(&mut self, fill: &Fill) -> Option<OutMessage> {
+        let order = self.orders.get_mut(&fill.order_id)?;
+        order.cum_qty += fill.quantity;
+        order.notional += fill.price as u128 * fill.quantity as u128;
+
+        let report = execution_report(order, "F", order.status())
+            .field(tags::LAST_QTY, math::sats_to_btc_string(fill.quantity))
+            .field(tags::LAST_PX, math::micro_to_price_string(fill.price))
+            .field(tags::TRD_MATCH_ID, fill.trade_id);
+        if order.leaves_qty() == 0 {
+            self.untrack(fill.order_id);
+        }
+        Some(report)
+    }
+
+    fn track(&mut self, order_id: Uuid, cl_ord_id: String, order: LimitOrder) -> FixOrder {
+        let order = FixOrder {
+            order_id,
+            cl_ord_id,
+            side: order.side,
+            price: order.price,
+            quantity: order.quantity,
+            cum_qty: 0,
+            notional: 0,
+        };
+        self.cl_ord_ids.insert(order.cl_ord_id.clone(), order_id);
+        self.orders.insert(order_id, order.clone());
+        order
+    }
+
+    fn untrack(&mut self, order_id: Uuid) -> Option<FixOrder> {
+        let order = self.orders.remove(&order_id)?;
+        self.cl_ord_ids.remove(&order.cl_ord_id);
+        Some(order)
+    }
+
+    /// The open order a cancel or replace refers to, by OrderID or else OrigClOrdID.
+    fn find_order(&self, msg: &Message) -> Option<Uuid> {
+        let order_id = match msg.get(tags::ORDER_ID).and_then(|id| Uuid::parse_str(id).ok()) {
+            Some(order_id) => order_id,
+            None => *self.cl_ord_ids.get(msg.get(tags::ORIG_CL_ORD_ID)?)?,
+        };
+        self.orders.contains_key(&order_id).then_some(order_id)
+    }
+
+    /// The ClOrdID, or `None` after rejecting a message that lacks one of `required`.
+    async fn required(&mut self, msg: &Message, seq: u64, required: &[u32]) -> io::Result<Option<String>> {
+        if let Some(tag) = required.iter().find(|tag| msg.get(**tag).is_none_or(str::is_empty)) {
+            self.send_reject(seq, 1, Some(*tag), "required tag missing").await?;
+            return Ok(None);
+        }
+        Ok(msg.get(tags::CL_ORD_ID).map(String::from))
+    }
+}
+
+fn parse_limit_order(msg: &Message) -> Result<LimitOrder, String> {
+    if msg.get(tags::SYMBOL) != Some(MARKET_SYMBOL) {
+        return Err(format!("unknown symbol, only {MARKET_SYMBOL} is traded"));
+    }
+    let side = match msg.get(tags::SIDE) {
+        Some("1") => Side::Bid,
+        Some("2") => Side::Ask,
+        _ => return Err("Side must be 1 (buy) or 2 (sell)".into()),
+    };
+    if msg.get(tags::ORD_TYPE) != Some("2") {
+        return Err("only limit orders (OrdType 2) are supported".into());
+    }
+    if !matches!(msg.get(tags::TIME_IN_FORCE), None | Some("0") | Some("1")) {
+        return Err("TimeInForce must be 0 (day) or 1 (good till cancel)".into());
+    }
+    let price = math::price_to_micro_str(msg.get(tags::PRICE).ok_or("Price is required for limit orders")?)?;
+    if price == 0 {
+        return Err("Price must be positive".into());
+    }
+    let quantity = math::btc_to_sats_str(msg.get(tags::ORDER_QTY).unwrap_or_default())?;
+    if quantity == 0 {
+        return Err("OrderQty must be positive".into());
+    }
+    Ok(LimitOrder { side, price, quantity })
+}
+
+fn validate_cl_ord_id(cl_ord_id: &str) -> Result<(), String> {
+    if cl_ord_id.len() > MAX_CL_ORD_ID_LEN {
+        return Err(format!("ClOrdID must be at most {MAX_CL_ORD_ID_LEN} characters"));
+    }
+    Ok(())
+}
+
+fn side_code(side: Side) -> &'static str {
+    match side {
+        Side::Bid => "1",
+        Side::Ask => "2",
+    }
+}
+
+fn execution_report(order: &FixOrder, exec_type: &str, ord_status: &str) -> OutMessage {
+    let leaves_qty = if matches!(ord_status, "0" | "1") { order.leaves_qty() } else { 0 };
+    let avg_px = match order.cum_qty {
+        0 => 0,
+        cum => (order.notional / cum as u128) as u64,
+    };
+    OutMessage::new(msg_type::EXECUTION_REPORT)
+        .field(tags::ORDER_ID, order.order_id)
+        .field(tags::CL_ORD_ID, &order.cl_ord_id)
+        .field(tags::EXEC_ID, Uuid::new_v4().simple())
+        .field(tags::EXEC_TYPE, exec_type)
+        .field(tags::ORD_STATUS, ord_status)
+        .field(tags::SYMBOL, MARKET_SYMBOL)
+        .field(tags::SIDE, side_code(order.side))
+        .field(tags::ORD_TYPE, 2)
+        .field(tags::PRICE, math::micro_to_price_string(order.price))
+        .field(tags::ORDER_QTY, math::sats_to_btc_string(order.quantity))
+        .field(tags::CUM_QTY, math::sats_to_btc_string(order.cum_qty))
+        .field(tags::LEAVES_QTY, math::sats_to_btc_string(leaves_qty))
+        .field(tags::AVG_PX, math::micro_to_price_string(avg_px))
+        .field(tags::TRANSACT_TIME, message::now())
+}
+
+/// An ExecutionReport rejecting an order the engine never accepted.
+fn order_reject(msg: &Message, cl_ord_id: &str, text: &str) -> OutMessage {
+    OutMessage::new(msg_type::EXECUTION_REPORT)
+        .field(tags::ORDER_ID, "NONE")
+        .field(tags::CL_ORD_ID, cl_ord_id)
+        .field(tags::EXEC_ID, Uuid::new_v4().simple())
+        .field(tags::EXEC_TYPE, 8)
+        .field(tags::ORD_STATUS, 8)
+        .field(tags::ORD_REJ_REASON, 99)
+        .field(tags::SYMBOL, msg.get(tags::SYMBOL).unwrap_or_default())
+        .field(tags::SIDE, msg.get(tags::SIDE).unwrap_or_default())
+        .field(tags::ORDER_QTY, msg.get(tags::ORDER_QTY).unwrap_or_default())
+        .field(tags::CUM_QTY, 0)
+        .field(tags::LEAVES_QTY, 0)
+        .field(tags::AVG_PX, 0)
+        .field(tags::TEXT, text)
+        .field(tags::TRANSACT_TIME, message::now())
+}
+
+/// OrderCancelReject for a cancel (`response_to` 1) or cancel/replace (2) that failed.
+fn cancel_reject(msg: &Message, cl_ord_id: &str, order: Option<&FixOrder>, response_to: &str, text: &str) -> OutMessage {
+    let (order_id, ord_status) = match order {
+        Some(order) => (order.order_id.to_string(), order.status()),
+        None => ("NONE".to_string(), "8"),
+    };
+    OutMessage::new(msg_type::ORDER_CANCEL_REJECT)
+        .field(tags::ORDER_ID, order_id)
+        .field(tags::CL_ORD_ID, cl_ord_id)
+        .field(tags::ORIG_CL_ORD_ID, msg.get(tags::ORIG_CL_ORD_ID).unwrap_or("NONE"))
+        .field(tags::ORD_STATUS, ord_status)
+        .field(tags::CXL_REJ_RESPONSE_TO, response_to)
+        .field(tags::CXL_REJ_REASON, if order.is_none() { 1 } else { 99 })
+        .field(tags::TEXT, text)
+}
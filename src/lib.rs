@@ -0,0 +1,5 @@
+//! Client side of the exchange's binary protocols. The exchange itself is the
+//! `single-threaded-orderbook` binary; see `sbe` for order entry, `sbe::client` for a
+//! blocking order entry client and `sbe::market_data` for the UDP market data feed.
+
+pub mod sbe;
@@ -1,66 +1,369 @@
-use actix_web::{HttpServer, HttpResponse, web, App, Responder, post, get};
-use std::sync::mpsc;
-use tokio::sync::oneshot;
+use actix_web::{HttpServer, HttpRequest, HttpResponse, web, App, Responder, middleware, post, get};
+use serde::de::IgnoredAny;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, oneshot};
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
 
+mod auth;
+use auth::{Admin, ApiKeys, Authenticated, Scope};
+
+mod rate_limit;
+use rate_limit::{RateLimitConfig, RateLimiter};
+
 mod engine;
+mod fix;
+use engine::allocation::MatchingAlgorithm;
+use engine::candles::CandleInterval;
+use engine::client_orders::OrderKey;
+use engine::feed::L3Replay;
+use engine::market::{MarketState, MARKET_SYMBOL};
 use engine::orderbook::Side;
+use engine::peg::{Peg, PegReference};
+use engine::batch::{BatchOrder, MAX_BATCH_SIZE};
+use engine::queue::{self, CommandSender, SendError};
+use engine::risk::RiskLimits;
+use engine::stops::TrailAmount;
+use engine::user_feed::UserUpdates;
+use engine::volatility::VolatilityControls;
 
 mod math;
+mod sbe_gateway;
+mod udp_feed;
+mod ws;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct DepositRequest {
-    user_id: String,
     asset: String,
     amount: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct GetBalanceRequest {
+struct GetRiskLimitsRequest {
     user_id: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct CreateOrderRequest {
-    user_id: String,
     side: String,
     price: String,
     quantity: String,
+    client_order_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CreatePeggedOrderRequest {
+    side: String,
+    quantity: String,
+    peg: String,
+    offset: Option<String>,
+    limit_price: Option<String>,
+    client_order_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CreateTrailingStopRequest {
+    side: String,
+    quantity: String,
+    trail_offset: Option<String>,
+    trail_percent: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct CancelOrderRequest {
+    order_id: Option<String>,
+    client_order_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GetOrderRequest {
+    order_id: Option<String>,
+    client_order_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GetDepthQuery {
+    levels: Option<String>,
+    aggregation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GetCandlesQuery {
+    interval: String,
+    start: Option<u64>,
+    end: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct GetL3UpdatesQuery {
+    after: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct MarketRequest {
+    market: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SetMarketStateRequest {
+    market: String,
+    state: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SetMatchingAlgorithmRequest {
+    market: String,
+    algorithm: String,
+    min_allocation: Option<String>,
+    top_order_priority: Option<bool>,
+    fifo_percent: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SetVolatilityControlsRequest {
+    market: String,
+    band_percent: Option<String>,
+    breaker_percent: Option<String>,
+    breaker_window_secs: Option<u64>,
+    breaker_action: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SetRiskLimitsRequest {
     user_id: String,
-    order_id: String,
+    max_order_quantity: Option<String>,
+    max_order_notional: Option<String>,
+    max_open_orders: Option<usize>,
+    max_open_bid_notional: Option<String>,
+    max_open_ask_notional: Option<String>,
+    max_orders_per_second: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct BatchOrdersRequest {
+    orders: Vec<CreateOrderRequest>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
-struct GetUserOrdersRequest {
+struct BatchCancelRequest {
+    orders: Vec<CancelOrderRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CreateApiKeyRequest {
     user_id: String,
+    scopes: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CreateDropCopyKeyRequest {
+    accounts: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct RevokeApiKeyRequest {
+    api_key: String,
 }
 
+const MAX_CLIENT_ORDER_ID_LEN: usize = 64;
+
+fn validate_client_order_id(client_order_id: &Option<String>) -> Result<(), &'static str> {
+    match client_order_id {
+        Some(id) if id.is_empty() || id.len() > MAX_CLIENT_ORDER_ID_LEN => Err("client order id must be 1 to 64 characters"),
+        _ => Ok(()),
+    }
+}
+
+/// Parses an order quantity, which must be at least one sat.
+fn parse_order_quantity(quantity: &str) -> Result<u64, String> {
+    match math::btc_to_sats_str(quantity)? {
+        0 => Err("quantity must be positive".into()),
+        sats => Ok(sats),
+    }
+}
+
+/// Parses a limit price, which must be above zero.
+fn parse_order_price(price: &str) -> Result<u64, String> {
+    match math::price_to_micro_str(price)? {
+        0 => Err("price must be positive".into()),
+        micro => Ok(micro),
+    }
+}
+
+fn parse_order_key(order_id: &Option<String>, client_order_id: &Option<String>) -> Result<OrderKey, &'static str> {
+    match (order_id, client_order_id) {
+        (Some(id), None) => Uuid::parse_str(id)
+            .map(OrderKey::OrderId)
+            .map_err(|_| "invalid order id"),
+        (None, Some(id)) => Ok(OrderKey::ClientOrderId(id.clone())),
+        _ => Err("provide exactly one of order_id or client_order_id"),
+    }
+}
+
+/// Response for a command the engine couldn't take: 503 with `Retry-After` while the queue
+/// is full, 500 once the engine thread has died.
+fn engine_unavailable(e: SendError) -> HttpResponse {
+    match e {
+        SendError::Full => HttpResponse::ServiceUnavailable()
+            .insert_header(("Retry-After", "1"))
+            .body(e.to_string()),
+        SendError::EngineStopped => HttpResponse::InternalServerError().body(e.to_string()),
+    }
+}
+
+/// Market data events buffered per subscriber before a slow one starts missing them.
+const FEED_CAPACITY: usize = 4096;
+/// Private updates buffered per user. Each user has a channel of their own, so this only
+/// needs to cover one account's bursts.
+const USER_UPDATES_CAPACITY: usize = 1024;
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     
-    let (tx, rx) = mpsc::channel();
+    let rate_limiter = match RateLimitConfig::from_env() {
+        Ok(config) => web::Data::new(RateLimiter::new(config)),
+        Err(e) => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, e)),
+    };
+
+    // commands waiting for the engine beyond this are refused with a 503
+    let queue_capacity = match std::env::var("ENGINE_QUEUE_CAPACITY") {
+        Ok(v) => match v.parse::<usize>() {
+            Ok(capacity) if capacity > 0 => capacity,
+            _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("ENGINE_QUEUE_CAPACITY must be a positive integer, got {v:?}"))),
+        },
+        Err(_) => queue::DEFAULT_QUEUE_CAPACITY,
+    };
+    let (tx, rx) = queue::command_queue(queue_capacity);
+    let (feed_tx, _) = broadcast::channel(FEED_CAPACITY);
+    let user_updates = UserUpdates::new(USER_UPDATES_CAPACITY);
+    let (drop_copy_tx, _) = broadcast::channel(FEED_CAPACITY);
+    let engine_feed_tx = feed_tx.clone();
+    let engine_user_updates = user_updates.clone();
+    let engine_drop_copy_tx = drop_copy_tx.clone();
+    // trades are journaled, and candle history rebuilt from it on startup, only if set
+    let journal_path = std::env::var_os("TRADE_JOURNAL").map(PathBuf::from);
 
     std::thread::spawn( move || {
         println!("inside the new OS thread");
-        engine::run(rx);
+        engine::run(rx, engine_feed_tx, engine_user_updates, engine_drop_copy_tx, journal_path);
     });
 
+    let api_keys = web::Data::new(ApiKeys::new());
+    // the /admin endpoints take requests signed with this key and nothing else
+    match (std::env::var("ADMIN_API_KEY"), std::env::var("ADMIN_API_SECRET")) {
+        (Ok(api_key), Ok(secret)) if !api_key.is_empty() && secret.len() >= 32 => api_keys.add_admin(api_key, secret),
+        (Err(_), Err(_)) => println!("ADMIN_API_KEY and ADMIN_API_SECRET are not set, the admin endpoints are disabled"),
+        _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "ADMIN_API_KEY and ADMIN_API_SECRET must both be set, the secret at least 32 characters")),
+    }
+
+    // the FIX acceptor only runs if given an address to listen on, e.g. 127.0.0.1:9878
+    if let Ok(fix_addr) = std::env::var("FIX_ADDR") {
+        let listener = std::net::TcpListener::bind(&fix_addr)?;
+        println!("FIX acceptor listening on {fix_addr}");
+        let gateway = fix::Gateway {
+            comp_id: std::env::var("FIX_COMP_ID").unwrap_or_else(|_| fix::DEFAULT_COMP_ID.to_string()),
+            tx: tx.clone(),
+            user_updates: user_updates.clone(),
+            api_keys: api_keys.clone(),
+        };
+        actix_web::rt::spawn(async move {
+            if let Err(e) = fix::serve(listener, gateway).await {
+                println!("FIX acceptor stopped: {e}");
+            }
+        });
+    }
+
+    // likewise the binary order entry acceptor, e.g. 127.0.0.1:9879
+    if let Ok(sbe_addr) = std::env::var("SBE_ADDR") {
+        let listener = std::net::TcpListener::bind(&sbe_addr)?;
+        println!("binary order entry listening on {sbe_addr}");
+        let gateway = sbe_gateway::Gateway {
+            tx: tx.clone(),
+            user_updates: user_updates.clone(),
+            api_keys: api_keys.clone(),
+        };
+        actix_web::rt::spawn(async move {
+            if let Err(e) = sbe_gateway::serve(listener, gateway).await {
+                println!("binary order entry stopped: {e}");
+            }
+        });
+    }
+
+    // UDP market data goes to MARKET_DATA_ADDR, e.g. 127.0.0.1:9880 or a multicast group
+    // like 239.1.1.1:9880, with retransmits and snapshots on MARKET_DATA_RECOVERY_ADDR
+    let history = Arc::new(Mutex::new(udp_feed::History::default()));
+    if let Ok(md_addr) = std::env::var("MARKET_DATA_ADDR") {
+        let dest: SocketAddr = md_addr.parse().map_err(|_| std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("MARKET_DATA_ADDR must be ip:port, got {md_addr:?}")))?;
+        let bind_addr = if dest.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = std::net::UdpSocket::bind(bind_addr)?;
+        println!("publishing market data to {dest}");
+        let feed = feed_tx.subscribe();
+        let history = history.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = udp_feed::publish(socket, dest, feed, history).await {
+                println!("market data publisher stopped: {e}");
+            }
+        });
+    }
+    if let Ok(recovery_addr) = std::env::var("MARKET_DATA_RECOVERY_ADDR") {
+        let listener = std::net::TcpListener::bind(&recovery_addr)?;
+        println!("market data recovery listening on {recovery_addr}");
+        let recovery = udp_feed::Recovery { tx: tx.clone(), history };
+        actix_web::rt::spawn(async move {
+            if let Err(e) = udp_feed::serve_recovery(listener, recovery).await {
+                println!("market data recovery stopped: {e}");
+            }
+        });
+    }
+
     HttpServer::new( move || {
         App::new()
             .app_data(web::Data::new(tx.clone()))
+            .app_data(web::Data::new(feed_tx.clone()))
+            .app_data(web::Data::new(user_updates.clone()))
+            .app_data(web::Data::new(drop_copy_tx.clone()))
+            .app_data(api_keys.clone())
+            .app_data(rate_limiter.clone())
+            .wrap(middleware::from_fn(rate_limit::limit_by_ip))
             .service(hello)
             .service(initialize_user)
             .service(deposit)
             .service(get_balances)
             .service(create_order)
+            .service(create_pegged_order)
+            .service(create_trailing_stop)
             .service(cancel_order)
+            .service(batch_orders)
+            .service(batch_cancel)
             .service(get_user_orders)
+            .service(get_order)
+            .service(get_user_trailing_stops)
             .service(get_depth)
+            .service(get_candles)
+            .service(get_ticker)
+            .service(get_bbo)
+            .service(get_l3_snapshot)
+            .service(get_l3_updates)
+            .service(ws::market_data)
+            .service(ws::user_stream)
+            .service(ws::drop_copy)
+            .service(get_matching_algorithm)
+            .service(set_matching_algorithm)
+            .service(set_market_state)
+            .service(get_volatility_controls)
+            .service(set_volatility_controls)
+            .service(get_risk_limits)
+            .service(set_risk_limits)
+            .service(get_engine_queue)
+            .service(create_api_key)
+            .service(create_drop_copy_key)
+            .service(revoke_api_key)
+            .service(start_auction)
+            .service(end_auction)
+            .service(get_auction)
     })
     .bind(("127.0.0.1", 8080))?
     .run()
@@ -73,12 +376,14 @@ async fn hello() -> impl Responder {
 }
 
 #[post("/initialize_user")]
-async fn initialize_user(tx: web::Data<mpsc::Sender<engine::EngineCommand>>) -> impl Responder {
+async fn initialize_user(tx: web::Data<CommandSender>) -> impl Responder {
     let (tx_oneshot, rx) = oneshot::channel();
 
-    tx.send(engine::EngineCommand::InitializeUser {
+    if let Err(e) = tx.send(engine::EngineCommand::InitializeUser {
         tx_oneshot
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
 
     match rx.await {
         Ok(msg) => HttpResponse::Ok().body(format!("engine replied: {}", msg)),
@@ -87,7 +392,11 @@ async fn initialize_user(tx: web::Data<mpsc::Sender<engine::EngineCommand>>) ->
 }
 
 #[post("/deposit")]
-async fn deposit(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::Json<DepositRequest>) -> impl Responder {
+async fn deposit(tx: web::Data<CommandSender>, auth: Authenticated<DepositRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Deposit) {
+        return resp;
+    }
+    let body = &auth.body;
 
     let amount = match body.asset.to_uppercase().as_str() {
         "BTC" => match math::btc_to_sats_str(&body.amount) {
@@ -102,12 +411,14 @@ async fn deposit(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::
     };
 
     let (tx_oneshot, rx) = oneshot::channel();
-    tx.send(engine::EngineCommand::Deposit {
-        user_id: Uuid::parse_str(&body.user_id).unwrap(),
+    if let Err(e) = tx.send(engine::EngineCommand::Deposit {
+        user_id: auth.user_id,
         asset: body.asset.to_uppercase(),
         amount,
         tx_oneshot
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
 
     match rx.await {
         Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
@@ -118,14 +429,19 @@ async fn deposit(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::
 }
 
 #[post("/get_balances")]
-async fn get_balances(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::Json<GetBalanceRequest>) -> impl Responder {
+async fn get_balances(tx: web::Data<CommandSender>, auth: Authenticated<IgnoredAny>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Read) {
+        return resp;
+    }
 
     let (tx_oneshot, rx) = oneshot::channel();
 
-    tx.send(engine::EngineCommand::GetBalances {
-        user_id: Uuid::parse_str(&body.user_id).unwrap(),
+    if let Err(e) = tx.send(engine::EngineCommand::GetBalances {
+        user_id: auth.user_id,
         tx_oneshot
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
 
      match rx.await {
          Ok(Some(user_balance)) => HttpResponse::Ok().json(user_balance),
@@ -140,7 +456,11 @@ async fn get_balances(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body:
 }
 
 #[post("/create_order")]
-async fn create_order(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::Json<CreateOrderRequest>) -> impl Responder {
+async fn create_order(tx: web::Data<CommandSender>, auth: Authenticated<CreateOrderRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Trade) {
+        return resp;
+    }
+    let body = &auth.body;
     let (tx_oneshot, rx) = oneshot::channel();
 
     let side = match body.side.to_lowercase().as_str() {
@@ -149,85 +469,856 @@ async fn create_order(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body:
         _ => return HttpResponse::BadRequest().body("invalid side"),
     };
 
-    let price = match math::price_to_micro_str(&body.price) {
+    let price = match parse_order_price(&body.price) {
         Ok(v) => v,
         Err(e) => return HttpResponse::BadRequest().body(e),
     };
-    let quantity = match math::btc_to_sats_str(&body.quantity) {
+    let quantity = match parse_order_quantity(&body.quantity) {
         Ok(v) => v,
         Err(e) => return HttpResponse::BadRequest().body(e),
     };
+    if let Err(e) = validate_client_order_id(&body.client_order_id) {
+        return HttpResponse::BadRequest().body(e);
+    }
 
-    tx.send(engine::EngineCommand::CreateOrder {
-        user_id: Uuid::parse_str(&body.user_id).unwrap(),
+    if let Err(e) = tx.send(engine::EngineCommand::CreateOrder {
+        user_id: auth.user_id,
         side,
         price,
         quantity,
+        client_order_id: body.client_order_id.clone(),
         tx_oneshot,
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
 
     match rx.await {
-        Ok(order_id) => HttpResponse::Ok().json(serde_json::json!({
+        Ok(Ok(order_id)) => HttpResponse::Ok().json(serde_json::json!({
             "msg": "order was created successfully",
             "order_id": order_id
         })),
+        Ok(Err(reason)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        })),
         Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
     }
 }
 
-#[post("/cancel_order")]
-async fn cancel_order(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::Json<CancelOrderRequest>) -> impl Responder {
-    let user_id = match Uuid::parse_str(&body.user_id) {
+#[post("/create_pegged_order")]
+async fn create_pegged_order(tx: web::Data<CommandSender>, auth: Authenticated<CreatePeggedOrderRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Trade) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let body = &auth.body;
+
+    let side = match body.side.to_lowercase().as_str() {
+        "bid" => Side::Bid,
+        "ask" => Side::Ask,
+        _ => return HttpResponse::BadRequest().body("invalid side"),
+    };
+
+    let quantity = match parse_order_quantity(&body.quantity) {
         Ok(v) => v,
-        Err(_) => return HttpResponse::BadRequest().body("invalid user id"),
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let reference = match body.peg.to_lowercase().as_str() {
+        "primary" => PegReference::Primary,
+        "market" => PegReference::Market,
+        "mid" => PegReference::Mid,
+        _ => return HttpResponse::BadRequest().body("invalid peg, expected primary, market or mid"),
+    };
+
+    let offset = match &body.offset {
+        Some(o) => match math::signed_price_to_micro_str(o) {
+            Ok(v) => v,
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        None => 0,
+    };
+
+    let limit = match &body.limit_price {
+        Some(l) => match math::price_to_micro_str(l) {
+            Ok(v) => Some(v),
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        None => None,
     };
-    let order_id = match Uuid::parse_str(&body.order_id) {
+
+    if let Err(e) = validate_client_order_id(&body.client_order_id) {
+        return HttpResponse::BadRequest().body(e);
+    }
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::CreatePeggedOrder {
+        user_id,
+        side,
+        quantity,
+        peg: Peg { reference, offset, limit },
+        client_order_id: body.client_order_id.clone(),
+        tx_oneshot,
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(Ok(order_id)) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": "pegged order was created successfully",
+            "order_id": order_id
+        })),
+        Ok(Err(reason)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/create_trailing_stop")]
+async fn create_trailing_stop(tx: web::Data<CommandSender>, auth: Authenticated<CreateTrailingStopRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Trade) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let body = &auth.body;
+
+    let side = match body.side.to_lowercase().as_str() {
+        "bid" => Side::Bid,
+        "ask" => Side::Ask,
+        _ => return HttpResponse::BadRequest().body("invalid side"),
+    };
+
+    let quantity = match parse_order_quantity(&body.quantity) {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+
+    let trail = match (&body.trail_offset, &body.trail_percent) {
+        (Some(offset), None) => match math::price_to_micro_str(offset) {
+            Ok(0) => return HttpResponse::BadRequest().body("trail offset must be positive"),
+            Ok(v) => TrailAmount::Offset(v),
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        (None, Some(percent)) => match math::percent_to_bps(percent) {
+            Ok(v) if v == 0 || v >= 10_000 => return HttpResponse::BadRequest().body("trail percent must be between 0 and 100"),
+            Ok(v) => TrailAmount::Percent(v),
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        _ => return HttpResponse::BadRequest().body("provide exactly one of trail_offset or trail_percent"),
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::CreateTrailingStop {
+        user_id,
+        side,
+        quantity,
+        trail,
+        tx_oneshot,
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(Ok(stop_id)) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": stop_id
+        })),
+        Ok(Err(reason)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/cancel_order")]
+async fn cancel_order(tx: web::Data<CommandSender>, auth: Authenticated<CancelOrderRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Trade) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let body = &auth.body;
+    let order = match parse_order_key(&body.order_id, &body.client_order_id) {
         Ok(v) => v,
-        Err(_) => return HttpResponse::BadRequest().body("invalid order id"),
+        Err(e) => return HttpResponse::BadRequest().body(e),
     };
 
     let (tx_oneshot, rx) = oneshot::channel();
-    tx.send(engine::EngineCommand::CancelOrder {
+    if let Err(e) = tx.send(engine::EngineCommand::CancelOrder {
         user_id,
-        order_id,
+        order,
         tx_oneshot
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
     
     match rx.await {
-        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+        Ok(Ok(msg)) => HttpResponse::Ok().json(serde_json::json!({
             "msg": msg
         })),
+        Ok(Err(reason)) => HttpResponse::BadRequest().json(serde_json::json!({
+            "error": reason
+        })),
         Err(_) => HttpResponse::InternalServerError().body("engine failed to cancel order"),
     }
 }
 
+/// Places up to `MAX_BATCH_SIZE` limit orders in one engine step, in the order given.
+/// Items that don't parse are rejected on their own; `results` lines up with `orders`.
+#[post("/batch_orders")]
+async fn batch_orders(req: HttpRequest, tx: web::Data<CommandSender>, auth: Authenticated<BatchOrdersRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Trade) {
+        return resp;
+    }
+    let items = &auth.body.orders;
+    if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+        return HttpResponse::BadRequest().body(format!("a batch holds 1 to {MAX_BATCH_SIZE} orders"));
+    }
+    if let Some(resp) = rate_limit::charge_batch(&req, &auth.api_key, items.len()) {
+        return resp;
+    }
+
+    let parsed: Vec<Result<BatchOrder, String>> = items.iter().map(|item| {
+        let side = match item.side.to_lowercase().as_str() {
+            "bid" => Side::Bid,
+            "ask" => Side::Ask,
+            _ => return Err("invalid side".to_string()),
+        };
+        validate_client_order_id(&item.client_order_id)?;
+        Ok(BatchOrder {
+            side,
+            price: parse_order_price(&item.price)?,
+            quantity: parse_order_quantity(&item.quantity)?,
+            client_order_id: item.client_order_id.clone(),
+        })
+    }).collect();
+    let orders: Vec<BatchOrder> = parsed.iter().filter_map(|p| p.as_ref().ok().cloned()).collect();
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::BatchCreateOrders {
+        user_id: auth.user_id,
+        orders,
+        tx_oneshot,
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(replies) => {
+            let mut replies = replies.into_iter();
+            let results: Vec<_> = parsed.into_iter().map(|p| match p.and_then(|_| replies.next().unwrap()) {
+                Ok(order_id) => serde_json::json!({ "order_id": order_id }),
+                Err(e) => serde_json::json!({ "error": e }),
+            }).collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "results": results
+            }))
+        }
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+/// Cancels up to `MAX_BATCH_SIZE` orders in one engine step, in the order given.
+#[post("/batch_cancel")]
+async fn batch_cancel(req: HttpRequest, tx: web::Data<CommandSender>, auth: Authenticated<BatchCancelRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Trade) {
+        return resp;
+    }
+    let items = &auth.body.orders;
+    if items.is_empty() || items.len() > MAX_BATCH_SIZE {
+        return HttpResponse::BadRequest().body(format!("a batch holds 1 to {MAX_BATCH_SIZE} cancels"));
+    }
+    if let Some(resp) = rate_limit::charge_batch(&req, &auth.api_key, items.len()) {
+        return resp;
+    }
+
+    let parsed: Vec<Result<OrderKey, &str>> = items.iter()
+        .map(|item| parse_order_key(&item.order_id, &item.client_order_id))
+        .collect();
+    let orders: Vec<OrderKey> = parsed.iter().filter_map(|p| p.as_ref().ok().cloned()).collect();
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::BatchCancelOrders {
+        user_id: auth.user_id,
+        orders,
+        tx_oneshot,
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(replies) => {
+            let mut replies = replies.into_iter();
+            let results: Vec<_> = parsed.into_iter().map(|p| match p.map_err(String::from).and_then(|_| replies.next().unwrap()) {
+                Ok(msg) => serde_json::json!({ "msg": msg }),
+                Err(e) => serde_json::json!({ "error": e }),
+            }).collect();
+            HttpResponse::Ok().json(serde_json::json!({
+                "results": results
+            }))
+        }
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
 #[post("/get_user_orders")]
-async fn get_user_orders(tx: web::Data<mpsc::Sender<engine::EngineCommand>>, body: web::Json<GetUserOrdersRequest>) -> impl Responder {
-    let user_id = match Uuid::parse_str(&body.user_id) {
+async fn get_user_orders(tx: web::Data<CommandSender>, auth: Authenticated<IgnoredAny>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Read) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetUserOrders {
+        user_id,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(list) => HttpResponse::Ok().json(list),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+
+}
+
+#[post("/get_order")]
+async fn get_order(tx: web::Data<CommandSender>, auth: Authenticated<GetOrderRequest>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Read) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let body = &auth.body;
+    let order = match parse_order_key(&body.order_id, &body.client_order_id) {
         Ok(v) => v,
-        Err(_) => return HttpResponse::BadRequest().body("invalid user id"),
+        Err(e) => return HttpResponse::BadRequest().body(e),
     };
+
     let (tx_oneshot, rx) = oneshot::channel();
-    tx.send(engine::EngineCommand::GetUserOrders {
+    if let Err(e) = tx.send(engine::EngineCommand::GetOrder {
         user_id,
+        order,
         tx_oneshot
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
 
     match rx.await {
-        Ok(list) => HttpResponse::Ok().json(list),
+        Ok(Some(order)) => HttpResponse::Ok().json(order),
+        Ok(None) => HttpResponse::NotFound().json(serde_json::json!({
+            "error": "order not found"
+        })),
         Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
     }
+}
+
+#[post("/get_user_trailing_stops")]
+async fn get_user_trailing_stops(tx: web::Data<CommandSender>, auth: Authenticated<IgnoredAny>) -> impl Responder {
+    if let Some(resp) = auth.missing_scope(Scope::Read) {
+        return resp;
+    }
+    let user_id = auth.user_id;
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetUserTrailingStops {
+        user_id,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
 
+    match rx.await {
+        Ok(list) => HttpResponse::Ok().json(list),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
 }
 
+const DEFAULT_DEPTH_LEVELS: usize = 10;
+
 #[get("/get_depth")]
-async fn get_depth(tx: web::Data<mpsc::Sender<engine::EngineCommand>>) -> impl Responder {
+async fn get_depth(tx: web::Data<CommandSender>, query: web::Query<GetDepthQuery>) -> impl Responder {
+    let levels = match query.levels.as_deref() {
+        None => Some(DEFAULT_DEPTH_LEVELS),
+        Some("full") => None,
+        Some(n) => match n.parse::<usize>() {
+            Ok(v) if v > 0 => Some(v),
+            _ => return HttpResponse::BadRequest().body("levels must be a positive number or \"full\""),
+        },
+    };
+    let bucket = match query.aggregation.as_deref().map(math::price_to_micro_str) {
+        Some(Ok(0)) => return HttpResponse::BadRequest().body("aggregation must be positive"),
+        Some(Ok(v)) => Some(v),
+        Some(Err(e)) => return HttpResponse::BadRequest().body(e),
+        None => None,
+    };
+
     let (tx_oneshot, rx) = oneshot::channel();
-    tx.send(engine::EngineCommand::GetDepth {
+    if let Err(e) = tx.send(engine::EngineCommand::GetDepth {
+        levels,
+        bucket,
         tx_oneshot
-    }).unwrap();
+    }) {
+        return engine_unavailable(e);
+    }
     match rx.await {
         Ok(depth) => HttpResponse::Ok().json(depth),
         Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
     }
 }
+
+#[get("/bbo")]
+async fn get_bbo(tx: web::Data<CommandSender>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetBbo {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(bbo) => HttpResponse::Ok().json(bbo),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[get("/ticker")]
+async fn get_ticker(tx: web::Data<CommandSender>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetTicker {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(ticker) => HttpResponse::Ok().json(ticker),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+/// Candles for `interval` opening in `[start, end)` (UTC milliseconds), oldest first and
+/// at most `MAX_CANDLES_PER_QUERY` of them. Intervals without trades are left out.
+#[get("/candles")]
+async fn get_candles(tx: web::Data<CommandSender>, query: web::Query<GetCandlesQuery>) -> impl Responder {
+    let interval = match CandleInterval::parse(&query.interval) {
+        Some(i) => i,
+        None => return HttpResponse::BadRequest().body("interval must be one of 1m, 5m, 15m, 1h, 1d"),
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetCandles {
+        interval,
+        start: query.start.unwrap_or(0),
+        end: query.end.unwrap_or(u64::MAX),
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(candles) => HttpResponse::Ok().json(candles),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[get("/l3_snapshot")]
+async fn get_l3_snapshot(tx: web::Data<CommandSender>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetL3Snapshot {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(snapshot) => HttpResponse::Ok().json(snapshot),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+/// L3 updates with a sequence above `after`. A client that has fallen behind the replay
+/// buffer gets 410 Gone and should start again from `/l3_snapshot`.
+#[get("/l3_updates")]
+async fn get_l3_updates(tx: web::Data<CommandSender>, query: web::Query<GetL3UpdatesQuery>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetL3Updates {
+        after: query.after,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(L3Replay::Updates(updates)) => HttpResponse::Ok().json(updates),
+        Ok(L3Replay::TooOld { oldest }) => HttpResponse::Gone().json(serde_json::json!({
+            "msg": "sequence is no longer retained, take a new snapshot",
+            "oldest_sequence": oldest
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[get("/admin/matching_algorithm")]
+async fn get_matching_algorithm(tx: web::Data<CommandSender>, _admin: Admin<IgnoredAny>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetMatchingAlgorithm {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(matching) => HttpResponse::Ok().json(serde_json::json!({
+            "market": MARKET_SYMBOL,
+            "algorithm": matching
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/set_matching_algorithm")]
+async fn set_matching_algorithm(tx: web::Data<CommandSender>, body: Admin<SetMatchingAlgorithmRequest>) -> impl Responder {
+    if body.market != MARKET_SYMBOL {
+        return HttpResponse::BadRequest().body("unknown market");
+    }
+
+    let min_allocation = match &body.min_allocation {
+        Some(m) => match math::btc_to_sats_str(m) {
+            Ok(v) => v,
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        },
+        None => 0,
+    };
+    let top_order_priority = body.top_order_priority.unwrap_or(false);
+
+    let matching = match body.algorithm.to_lowercase().as_str() {
+        "fifo" => MatchingAlgorithm::Fifo,
+        "pro_rata" => MatchingAlgorithm::ProRata { min_allocation, top_order_priority },
+        "hybrid" => {
+            let fifo_bps = match body.fifo_percent.as_deref().map(math::percent_to_bps) {
+                Some(Ok(v)) if v <= 10_000 => v,
+                Some(Ok(_)) => return HttpResponse::BadRequest().body("fifo_percent must be at most 100"),
+                Some(Err(e)) => return HttpResponse::BadRequest().body(e),
+                None => return HttpResponse::BadRequest().body("hybrid matching needs fifo_percent"),
+            };
+            MatchingAlgorithm::Hybrid { fifo_bps, min_allocation, top_order_priority }
+        }
+        _ => return HttpResponse::BadRequest().body("invalid algorithm, expected fifo, pro_rata or hybrid"),
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::SetMatchingAlgorithm {
+        matching,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": msg
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/set_market_state")]
+async fn set_market_state(tx: web::Data<CommandSender>, body: Admin<SetMarketStateRequest>) -> impl Responder {
+    if body.market != MARKET_SYMBOL {
+        return HttpResponse::BadRequest().body("unknown market");
+    }
+
+    let state = match body.state.to_lowercase().as_str() {
+        "continuous" => MarketState::Continuous,
+        "halted" => MarketState::Halted,
+        "cancel_only" => MarketState::CancelOnly,
+        "post_only" => MarketState::PostOnly,
+        "auction" => MarketState::Auction,
+        _ => return HttpResponse::BadRequest().body("invalid state, expected continuous, halted, cancel_only, post_only or auction"),
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::SetMarketState {
+        state,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": msg
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[get("/admin/volatility_controls")]
+async fn get_volatility_controls(tx: web::Data<CommandSender>, _admin: Admin<IgnoredAny>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetVolatilityControls {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+    match rx.await {
+        Ok(controls) => HttpResponse::Ok().json(serde_json::json!({
+            "market": MARKET_SYMBOL,
+            "controls": controls
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/set_volatility_controls")]
+async fn set_volatility_controls(tx: web::Data<CommandSender>, body: Admin<SetVolatilityControlsRequest>) -> impl Responder {
+    if body.market != MARKET_SYMBOL {
+        return HttpResponse::BadRequest().body("unknown market");
+    }
+
+    let mut controls = VolatilityControls::new();
+    for (field, percent) in [(&mut controls.band_bps, &body.band_percent), (&mut controls.breaker_bps, &body.breaker_percent)] {
+        *field = match percent.as_deref().map(math::percent_to_bps) {
+            Some(Ok(v)) if v > 0 && v < 10_000 => Some(v),
+            Some(Ok(_)) => return HttpResponse::BadRequest().body("percent must be between 0 and 100"),
+            Some(Err(e)) => return HttpResponse::BadRequest().body(e),
+            None => None,
+        };
+    }
+    if let Some(secs) = body.breaker_window_secs {
+        if secs == 0 {
+            return HttpResponse::BadRequest().body("breaker window must be positive");
+        }
+        controls.breaker_window_ms = secs * 1000;
+    }
+    controls.breaker_action = match body.breaker_action.as_deref().map(|a| a.to_lowercase()).as_deref() {
+        None | Some("auction") => MarketState::Auction,
+        Some("halted") => MarketState::Halted,
+        Some(_) => return HttpResponse::BadRequest().body("invalid breaker action, expected auction or halted"),
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::SetVolatilityControls {
+        controls,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": msg
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/get_risk_limits")]
+async fn get_risk_limits(tx: web::Data<CommandSender>, body: Admin<GetRiskLimitsRequest>) -> impl Responder {
+    let user_id = match Uuid::parse_str(&body.user_id) {
+        Ok(v) => v,
+        Err(_) => return HttpResponse::BadRequest().body("invalid user id"),
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetRiskLimits {
+        user_id,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(limits) => HttpResponse::Ok().json(serde_json::json!({
+            "user_id": user_id,
+            "max_order_quantity": limits.max_order_quantity.map(math::sats_to_btc_string),
+            "max_order_notional": limits.max_order_notional.map(math::micro_to_price_string),
+            "max_open_orders": limits.max_open_orders,
+            "max_open_bid_notional": limits.max_open_bid_notional.map(math::micro_to_price_string),
+            "max_open_ask_notional": limits.max_open_ask_notional.map(math::micro_to_price_string),
+            "max_orders_per_second": limits.max_orders_per_second,
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/set_risk_limits")]
+async fn set_risk_limits(tx: web::Data<CommandSender>, body: Admin<SetRiskLimitsRequest>) -> impl Responder {
+    let user_id = match Uuid::parse_str(&body.user_id) {
+        Ok(v) => v,
+        Err(_) => return HttpResponse::BadRequest().body("invalid user id"),
+    };
+
+    let max_order_quantity = match body.max_order_quantity.as_deref().map(math::btc_to_sats_str).transpose() {
+        Ok(v) => v,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut notionals = Vec::new();
+    for notional in [&body.max_order_notional, &body.max_open_bid_notional, &body.max_open_ask_notional] {
+        match notional.as_deref().map(math::usdc_to_micro_usdc).transpose() {
+            Ok(v) => notionals.push(v),
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        }
+    }
+
+    let limits = RiskLimits {
+        max_order_quantity,
+        max_order_notional: notionals[0],
+        max_open_orders: body.max_open_orders,
+        max_open_bid_notional: notionals[1],
+        max_open_ask_notional: notionals[2],
+        max_orders_per_second: body.max_orders_per_second,
+    };
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::SetRiskLimits {
+        user_id,
+        limits,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": msg
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/start_auction")]
+async fn start_auction(tx: web::Data<CommandSender>, body: Admin<MarketRequest>) -> impl Responder {
+    if body.market != MARKET_SYMBOL {
+        return HttpResponse::BadRequest().body("unknown market");
+    }
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::StartAuction {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": msg
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/end_auction")]
+async fn end_auction(tx: web::Data<CommandSender>, body: Admin<MarketRequest>) -> impl Responder {
+    if body.market != MARKET_SYMBOL {
+        return HttpResponse::BadRequest().body("unknown market");
+    }
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::EndAuction {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(msg) => HttpResponse::Ok().json(serde_json::json!({
+            "msg": msg
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[get("/auction")]
+async fn get_auction(tx: web::Data<CommandSender>) -> impl Responder {
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetAuctionInfo {
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(info) => HttpResponse::Ok().json(serde_json::json!({
+            "market": info.market,
+            "state": info.state,
+            "indicative_price": info.indicative_price.map(math::micro_to_price_string),
+            "indicative_volume": math::sats_to_btc_string(info.indicative_volume),
+            "imbalance": format!("{}{}", if info.imbalance < 0 { "-" } else { "" }, math::sats_to_btc_string(info.imbalance.unsigned_abs() as u64)),
+        })),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+#[post("/admin/create_api_key")]
+async fn create_api_key(tx: web::Data<CommandSender>, api_keys: web::Data<ApiKeys>, body: Admin<CreateApiKeyRequest>) -> impl Responder {
+    let user_id = match Uuid::parse_str(&body.user_id) {
+        Ok(v) => v,
+        Err(_) => return HttpResponse::BadRequest().body("invalid user id"),
+    };
+    let mut scopes = Vec::new();
+    for scope in &body.scopes {
+        match Scope::parse(scope) {
+            Some(s) if !scopes.contains(&s) => scopes.push(s),
+            Some(_) => {}
+            None => return HttpResponse::BadRequest().body(format!("invalid scope: {scope}")),
+        }
+    }
+    if scopes.is_empty() {
+        return HttpResponse::BadRequest().body("at least one scope is required");
+    }
+
+    let (tx_oneshot, rx) = oneshot::channel();
+    if let Err(e) = tx.send(engine::EngineCommand::GetBalances {
+        user_id,
+        tx_oneshot
+    }) {
+        return engine_unavailable(e);
+    }
+
+    match rx.await {
+        Ok(Some(_)) => {
+            let (api_key, secret) = api_keys.create(user_id, scopes.clone());
+            HttpResponse::Ok().json(serde_json::json!({
+                "api_key": api_key,
+                "secret": secret,
+                "user_id": user_id,
+                "scopes": scopes,
+            }))
+        }
+        Ok(None) => HttpResponse::NotFound().body("user not found"),
+        Err(_) => HttpResponse::InternalServerError().body("engine failed to respond"),
+    }
+}
+
+/// A back office key for `/ws/drop_copy`, limited to `accounts` if given.
+#[post("/admin/create_drop_copy_key")]
+async fn create_drop_copy_key(api_keys: web::Data<ApiKeys>, body: Admin<CreateDropCopyKeyRequest>) -> impl Responder {
+    let accounts = match &body.accounts {
+        Some(ids) => match ids.iter().map(|id| Uuid::parse_str(id)).collect::<Result<HashSet<_>, _>>() {
+            Ok(accounts) if !accounts.is_empty() => Some(accounts),
+            Ok(_) => return HttpResponse::BadRequest().body("accounts must not be empty, leave it out to copy every user"),
+            Err(_) => return HttpResponse::BadRequest().body("invalid user id in accounts"),
+        },
+        None => None,
+    };
+
+    let (api_key, secret) = api_keys.create_drop_copy(accounts.clone());
+    HttpResponse::Ok().json(serde_json::json!({
+        "api_key": api_key,
+        "secret": secret,
+        "scopes": [Scope::DropCopy],
+        "accounts": accounts,
+    }))
+}
+
+#[post("/admin/revoke_api_key")]
+async fn revoke_api_key(api_keys: web::Data<ApiKeys>, body: Admin<RevokeApiKeyRequest>) -> impl Responder {
+    if api_keys.revoke(&body.api_key) {
+        HttpResponse::Ok().json(serde_json::json!({
+            "msg": "api key revoked"
+        }))
+    } else {
+        HttpResponse::NotFound().body("api key not found")
+    }
+}
+
+#[get("/admin/engine_queue")]
+async fn get_engine_queue(tx: web::Data<CommandSender>, _admin: Admin<IgnoredAny>) -> impl Responder {
+    HttpResponse::Ok().json(serde_json::json!({
+        "depth": tx.depth(),
+        "capacity": tx.capacity(),
+        "rejected": tx.rejected(),
+    }))
+}
@@ -44,6 +44,20 @@ pub fn price_to_micro_str(s: &str) -> Result<u64, String> {
     Ok(micro.to_u64().ok_or("overflow converting price to u64")?)
 }
 
+pub fn signed_price_to_micro_str(s: &str) -> Result<i64, String> {
+    let dec = Decimal::from_str(s).map_err(|e| e.to_string())?;
+
+    let micro = dec
+        .checked_mul(Decimal::from(MICRO_USDC))
+        .ok_or("overflow converting price to micro")?;
+
+    if !micro.fract().is_zero() {
+        return Err("price has more than 6 decimals".into());
+    }
+
+    Ok(micro.to_i64().ok_or("overflow converting price to i64")?)
+}
+
 pub fn micro_to_price_string(micro: u64) -> String {
     let dec = Decimal::from(micro) / Decimal::from(1_000_000u64);
     dec.normalize().to_string()
@@ -53,3 +67,16 @@ pub fn sats_to_btc_string(sats: u64) -> String {
     let dec = Decimal::from(sats) / Decimal::from(100_000_000u64);
     dec.normalize().to_string()
 }
+
+pub fn percent_to_bps(s: &str) -> Result<u32, String> {
+    let dec = Decimal::from_str(s).map_err(|e| e.to_string())?;
+    let bps = dec
+        .checked_mul(Decimal::from(100u32))
+        .ok_or("overflow converting percent to bps")?;
+
+    if !bps.fract().is_zero() {
+        return Err("percent has more than 2 decimals".into());
+    }
+
+    Ok(bps.to_u32().ok_or("overflow converting bps to u32")?)
+}
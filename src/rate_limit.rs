@@ -0,0 +1,444 @@
+use actix_web::body::{BoxBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::InternalError;
+use actix_web::http::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use actix_web::middleware::Next;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, web};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const LIMIT_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+pub const REMAINING_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+/// Which bucket the other rate limit headers describe, e.g. `key-orders`.
+pub const BUCKET_HEADER: HeaderName = HeaderName::from_static("x-ratelimit-bucket");
+
+/// Buckets idle for this long are full again and get dropped.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Order entry and queries draw from separate buckets, so reading doesn't eat into the
+/// budget for placing and cancelling orders.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Class {
+    Orders,
+    Queries,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Weight {
+    pub class: Class,
+    pub cost: u32,
+}
+
+/// What a request to `path` costs. Snapshots and history cost more than a single lookup.
+pub fn endpoint_weight(path: &str) -> Weight {
+    let (class, cost) = match path {
+        "/create_order" | "/create_pegged_order" | "/create_trailing_stop" | "/cancel_order" => (Class::Orders, 1),
+        // the first order of a batch; the rest are charged by `charge_batch` once parsed
+        "/batch_orders" | "/batch_cancel" => (Class::Orders, 1),
+        "/l3_snapshot" => (Class::Queries, 10),
+        "/candles" | "/l3_updates" => (Class::Queries, 5),
+        "/get_depth" | "/get_user_orders" | "/get_user_trailing_stops" => (Class::Queries, 2),
+        _ => (Class::Queries, 1),
+    };
+    Weight { class, cost }
+}
+
+/// Refill rate and capacity of a token bucket.
+#[derive(Debug, Clone, Copy)]
+pub struct Limit {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+impl Limit {
+    /// Parses `<per second>/<burst>`, e.g. `10/20`.
+    pub fn parse(s: &str) -> Option<Self> {
+        let (rate, burst) = s.split_once('/')?;
+        let limit = Limit {
+            per_second: rate.trim().parse().ok()?,
+            burst: burst.trim().parse().ok()?,
+        };
+        (limit.per_second > 0.0 && limit.burst > 0).then_some(limit)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitConfig {
+    pub ip_orders: Limit,
+    pub ip_queries: Limit,
+    pub key_orders: Limit,
+    pub key_queries: Limit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            ip_orders: Limit { per_second: 20.0, burst: 40 },
+            ip_queries: Limit { per_second: 50.0, burst: 100 },
+            key_orders: Limit { per_second: 10.0, burst: 20 },
+            key_queries: Limit { per_second: 20.0, burst: 40 },
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Defaults, overridden by `RATE_LIMIT_IP_ORDERS`, `RATE_LIMIT_IP_QUERIES`,
+    /// `RATE_LIMIT_KEY_ORDERS` and `RATE_LIMIT_KEY_QUERIES`, each `<per second>/<burst>`.
+    pub fn from_env() -> Result<Self, String> {
+        let mut config = Self::default();
+        let overrides = [
+            ("RATE_LIMIT_IP_ORDERS", &mut config.ip_orders),
+            ("RATE_LIMIT_IP_QUERIES", &mut config.ip_queries),
+            ("RATE_LIMIT_KEY_ORDERS", &mut config.key_orders),
+            ("RATE_LIMIT_KEY_QUERIES", &mut config.key_queries),
+        ];
+        for (name, limit) in overrides {
+            if let Ok(value) = std::env::var(name) {
+                *limit = Limit::parse(&value).ok_or(format!("{name} must be <per second>/<burst>, got {value:?}"))?;
+            }
+        }
+        Ok(config)
+    }
+
+    fn limit(&self, subject: &Subject, class: Class) -> Limit {
+        match (subject, class) {
+            (Subject::Ip(_), Class::Orders) => self.ip_orders,
+            (Subject::Ip(_), Class::Queries) => self.ip_queries,
+            (Subject::Key(_), Class::Orders) => self.key_orders,
+            (Subject::Key(_), Class::Queries) => self.key_queries,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    Ip(IpAddr),
+    Key(String),
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Outcome of charging one bucket.
+#[derive(Debug, Clone)]
+pub struct Status {
+    bucket: &'static str,
+    limit: u32,
+    remaining: u32,
+    /// Set when the request was refused: how long until it would fit.
+    retry_after: Option<Duration>,
+}
+
+impl Status {
+    pub fn is_limited(&self) -> bool {
+        self.retry_after.is_some()
+    }
+
+    /// A 429 carrying `Retry-After` (whole seconds, rounded up) and the rate limit headers.
+    pub fn too_many_requests(&self) -> HttpResponse {
+        let retry_after = self.retry_after.unwrap_or_default().as_millis().div_ceil(1000).max(1);
+        let mut response = HttpResponse::TooManyRequests()
+            .insert_header((RETRY_AFTER, retry_after.to_string()))
+            .body(format!("rate limit exceeded, retry in {retry_after}s"));
+        self.write_headers(&mut response);
+        response
+    }
+
+    fn write_headers<B>(&self, response: &mut actix_web::HttpResponse<B>) {
+        let headers = response.headers_mut();
+        headers.insert(LIMIT_HEADER, HeaderValue::from(self.limit));
+        headers.insert(REMAINING_HEADER, HeaderValue::from(self.remaining));
+        headers.insert(BUCKET_HEADER, HeaderValue::from_static(self.bucket));
+    }
+}
+
+/// Token buckets per client IP and per API key, each split into order and query classes.
+#[derive(Debug)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<(Subject, Class), Bucket>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Charges an API key for a request that has already been authenticated.
+    pub fn charge_key(&self, api_key: &str, weight: Weight) -> Status {
+        self.charge(Subject::Key(api_key.to_string()), weight)
+    }
+
+    fn charge(&self, subject: Subject, weight: Weight) -> Status {
+        self.charge_rest(subject, weight, 0, Instant::now())
+    }
+
+    /// Charges the rest of a request of which `prepaid` was charged earlier.
+    fn charge_rest(&self, subject: Subject, weight: Weight, prepaid: u32, now: Instant) -> Status {
+        self.charge_all(vec![subject], weight, prepaid, now).pop().unwrap()
+    }
+
+    /// Charges every subject for the same request, or none of them: if any bucket can't
+    /// afford it nothing is debited. Statuses come back in the order of `subjects`.
+    fn charge_all(&self, subjects: Vec<Subject>, weight: Weight, prepaid: u32, now: Instant) -> Vec<Status> {
+        self.sweep(now);
+        let mut buckets = self.buckets.lock().unwrap();
+        let mut charged = Vec::with_capacity(subjects.len());
+        for subject in subjects {
+            let limit = self.config.limit(&subject, weight.class);
+            let bucket_name = match (&subject, weight.class) {
+                (Subject::Ip(_), Class::Orders) => "ip-orders",
+                (Subject::Ip(_), Class::Queries) => "ip-queries",
+                (Subject::Key(_), Class::Orders) => "key-orders",
+                (Subject::Key(_), Class::Queries) => "key-queries",
+            };
+            // a request heavier than the whole bucket goes through once it's full and leaves
+            // it in debt, or it could never go through at all
+            let needed = (weight.cost + prepaid).min(limit.burst).saturating_sub(prepaid) as f64;
+
+            let key = (subject, weight.class);
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket {
+                tokens: limit.burst as f64,
+                updated: now,
+            });
+            let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+            bucket.updated = now;
+
+            let retry_after = (bucket.tokens < needed)
+                .then(|| Duration::from_secs_f64((needed - bucket.tokens) / limit.per_second));
+            charged.push((key, Status {
+                bucket: bucket_name,
+                limit: limit.burst,
+                remaining: bucket.tokens.max(0.0).floor() as u32,
+                retry_after,
+            }));
+        }
+
+        let limited = charged.iter().any(|(_, status)| status.is_limited());
+        charged
+            .into_iter()
+            .map(|(key, mut status)| {
+                if !limited {
+                    let bucket = buckets.get_mut(&key).unwrap();
+                    bucket.tokens -= weight.cost as f64;
+                    status.remaining = bucket.tokens.max(0.0).floor() as u32;
+                }
+                status
+            })
+            .collect()
+    }
+
+    /// Drops buckets that have been idle long enough to be full again, so clients that
+    /// went away don't pile up.
+    fn sweep(&self, now: Instant) {
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+        *last_sweep = now;
+
+        let config = self.config;
+        self.buckets.lock().unwrap().retain(|(subject, class), bucket| {
+            let limit = config.limit(subject, *class);
+            let refilled = bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * limit.per_second;
+            refilled < limit.burst as f64
+        });
+    }
+}
+
+/// Charges the key behind an authenticated request. The status is kept on the request so
+/// the response reports whichever of the IP and key buckets is closer to empty.
+pub fn charge_authenticated(req: &HttpRequest, api_key: &str) -> Result<(), actix_web::Error> {
+    let Some(limiter) = req.app_data::<web::Data<RateLimiter>>() else {
+        return Ok(());
+    };
+    let status = limiter.charge_key(api_key, endpoint_weight(req.path()));
+    req.extensions_mut().insert(status.clone());
+    if status.is_limited() {
+        return Err(InternalError::from_response("rate limit exceeded", status.too_many_requests()).into());
+    }
+    Ok(())
+}
+
+/// Charges the client IP and the key for the orders of a batch of `items` after the first,
+/// which was charged like a single order before the body was read. A 429 response if
+/// either can't afford them.
+pub fn charge_batch(req: &HttpRequest, api_key: &str, items: usize) -> Option<HttpResponse> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>()?;
+    let prepaid = endpoint_weight(req.path()).cost;
+    let weight = Weight { class: Class::Orders, cost: (items as u32).saturating_sub(prepaid) };
+    if weight.cost == 0 {
+        return None;
+    }
+
+    // the IP isn't charged for orders the key can't afford, and the other way round
+    let mut subjects = vec![Subject::Key(api_key.to_string())];
+    if let Some(peer) = req.peer_addr() {
+        subjects.push(Subject::Ip(peer.ip()));
+    }
+    let mut statuses = limiter.charge_all(subjects, weight, prepaid, Instant::now()).into_iter();
+    let status = statuses.next().unwrap();
+    if let Some(ip_status) = statuses.next()
+        && ip_status.is_limited()
+    {
+        return Some(ip_status.too_many_requests());
+    }
+    req.extensions_mut().insert(status.clone());
+    status.is_limited().then(|| status.too_many_requests())
+}
+
+/// Middleware charging the client IP for every request. Uses the socket's peer address, not
+/// `X-Forwarded-For`, which the client controls.
+pub async fn limit_by_ip(req: ServiceRequest, next: Next<impl MessageBody + 'static>) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    let (Some(limiter), Some(peer)) = (limiter, req.peer_addr()) else {
+        return Ok(next.call(req).await?.map_into_boxed_body());
+    };
+
+    let ip_status = limiter.charge(Subject::Ip(peer.ip()), endpoint_weight(req.path()));
+    if ip_status.is_limited() {
+        return Ok(req.into_response(ip_status.too_many_requests()));
+    }
+
+    let mut response = next.call(req).await?.map_into_boxed_body();
+    let key_status = response.request().extensions().get::<Status>().cloned();
+    let status = match key_status {
+        Some(key_status) if key_status.is_limited() || key_status.remaining < ip_status.remaining => key_status,
+        _ => ip_status,
+    };
+    if !status.is_limited() {
+        status.write_headers(response.response_mut());
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORDER: Weight = Weight { class: Class::Orders, cost: 1 };
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitConfig {
+            ip_orders: Limit { per_second: 10.0, burst: 20 },
+            ip_queries: Limit { per_second: 10.0, burst: 20 },
+            key_orders: Limit { per_second: 10.0, burst: 4 },
+            key_queries: Limit { per_second: 10.0, burst: 20 },
+        })
+    }
+
+    fn key() -> Subject {
+        Subject::Key("key".into())
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_at_the_rate() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for remaining in (0..4).rev() {
+            let status = limiter.charge_rest(key(), ORDER, 0, start);
+            assert!(!status.is_limited());
+            assert_eq!((status.bucket, status.limit, status.remaining), ("key-orders", 4, remaining));
+        }
+        let status = limiter.charge_rest(key(), ORDER, 0, start);
+        assert_eq!(status.retry_after, Some(ms(100)));
+
+        // a refused request costs nothing, and 10 a second is one every 100ms
+        let status = limiter.charge_rest(key(), ORDER, 0, start + ms(50));
+        assert!(status.retry_after.unwrap().abs_diff(ms(50)) < ms(1));
+        assert!(!limiter.charge_rest(key(), ORDER, 0, start + ms(100)).is_limited());
+        // refills stop at the burst
+        let status = limiter.charge_rest(key(), ORDER, 0, start + ms(10_000));
+        assert_eq!(status.remaining, 3);
+    }
+
+    #[test]
+    fn weight_is_drawn_from_its_own_class_and_subject() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let snapshot = Weight { class: Class::Queries, cost: 15 };
+        assert_eq!(limiter.charge_rest(key(), snapshot, 0, now).remaining, 5);
+        let status = limiter.charge_rest(key(), snapshot, 0, now);
+        assert_eq!(status.retry_after, Some(ms(1_000)));
+
+        // order entry and other subjects are untouched
+        assert_eq!(limiter.charge_rest(key(), ORDER, 0, now).remaining, 3);
+        assert_eq!(limiter.charge_rest(Subject::Key("other".into()), snapshot, 0, now).remaining, 5);
+        let ip = Subject::Ip(IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(limiter.charge_rest(ip, snapshot, 0, now).bucket, "ip-queries");
+    }
+
+    #[test]
+    fn requests_heavier_than_the_bucket_wait_for_it_to_fill_and_leave_it_in_debt() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let batch = Weight { class: Class::Orders, cost: 6 };
+        limiter.charge_rest(key(), ORDER, 0, now);
+        assert_eq!(limiter.charge_rest(key(), batch, 0, now).retry_after, Some(ms(100)));
+
+        let status = limiter.charge_rest(key(), batch, 0, now + ms(100));
+        assert_eq!((status.is_limited(), status.remaining), (false, 0));
+        // 2 tokens of debt to pay off before the next order fits
+        assert!(limiter.charge_rest(key(), ORDER, 0, now + ms(100)).retry_after.unwrap().abs_diff(ms(300)) < ms(1));
+    }
+
+    #[test]
+    fn prepaid_tokens_count_towards_the_bucket_size() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let rest = Weight { class: Class::Orders, cost: 5 };
+        // with 1 of 6 prepaid, the rest only needs the 3 tokens the full bucket has left
+        limiter.charge_rest(key(), ORDER, 0, now);
+        let status = limiter.charge_rest(key(), rest, 1, now);
+        assert!(!status.is_limited());
+        assert_eq!(limiter.charge_rest(key(), ORDER, 0, now).retry_after, Some(ms(300)));
+    }
+
+    #[test]
+    fn charging_several_buckets_debits_none_unless_all_have_room() {
+        let limiter = limiter();
+        let now = Instant::now();
+        let ip = || Subject::Ip(IpAddr::from([127, 0, 0, 1]));
+        let batch = Weight { class: Class::Orders, cost: 3 };
+
+        let statuses = limiter.charge_all(vec![key(), ip()], batch, 0, now);
+        assert_eq!(statuses.iter().map(|s| (s.bucket, s.remaining)).collect::<Vec<_>>(), [("key-orders", 1), ("ip-orders", 17)]);
+
+        // the key is out of room, so the IP keeps its tokens
+        let statuses = limiter.charge_all(vec![key(), ip()], batch, 0, now);
+        assert!(statuses[0].is_limited());
+        assert!(!statuses[1].is_limited());
+        assert_eq!(limiter.charge_rest(ip(), ORDER, 0, now).remaining, 16);
+        assert_eq!(limiter.charge_rest(key(), ORDER, 0, now).remaining, 0);
+    }
+
+    #[test]
+    fn retry_after_header_rounds_up_to_whole_seconds() {
+        let status = |retry_after| Status { bucket: "key-orders", limit: 4, remaining: 0, retry_after: Some(retry_after) };
+        let header = |status: Status| status.too_many_requests().headers().get(RETRY_AFTER).unwrap().to_str().unwrap().to_string();
+        assert_eq!(header(status(ms(1))), "1");
+        assert_eq!(header(status(ms(1_000))), "1");
+        assert_eq!(header(status(ms(1_001))), "2");
+        assert_eq!(header(status(Duration::ZERO)), "1");
+
+        let response = status(ms(2_500)).too_many_requests();
+        assert_eq!(response.status(), actix_web::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers().get(BUCKET_HEADER).unwrap(), "key-orders");
+        assert_eq!(response.headers().get(REMAINING_HEADER).unwrap(), "0");
+    }
+}
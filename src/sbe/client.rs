@@ -0,0 +1,94 @@
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+use super::{CancelOrder, LOGON_SIGNATURE_SUFFIX, Logon, Message, NewOrder, Side, take_message};
+
+/// Blocking order entry client over one TCP connection.
+///
+/// ```no_run
+/// use single_threaded_orderbook::sbe::{Message, Side, client::Client};
+///
+/// let mut client = Client::connect("127.0.0.1:9879", "<api key>", "<secret>")?;
+/// client.new_order(1, Side::Buy, 60_000_000_000, 10_000)?;
+/// if let Message::ExecutionReport(report) = client.recv()? {
+///     println!("{:?} {:?}", report.exec_type, report.ord_status);
+/// }
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct Client {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl Client {
+    /// Connects and logs on with an API key that has the `trade` scope. A refused logon is
+    /// a `PermissionDenied` error carrying the server's reason.
+    pub fn connect(addr: impl ToSocketAddrs, api_key: &str, secret: &str) -> io::Result<Self> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        let mut client = Client { stream, buf: Vec::new(), out: Vec::new() };
+
+        let logon = signed_logon(api_key, secret, now_millis(), Uuid::new_v4().as_u64_pair().0)?;
+        client.send(&Message::Logon(logon))?;
+        match client.recv()? {
+            Message::LogonResponse(response) if response.accepted => Ok(client),
+            Message::LogonResponse(response) => Err(io::Error::new(io::ErrorKind::PermissionDenied, response.text)),
+            other => Err(io::Error::new(io::ErrorKind::InvalidData, format!("expected a logon response, got template {}", other.template_id()))),
+        }
+    }
+
+    pub fn send(&mut self, msg: &Message) -> io::Result<()> {
+        self.out.clear();
+        msg.encode(&mut self.out);
+        self.stream.write_all(&self.out)
+    }
+
+    pub fn new_order(&mut self, cl_ord_id: u64, side: Side, price: u64, quantity: u64) -> io::Result<()> {
+        self.send(&Message::NewOrder(NewOrder { cl_ord_id, side, price, quantity }))
+    }
+
+    pub fn cancel_order(&mut self, cl_ord_id: u64, orig_cl_ord_id: u64) -> io::Result<()> {
+        self.send(&Message::CancelOrder(CancelOrder { cl_ord_id, orig_cl_ord_id }))
+    }
+
+    /// Blocks for the next message from the server.
+    pub fn recv(&mut self) -> io::Result<Message> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(msg) = take_message(&mut self.buf) {
+                return msg.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            let n = self.stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// A logon for `api_key`, signed with its secret.
+pub fn signed_logon(api_key: &str, secret: &str, timestamp: u64, nonce: u64) -> io::Result<Logon> {
+    let api_key: [u8; 32] = api_key
+        .as_bytes()
+        .try_into()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "api key must be 32 characters"))?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(nonce.to_string().as_bytes());
+    mac.update(LOGON_SIGNATURE_SUFFIX);
+    let signature = mac.finalize().into_bytes().into();
+    Ok(Logon { api_key, timestamp, nonce, signature })
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,393 @@
+//! Binary market data: sequenced book changes and trades published over UDP, and a TCP
+//! recovery service that retransmits missed messages and snapshots the book.
+//!
+//! A datagram is a packet header, the sequence number of its first message (`u64`) and how
+//! many messages it holds (`u16`), followed by the messages. Each is an SBE message header
+//! and block of schema 2, with no framing header since the block length gives its size.
+//! Sequence numbers start at 1 and run across every message type. A packet with no messages
+//! is a heartbeat, sent after a second without anything else, and carries the sequence the
+//! next message will have.
+//!
+//! Book messages also carry the engine's L3 sequence, which counts changes to the book alone.
+//! To build a book, buffer what arrives, request a snapshot, apply it, then apply the book
+//! messages with an L3 sequence above the snapshot's. A jump in the L3 sequence means book
+//! changes were lost for good and a new snapshot is needed.
+//!
+//! The recovery service speaks the framing of the order entry protocol. A
+//! `RetransmitRequest` is answered by a `RetransmitResponse` saying which sequences follow,
+//! then those messages in order; fewer than asked for, or none, once they have aged out.
+//! A `SnapshotRequest` is answered by a `SnapshotOrder` for every resting order in book
+//! priority, then a `SnapshotComplete`.
+
+use super::{Reader, Side, begin_frame, end_frame, put_message_header, put_u64, split_message, take_frame, MESSAGE_HEADER_LEN};
+
+pub const SCHEMA_ID: u16 = 2;
+pub const SCHEMA_VERSION: u16 = 1;
+pub const PACKET_HEADER_LEN: usize = 10;
+/// Packets are kept under a typical Ethernet MTU so they are never fragmented.
+pub const MAX_PACKET_LEN: usize = 1400;
+
+pub mod template {
+    pub const ADD_ORDER: u16 = 1;
+    pub const MODIFY_ORDER: u16 = 2;
+    pub const DELETE_ORDER: u16 = 3;
+    pub const EXECUTE_ORDER: u16 = 4;
+    pub const TRADE: u16 = 5;
+    pub const RETRANSMIT_REQUEST: u16 = 6;
+    pub const RETRANSMIT_RESPONSE: u16 = 7;
+    pub const SNAPSHOT_REQUEST: u16 = 8;
+    pub const SNAPSHOT_ORDER: u16 = 9;
+    pub const SNAPSHOT_COMPLETE: u16 = 10;
+}
+
+/// An order joined the back of its price level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddOrder {
+    pub l3_sequence: u64,
+    pub timestamp: u64,
+    pub order_id: [u8; 16],
+    pub side: Side,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+impl AddOrder {
+    const BLOCK_LENGTH: u16 = 49;
+}
+
+/// An order was repriced and moved to the back of its new level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModifyOrder {
+    pub l3_sequence: u64,
+    pub timestamp: u64,
+    pub order_id: [u8; 16],
+    pub side: Side,
+    pub old_price: u64,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+impl ModifyOrder {
+    const BLOCK_LENGTH: u16 = 57;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeleteOrder {
+    pub l3_sequence: u64,
+    pub timestamp: u64,
+    pub order_id: [u8; 16],
+    pub side: Side,
+    pub price: u64,
+}
+
+impl DeleteOrder {
+    const BLOCK_LENGTH: u16 = 41;
+}
+
+/// A resting order traded. One with nothing `remaining` has left the book.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecuteOrder {
+    pub l3_sequence: u64,
+    pub timestamp: u64,
+    pub order_id: [u8; 16],
+    pub side: Side,
+    pub price: u64,
+    pub executed: u64,
+    pub remaining: u64,
+}
+
+impl ExecuteOrder {
+    const BLOCK_LENGTH: u16 = 57;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trade {
+    pub trade_id: u64,
+    pub timestamp: u64,
+    pub price: u64,
+    pub quantity: u64,
+    pub taker_side: Side,
+}
+
+impl Trade {
+    const BLOCK_LENGTH: u16 = 33;
+}
+
+/// Asks for `count` messages starting at `from_sequence`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetransmitRequest {
+    pub from_sequence: u64,
+    pub count: u32,
+}
+
+impl RetransmitRequest {
+    const BLOCK_LENGTH: u16 = 12;
+}
+
+/// The next `count` frames are the messages from `from_sequence` on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetransmitResponse {
+    pub from_sequence: u64,
+    pub count: u32,
+}
+
+impl RetransmitResponse {
+    const BLOCK_LENGTH: u16 = 12;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotOrder {
+    pub order_id: [u8; 16],
+    pub side: Side,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+impl SnapshotOrder {
+    const BLOCK_LENGTH: u16 = 33;
+}
+
+/// Ends a snapshot of `orders` orders, taken once the book change `l3_sequence` was applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotComplete {
+    pub l3_sequence: u64,
+    pub orders: u64,
+}
+
+impl SnapshotComplete {
+    const BLOCK_LENGTH: u16 = 16;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MarketData {
+    AddOrder(AddOrder),
+    ModifyOrder(ModifyOrder),
+    DeleteOrder(DeleteOrder),
+    ExecuteOrder(ExecuteOrder),
+    Trade(Trade),
+    RetransmitRequest(RetransmitRequest),
+    RetransmitResponse(RetransmitResponse),
+    SnapshotRequest,
+    SnapshotOrder(SnapshotOrder),
+    SnapshotComplete(SnapshotComplete),
+}
+
+impl MarketData {
+    pub fn template_id(&self) -> u16 {
+        match self {
+            MarketData::AddOrder(_) => template::ADD_ORDER,
+            MarketData::ModifyOrder(_) => template::MODIFY_ORDER,
+            MarketData::DeleteOrder(_) => template::DELETE_ORDER,
+            MarketData::ExecuteOrder(_) => template::EXECUTE_ORDER,
+            MarketData::Trade(_) => template::TRADE,
+            MarketData::RetransmitRequest(_) => template::RETRANSMIT_REQUEST,
+            MarketData::RetransmitResponse(_) => template::RETRANSMIT_RESPONSE,
+            MarketData::SnapshotRequest => template::SNAPSHOT_REQUEST,
+            MarketData::SnapshotOrder(_) => template::SNAPSHOT_ORDER,
+            MarketData::SnapshotComplete(_) => template::SNAPSHOT_COMPLETE,
+        }
+    }
+
+    fn block_length(&self) -> u16 {
+        match self {
+            MarketData::AddOrder(_) => AddOrder::BLOCK_LENGTH,
+            MarketData::ModifyOrder(_) => ModifyOrder::BLOCK_LENGTH,
+            MarketData::DeleteOrder(_) => DeleteOrder::BLOCK_LENGTH,
+            MarketData::ExecuteOrder(_) => ExecuteOrder::BLOCK_LENGTH,
+            MarketData::Trade(_) => Trade::BLOCK_LENGTH,
+            MarketData::RetransmitRequest(_) => RetransmitRequest::BLOCK_LENGTH,
+            MarketData::RetransmitResponse(_) => RetransmitResponse::BLOCK_LENGTH,
+            MarketData::SnapshotRequest => 0,
+            MarketData::SnapshotOrder(_) => SnapshotOrder::BLOCK_LENGTH,
+            MarketData::SnapshotComplete(_) => SnapshotComplete::BLOCK_LENGTH,
+        }
+    }
+
+    /// Bytes `encode` appends.
+    pub fn encoded_len(&self) -> usize {
+        MESSAGE_HEADER_LEN + self.block_length() as usize
+    }
+
+    /// Appends the message header and block, as carried in a packet.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        put_message_header(out, self.block_length(), self.template_id(), SCHEMA_ID, SCHEMA_VERSION);
+        let block_start = out.len();
+
+        match self {
+            MarketData::AddOrder(m) => {
+                put_u64(out, m.l3_sequence);
+                put_u64(out, m.timestamp);
+                out.extend_from_slice(&m.order_id);
+                out.push(m.side as u8);
+                put_u64(out, m.price);
+                put_u64(out, m.quantity);
+            }
+            MarketData::ModifyOrder(m) => {
+                put_u64(out, m.l3_sequence);
+                put_u64(out, m.timestamp);
+                out.extend_from_slice(&m.order_id);
+                out.push(m.side as u8);
+                put_u64(out, m.old_price);
+                put_u64(out, m.price);
+                put_u64(out, m.quantity);
+            }
+            MarketData::DeleteOrder(m) => {
+                put_u64(out, m.l3_sequence);
+                put_u64(out, m.timestamp);
+                out.extend_from_slice(&m.order_id);
+                out.push(m.side as u8);
+                put_u64(out, m.price);
+            }
+            MarketData::ExecuteOrder(m) => {
+                put_u64(out, m.l3_sequence);
+                put_u64(out, m.timestamp);
+                out.extend_from_slice(&m.order_id);
+                out.push(m.side as u8);
+                put_u64(out, m.price);
+                put_u64(out, m.executed);
+                put_u64(out, m.remaining);
+            }
+            MarketData::Trade(m) => {
+                put_u64(out, m.trade_id);
+                put_u64(out, m.timestamp);
+                put_u64(out, m.price);
+                put_u64(out, m.quantity);
+                out.push(m.taker_side as u8);
+            }
+            MarketData::RetransmitRequest(RetransmitRequest { from_sequence, count })
+            | MarketData::RetransmitResponse(RetransmitResponse { from_sequence, count }) => {
+                put_u64(out, *from_sequence);
+                out.extend_from_slice(&count.to_le_bytes());
+            }
+            MarketData::SnapshotRequest => {}
+            MarketData::SnapshotOrder(m) => {
+                out.extend_from_slice(&m.order_id);
+                out.push(m.side as u8);
+                put_u64(out, m.price);
+                put_u64(out, m.quantity);
+            }
+            MarketData::SnapshotComplete(m) => {
+                put_u64(out, m.l3_sequence);
+                put_u64(out, m.orders);
+            }
+        }
+        debug_assert_eq!(out.len() - block_start, self.block_length() as usize);
+    }
+
+    /// Appends the message with a framing header, as sent over the recovery service.
+    pub fn encode_frame(&self, out: &mut Vec<u8>) {
+        let start = begin_frame(out);
+        self.encode(out);
+        end_frame(out, start);
+    }
+
+    /// Decodes the message at the front of `bytes`, returning it and the bytes left after it.
+    pub fn decode(bytes: &[u8]) -> Result<(Self, &[u8]), String> {
+        let parts = split_message(bytes, SCHEMA_ID)?;
+        let expected = match parts.template_id {
+            template::ADD_ORDER => AddOrder::BLOCK_LENGTH,
+            template::MODIFY_ORDER => ModifyOrder::BLOCK_LENGTH,
+            template::DELETE_ORDER => DeleteOrder::BLOCK_LENGTH,
+            template::EXECUTE_ORDER => ExecuteOrder::BLOCK_LENGTH,
+            template::TRADE => Trade::BLOCK_LENGTH,
+            template::RETRANSMIT_REQUEST => RetransmitRequest::BLOCK_LENGTH,
+            template::RETRANSMIT_RESPONSE => RetransmitResponse::BLOCK_LENGTH,
+            template::SNAPSHOT_REQUEST => 0,
+            template::SNAPSHOT_ORDER => SnapshotOrder::BLOCK_LENGTH,
+            template::SNAPSHOT_COMPLETE => SnapshotComplete::BLOCK_LENGTH,
+            other => return Err(format!("unknown template {other}")),
+        };
+        let b = &mut parts.block(expected)?;
+
+        let message = match parts.template_id {
+            template::ADD_ORDER => MarketData::AddOrder(AddOrder {
+                l3_sequence: b.u64()?,
+                timestamp: b.u64()?,
+                order_id: b.array()?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                price: b.u64()?,
+                quantity: b.u64()?,
+            }),
+            template::MODIFY_ORDER => MarketData::ModifyOrder(ModifyOrder {
+                l3_sequence: b.u64()?,
+                timestamp: b.u64()?,
+                order_id: b.array()?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                old_price: b.u64()?,
+                price: b.u64()?,
+                quantity: b.u64()?,
+            }),
+            template::DELETE_ORDER => MarketData::DeleteOrder(DeleteOrder {
+                l3_sequence: b.u64()?,
+                timestamp: b.u64()?,
+                order_id: b.array()?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                price: b.u64()?,
+            }),
+            template::EXECUTE_ORDER => MarketData::ExecuteOrder(ExecuteOrder {
+                l3_sequence: b.u64()?,
+                timestamp: b.u64()?,
+                order_id: b.array()?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                price: b.u64()?,
+                executed: b.u64()?,
+                remaining: b.u64()?,
+            }),
+            template::TRADE => MarketData::Trade(Trade {
+                trade_id: b.u64()?,
+                timestamp: b.u64()?,
+                price: b.u64()?,
+                quantity: b.u64()?,
+                taker_side: b.enum_value(Side::from_u8, "side")?,
+            }),
+            template::RETRANSMIT_REQUEST => MarketData::RetransmitRequest(RetransmitRequest {
+                from_sequence: b.u64()?,
+                count: b.u32()?,
+            }),
+            template::RETRANSMIT_RESPONSE => MarketData::RetransmitResponse(RetransmitResponse {
+                from_sequence: b.u64()?,
+                count: b.u32()?,
+            }),
+            template::SNAPSHOT_REQUEST => MarketData::SnapshotRequest,
+            template::SNAPSHOT_ORDER => MarketData::SnapshotOrder(SnapshotOrder {
+                order_id: b.array()?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                price: b.u64()?,
+                quantity: b.u64()?,
+            }),
+            _ => MarketData::SnapshotComplete(SnapshotComplete {
+                l3_sequence: b.u64()?,
+                orders: b.u64()?,
+            }),
+        };
+        Ok((message, parts.rest))
+    }
+}
+
+/// Appends a packet header. `count` is the number of messages that will follow it.
+pub fn put_packet_header(out: &mut Vec<u8>, sequence: u64, count: u16) {
+    put_u64(out, sequence);
+    out.extend_from_slice(&count.to_le_bytes());
+}
+
+/// The sequence number of a packet's first message, and its messages.
+pub fn decode_packet(datagram: &[u8]) -> Result<(u64, Vec<MarketData>), String> {
+    let mut header = Reader(datagram);
+    let sequence = header.u64()?;
+    let count = header.u16()?;
+    let Reader(mut rest) = header;
+
+    let mut messages = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (message, after) = MarketData::decode(rest)?;
+        messages.push(message);
+        rest = after;
+    }
+    Ok((sequence, messages))
+}
+
+/// Removes the next complete frame from the front of a recovery connection's `buf` and
+/// decodes it, as `sbe::take_message` does for order entry.
+pub fn take_message(buf: &mut Vec<u8>) -> Option<Result<MarketData, String>> {
+    take_frame(buf).map(|frame| frame.and_then(|frame| MarketData::decode(&frame).map(|(message, _)| message)))
+}
@@ -0,0 +1,605 @@
+//! Fixed-layout binary order entry protocol in the style of Simple Binary Encoding.
+//!
+//! Each message is framed by a Simple Open Framing Header: the length of the whole frame as
+//! a big-endian `u32`, then the encoding type `0x5BE0` as a big-endian `u16`. Next comes the
+//! SBE message header, four little-endian `u16`s (`block_length`, `template_id`,
+//! `schema_id`, `version`), and the message's fixed block. Integers in the block are
+//! little-endian, prices are micro USDC and quantities sats. Text, where a message has any,
+//! follows the block as a `u8` length and that many UTF-8 bytes.
+//!
+//! A block longer than this version expects is read for the fields it knows and the rest
+//! skipped, so fields can be appended to a message without breaking older readers.
+//!
+//! A connection starts with a `Logon`, answered by a `LogonResponse`. After that the client
+//! sends `NewOrder` and `CancelOrder` and gets an `ExecutionReport` for every ack, fill,
+//! cancel, expiry and reject of the orders it entered on that connection, and a
+//! `CancelReject` when a cancel fails.
+
+pub mod client;
+pub mod market_data;
+
+pub const SCHEMA_ID: u16 = 1;
+pub const SCHEMA_VERSION: u16 = 1;
+/// Simple Open Framing Header encoding type for little-endian SBE.
+pub const SBE_LITTLE_ENDIAN: u16 = 0x5BE0;
+/// Largest frame either side accepts.
+pub const MAX_FRAME_LEN: usize = 1024;
+/// What the logon signature covers after the timestamp and nonce.
+pub const LOGON_SIGNATURE_SUFFIX: &[u8] = b"SBE/logon";
+
+const FRAME_HEADER_LEN: usize = 6;
+const MESSAGE_HEADER_LEN: usize = 8;
+const MAX_TEXT_LEN: usize = u8::MAX as usize;
+
+pub mod template {
+    pub const LOGON: u16 = 1;
+    pub const LOGON_RESPONSE: u16 = 2;
+    pub const NEW_ORDER: u16 = 3;
+    pub const CANCEL_ORDER: u16 = 4;
+    pub const EXECUTION_REPORT: u16 = 5;
+    pub const CANCEL_REJECT: u16 = 6;
+}
+
+/// Declares a `u8`-backed enum with its wire values.
+macro_rules! wire_enum {
+    ($(#[$meta:meta])* $name:ident { $($(#[$vmeta:meta])* $variant:ident = $value:literal),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum $name {
+            $($(#[$vmeta])* $variant = $value),+
+        }
+
+        impl $name {
+            pub fn from_u8(value: u8) -> Option<Self> {
+                match value {
+                    $($value => Some($name::$variant),)+
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+wire_enum!(Side {
+    Buy = 1,
+    Sell = 2,
+});
+
+wire_enum!(ExecType {
+    New = 0,
+    Trade = 1,
+    Cancelled = 2,
+    Expired = 3,
+    Rejected = 4,
+});
+
+wire_enum!(OrdStatus {
+    New = 0,
+    PartiallyFilled = 1,
+    Filled = 2,
+    Cancelled = 3,
+    Expired = 4,
+    Rejected = 5,
+});
+
+/// First message on a connection. `signature` is the HMAC-SHA256 of
+/// `timestamp + nonce + "SBE/logon"` (both numbers in decimal) under the key's secret, and
+/// the key needs the `trade` scope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Logon {
+    /// The 32 character API key.
+    pub api_key: [u8; 32],
+    /// Milliseconds since the epoch; must be within the receive window of the server clock.
+    pub timestamp: u64,
+    pub nonce: u64,
+    pub signature: [u8; 32],
+}
+
+impl Logon {
+    const BLOCK_LENGTH: u16 = 80;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogonResponse {
+    pub accepted: bool,
+    /// Why the logon was refused; the server closes the connection right after.
+    pub text: String,
+}
+
+impl LogonResponse {
+    const BLOCK_LENGTH: u16 = 1;
+}
+
+/// A limit order. `cl_ord_id` is the client order id, unique among the user's orders.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NewOrder {
+    pub cl_ord_id: u64,
+    pub side: Side,
+    pub price: u64,
+    pub quantity: u64,
+}
+
+impl NewOrder {
+    const BLOCK_LENGTH: u16 = 25;
+}
+
+/// Cancels the order entered on this connection as `orig_cl_ord_id`. `cl_ord_id` identifies
+/// the request itself and comes back in a `CancelReject`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelOrder {
+    pub cl_ord_id: u64,
+    pub orig_cl_ord_id: u64,
+}
+
+impl CancelOrder {
+    const BLOCK_LENGTH: u16 = 16;
+}
+
+/// Something that happened to an order. `cl_ord_id` is always the order's own client order
+/// id, including on the report confirming a cancel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionReport {
+    pub cl_ord_id: u64,
+    /// The exchange's order id as UUID bytes, all zero for an order that was rejected.
+    pub order_id: [u8; 16],
+    pub exec_type: ExecType,
+    pub ord_status: OrdStatus,
+    pub side: Side,
+    pub price: u64,
+    pub quantity: u64,
+    pub cum_quantity: u64,
+    pub leaves_quantity: u64,
+    /// Price and quantity of this fill; zero unless `exec_type` is `Trade`.
+    pub last_price: u64,
+    pub last_quantity: u64,
+    /// Same id as the trade on the public trades channel; zero unless `exec_type` is `Trade`.
+    pub trade_id: u64,
+    /// Milliseconds since the epoch.
+    pub transact_time: u64,
+    /// The reason for a reject.
+    pub text: String,
+}
+
+impl ExecutionReport {
+    const BLOCK_LENGTH: u16 = 91;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelReject {
+    pub cl_ord_id: u64,
+    pub orig_cl_ord_id: u64,
+    pub text: String,
+}
+
+impl CancelReject {
+    const BLOCK_LENGTH: u16 = 16;
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Logon(Logon),
+    LogonResponse(LogonResponse),
+    NewOrder(NewOrder),
+    CancelOrder(CancelOrder),
+    ExecutionReport(ExecutionReport),
+    CancelReject(CancelReject),
+}
+
+impl Message {
+    pub fn template_id(&self) -> u16 {
+        match self {
+            Message::Logon(_) => template::LOGON,
+            Message::LogonResponse(_) => template::LOGON_RESPONSE,
+            Message::NewOrder(_) => template::NEW_ORDER,
+            Message::CancelOrder(_) => template::CANCEL_ORDER,
+            Message::ExecutionReport(_) => template::EXECUTION_REPORT,
+            Message::CancelReject(_) => template::CANCEL_REJECT,
+        }
+    }
+
+    fn block_length(&self) -> u16 {
+        match self {
+            Message::Logon(_) => Logon::BLOCK_LENGTH,
+            Message::LogonResponse(_) => LogonResponse::BLOCK_LENGTH,
+            Message::NewOrder(_) => NewOrder::BLOCK_LENGTH,
+            Message::CancelOrder(_) => CancelOrder::BLOCK_LENGTH,
+            Message::ExecutionReport(_) => ExecutionReport::BLOCK_LENGTH,
+            Message::CancelReject(_) => CancelReject::BLOCK_LENGTH,
+        }
+    }
+
+    /// Appends the framed message to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let start = begin_frame(out);
+        put_message_header(out, self.block_length(), self.template_id(), SCHEMA_ID, SCHEMA_VERSION);
+        let block_start = out.len();
+
+        match self {
+            Message::Logon(m) => {
+                out.extend_from_slice(&m.api_key);
+                put_u64(out, m.timestamp);
+                put_u64(out, m.nonce);
+                out.extend_from_slice(&m.signature);
+            }
+            Message::LogonResponse(m) => {
+                out.push(m.accepted as u8);
+            }
+            Message::NewOrder(m) => {
+                put_u64(out, m.cl_ord_id);
+                out.push(m.side as u8);
+                put_u64(out, m.price);
+                put_u64(out, m.quantity);
+            }
+            Message::CancelOrder(m) => {
+                put_u64(out, m.cl_ord_id);
+                put_u64(out, m.orig_cl_ord_id);
+            }
+            Message::ExecutionReport(m) => {
+                put_u64(out, m.cl_ord_id);
+                out.extend_from_slice(&m.order_id);
+                out.extend_from_slice(&[m.exec_type as u8, m.ord_status as u8, m.side as u8]);
+                for field in [m.price, m.quantity, m.cum_quantity, m.leaves_quantity, m.last_price, m.last_quantity, m.trade_id, m.transact_time] {
+                    put_u64(out, field);
+                }
+            }
+            Message::CancelReject(m) => {
+                put_u64(out, m.cl_ord_id);
+                put_u64(out, m.orig_cl_ord_id);
+            }
+        }
+        debug_assert_eq!(out.len() - block_start, self.block_length() as usize);
+
+        match self {
+            Message::LogonResponse(LogonResponse { text, .. })
+            | Message::ExecutionReport(ExecutionReport { text, .. })
+            | Message::CancelReject(CancelReject { text, .. }) => put_text(out, text),
+            _ => {}
+        }
+
+        end_frame(out, start);
+    }
+
+    fn decode(frame: &[u8]) -> Result<Self, String> {
+        let parts = split_message(frame, SCHEMA_ID)?;
+        let template_id = parts.template_id;
+        let expected = match template_id {
+            template::LOGON => Logon::BLOCK_LENGTH,
+            template::LOGON_RESPONSE => LogonResponse::BLOCK_LENGTH,
+            template::NEW_ORDER => NewOrder::BLOCK_LENGTH,
+            template::CANCEL_ORDER => CancelOrder::BLOCK_LENGTH,
+            template::EXECUTION_REPORT => ExecutionReport::BLOCK_LENGTH,
+            template::CANCEL_REJECT => CancelReject::BLOCK_LENGTH,
+            other => return Err(format!("unknown template {other}")),
+        };
+        let mut block = parts.block(expected)?;
+        let mut var_data = Reader(parts.rest);
+
+        let b = &mut block;
+        Ok(match template_id {
+            template::LOGON => Message::Logon(Logon {
+                api_key: b.array()?,
+                timestamp: b.u64()?,
+                nonce: b.u64()?,
+                signature: b.array()?,
+            }),
+            template::LOGON_RESPONSE => Message::LogonResponse(LogonResponse {
+                accepted: b.u8()? != 0,
+                text: var_data.text()?,
+            }),
+            template::NEW_ORDER => Message::NewOrder(NewOrder {
+                cl_ord_id: b.u64()?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                price: b.u64()?,
+                quantity: b.u64()?,
+            }),
+            template::CANCEL_ORDER => Message::CancelOrder(CancelOrder {
+                cl_ord_id: b.u64()?,
+                orig_cl_ord_id: b.u64()?,
+            }),
+            template::EXECUTION_REPORT => Message::ExecutionReport(ExecutionReport {
+                cl_ord_id: b.u64()?,
+                order_id: b.array()?,
+                exec_type: b.enum_value(ExecType::from_u8, "exec type")?,
+                ord_status: b.enum_value(OrdStatus::from_u8, "order status")?,
+                side: b.enum_value(Side::from_u8, "side")?,
+                price: b.u64()?,
+                quantity: b.u64()?,
+                cum_quantity: b.u64()?,
+                leaves_quantity: b.u64()?,
+                last_price: b.u64()?,
+                last_quantity: b.u64()?,
+                trade_id: b.u64()?,
+                transact_time: b.u64()?,
+                text: var_data.text()?,
+            }),
+            _ => Message::CancelReject(CancelReject {
+                cl_ord_id: b.u64()?,
+                orig_cl_ord_id: b.u64()?,
+                text: var_data.text()?,
+            }),
+        })
+    }
+}
+
+/// Removes the next complete frame from the front of `buf` and decodes it. `None` until a
+/// whole frame has arrived. A frame with a bad length or encoding type is left in place,
+/// since nothing after it can be trusted, so the caller should close the connection on an
+/// error.
+pub fn take_message(buf: &mut Vec<u8>) -> Option<Result<Message, String>> {
+    take_frame(buf).map(|frame| frame.and_then(|frame| Message::decode(&frame)))
+}
+
+/// Removes the next complete frame from the front of `buf` and returns what follows its
+/// framing header.
+fn take_frame(buf: &mut Vec<u8>) -> Option<Result<Vec<u8>, String>> {
+    if buf.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let frame_len = u32::from_be_bytes(buf[0..4].try_into().unwrap()) as usize;
+    let encoding = u16::from_be_bytes(buf[4..6].try_into().unwrap());
+    if encoding != SBE_LITTLE_ENDIAN {
+        return Some(Err(format!("unknown encoding type {encoding:#06x}")));
+    }
+    if !(FRAME_HEADER_LEN + MESSAGE_HEADER_LEN..=MAX_FRAME_LEN).contains(&frame_len) {
+        return Some(Err(format!("invalid frame length {frame_len}")));
+    }
+    if buf.len() < frame_len {
+        return None;
+    }
+    let frame = buf[FRAME_HEADER_LEN..frame_len].to_vec();
+    buf.drain(..frame_len);
+    Some(Ok(frame))
+}
+
+/// Reserves room for a framing header, returning where the frame starts for `end_frame`.
+fn begin_frame(out: &mut Vec<u8>) -> usize {
+    let start = out.len();
+    out.extend_from_slice(&[0; FRAME_HEADER_LEN]);
+    start
+}
+
+/// Fills in the framing header of the frame begun at `start`, which ends at the end of `out`.
+fn end_frame(out: &mut [u8], start: usize) {
+    let frame_len = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&frame_len.to_be_bytes());
+    out[start + 4..start + FRAME_HEADER_LEN].copy_from_slice(&SBE_LITTLE_ENDIAN.to_be_bytes());
+}
+
+fn put_message_header(out: &mut Vec<u8>, block_length: u16, template_id: u16, schema_id: u16, version: u16) {
+    for field in [block_length, template_id, schema_id, version] {
+        out.extend_from_slice(&field.to_le_bytes());
+    }
+}
+
+/// A message split at its header: the template, its block, and whatever follows the block.
+struct Parts<'a> {
+    template_id: u16,
+    block: &'a [u8],
+    rest: &'a [u8],
+}
+
+impl<'a> Parts<'a> {
+    /// A reader over the block, if it holds at least the `expected` bytes this version knows.
+    fn block(&self, expected: u16) -> Result<Reader<'a>, String> {
+        if self.block.len() < expected as usize {
+            return Err(format!("block of template {} is {} bytes, expected at least {expected}", self.template_id, self.block.len()));
+        }
+        Ok(Reader(self.block))
+    }
+}
+
+fn split_message(bytes: &[u8], schema: u16) -> Result<Parts<'_>, String> {
+    let mut header = Reader(bytes);
+    let block_length = header.u16()? as usize;
+    let template_id = header.u16()?;
+    let schema_id = header.u16()?;
+    let _version = header.u16()?;
+    if schema_id != schema {
+        return Err(format!("unknown schema {schema_id}"));
+    }
+    let Reader(rest) = header;
+    if rest.len() < block_length {
+        return Err("message is shorter than its block".into());
+    }
+    let (block, rest) = rest.split_at(block_length);
+    Ok(Parts { template_id, block, rest })
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Writes `text` as a `u8` length and its bytes, cut short at a character boundary if it
+/// doesn't fit.
+fn put_text(out: &mut Vec<u8>, text: &str) {
+    let mut len = text.len().min(MAX_TEXT_LEN);
+    while !text.is_char_boundary(len) {
+        len -= 1;
+    }
+    out.push(len as u8);
+    out.extend_from_slice(&text.as_bytes()[..len]);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        if self.0.len() < n {
+            return Err("message is truncated".into());
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn enum_value<T>(&mut self, from_u8: fn(u8) -> Option<T>, name: &str) -> Result<T, String> {
+        let value = self.u8()?;
+        from_u8(value).ok_or(format!("invalid {name} {value}"))
+    }
+
+    fn text(&mut self) -> Result<String, String> {
+        let len = self.u8()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| "text is not UTF-8".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(message: &Message) -> Vec<u8> {
+        let mut out = Vec::new();
+        message.encode(&mut out);
+        out
+    }
+
+    /// A frame holding a hand-built message header and `body`.
+    fn frame(block_length: u16, template_id: u16, schema_id: u16, body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let start = begin_frame(&mut out);
+        put_message_header(&mut out, block_length, template_id, schema_id, SCHEMA_VERSION);
+        out.extend_from_slice(body);
+        end_frame(&mut out, start);
+        out
+    }
+
+    fn execution_report(text: &str) -> ExecutionReport {
+        ExecutionReport {
+            cl_ord_id: 7,
+            order_id: [0xAB; 16],
+            exec_type: ExecType::Trade,
+            ord_status: OrdStatus::PartiallyFilled,
+            side: Side::Sell,
+            price: 100_000_000,
+            quantity: 150_000_000,
+            cum_quantity: 50_000_000,
+            leaves_quantity: 100_000_000,
+            last_price: 100_000_000,
+            last_quantity: 50_000_000,
+            trade_id: 42,
+            transact_time: 1_709_210_096_789,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn every_message_round_trips() {
+        let messages = [
+            Message::Logon(Logon { api_key: [b'k'; 32], timestamp: 1_709_210_096_789, nonce: 3, signature: [0x5A; 32] }),
+            Message::LogonResponse(LogonResponse { accepted: false, text: "invalid signature".into() }),
+            Message::NewOrder(NewOrder { cl_ord_id: 1, side: Side::Buy, price: 99_500_000, quantity: 1 }),
+            Message::CancelOrder(CancelOrder { cl_ord_id: 2, orig_cl_ord_id: 1 }),
+            Message::ExecutionReport(execution_report("")),
+            Message::CancelReject(CancelReject { cl_ord_id: 2, orig_cl_ord_id: 1, text: "order not found".into() }),
+        ];
+        let mut buf = Vec::new();
+        for message in &messages {
+            message.encode(&mut buf);
+        }
+        for message in messages {
+            assert_eq!(take_message(&mut buf), Some(Ok(message)));
+        }
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn encode_writes_the_framing_and_message_headers() {
+        let bytes = encoded(&Message::CancelOrder(CancelOrder { cl_ord_id: 2, orig_cl_ord_id: 1 }));
+        assert_eq!(bytes.len(), FRAME_HEADER_LEN + MESSAGE_HEADER_LEN + 16);
+        assert_eq!(bytes[..6], [0, 0, 0, 30, 0x5B, 0xE0]);
+        assert_eq!(bytes[6..14], [16, 0, 4, 0, 1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn long_text_is_cut_at_a_character_boundary() {
+        let mut buf = encoded(&Message::ExecutionReport(execution_report(&"é".repeat(200))));
+        let Some(Ok(Message::ExecutionReport(report))) = take_message(&mut buf) else {
+            panic!("expected an execution report");
+        };
+        assert_eq!(report.text, "é".repeat(127));
+    }
+
+    #[test]
+    fn take_message_waits_for_a_whole_frame() {
+        let full = encoded(&Message::NewOrder(NewOrder { cl_ord_id: 1, side: Side::Buy, price: 1, quantity: 1 }));
+        for len in [0, 3, FRAME_HEADER_LEN, full.len() - 1] {
+            let mut buf = full[..len].to_vec();
+            assert_eq!(take_message(&mut buf), None);
+            assert_eq!(buf.len(), len);
+        }
+    }
+
+    #[test]
+    fn take_frame_refuses_bad_framing_headers() {
+        let mut oversized = frame(0, template::NEW_ORDER, SCHEMA_ID, &[]);
+        oversized[..4].copy_from_slice(&(MAX_FRAME_LEN as u32 + 1).to_be_bytes());
+        assert_eq!(take_frame(&mut oversized), Some(Err(format!("invalid frame length {}", MAX_FRAME_LEN + 1))));
+        assert_eq!(oversized.len(), FRAME_HEADER_LEN + MESSAGE_HEADER_LEN);
+
+        let mut too_short = frame(0, template::NEW_ORDER, SCHEMA_ID, &[]);
+        too_short[..4].copy_from_slice(&(FRAME_HEADER_LEN as u32).to_be_bytes());
+        assert_eq!(take_frame(&mut too_short), Some(Err("invalid frame length 6".into())));
+
+        let mut big_endian = frame(0, template::NEW_ORDER, SCHEMA_ID, &[]);
+        big_endian[4..6].copy_from_slice(&0x5BE1u16.to_be_bytes());
+        assert_eq!(take_frame(&mut big_endian), Some(Err("unknown encoding type 0x5be1".into())));
+    }
+
+    #[test]
+    fn take_frame_accepts_a_frame_of_max_frame_len() {
+        let body = vec![0; MAX_FRAME_LEN - FRAME_HEADER_LEN - MESSAGE_HEADER_LEN];
+        let mut buf = frame(body.len() as u16, template::CANCEL_ORDER, SCHEMA_ID, &body);
+        assert_eq!(buf.len(), MAX_FRAME_LEN);
+        assert!(take_message(&mut buf).unwrap().is_ok());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_skips_fields_appended_to_the_block() {
+        let mut body = Vec::new();
+        put_u64(&mut body, 2);
+        put_u64(&mut body, 1);
+        body.extend_from_slice(&[0xFF; 4]);
+        let mut buf = frame(20, template::CANCEL_ORDER, SCHEMA_ID, &body);
+        assert_eq!(take_message(&mut buf), Some(Ok(Message::CancelOrder(CancelOrder { cl_ord_id: 2, orig_cl_ord_id: 1 }))));
+    }
+
+    #[test]
+    fn decode_rejects_malformed_messages() {
+        let cases = [
+            (frame(8, template::CANCEL_ORDER, SCHEMA_ID, &[0; 8]), "block of template 4 is 8 bytes, expected at least 16"),
+            (frame(16, template::CANCEL_ORDER, SCHEMA_ID, &[0; 8]), "message is shorter than its block"),
+            (frame(16, template::CANCEL_ORDER, 2, &[0; 16]), "unknown schema 2"),
+            (frame(16, 99, SCHEMA_ID, &[0; 16]), "unknown template 99"),
+            (frame(16, template::CANCEL_REJECT, SCHEMA_ID, &[0; 16]), "message is truncated"),
+            (frame(16, template::CANCEL_REJECT, SCHEMA_ID, &[[0; 16].as_slice(), &[5, b'a']].concat()), "message is truncated"),
+            (frame(16, template::CANCEL_REJECT, SCHEMA_ID, &[[0; 16].as_slice(), &[1, 0xFF]].concat()), "text is not UTF-8"),
+            (frame(25, template::NEW_ORDER, SCHEMA_ID, &[0; 25]), "invalid side 0"),
+        ];
+        for (mut buf, error) in cases {
+            assert_eq!(take_message(&mut buf), Some(Err(error.to_string())));
+            assert!(buf.is_empty(), "{error}");
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use actix_web::web;
+use single_threaded_orderbook::sbe::{self, CancelOrder, CancelReject, ExecType, ExecutionReport, LogonResponse, Message, NewOrder, OrdStatus};
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::auth::{ApiKeys, Scope};
+use crate::engine::EngineCommand;
+use crate::engine::client_orders::OrderKey;
+use crate::engine::orderbook::Side;
+use crate::engine::queue::CommandSender;
+use crate::engine::records::OrderStatus;
+use crate::engine::user_feed::{Fill, UserEvent, UserUpdate, UserUpdates};
+
+/// How long a new connection has to send its Logon.
+const LOGON_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What every binary order entry session needs from the rest of the server.
+#[derive(Clone)]
+pub struct Gateway {
+    pub tx: CommandSender,
+    pub user_updates: UserUpdates,
+    pub api_keys: web::Data<ApiKeys>,
+}
+
+/// Binary order entry acceptor, speaking the protocol described in `sbe`.
+///
+/// Requests on a connection are handled one at a time, in the order they arrive.
+pub async fn serve(listener: std::net::TcpListener, gateway: Gateway) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let _ = stream.set_nodelay(true);
+        actix_web::rt::spawn(run(stream, gateway.clone()));
+    }
+}
+
+impl From<sbe::Side> for Side {
+    fn from(side: sbe::Side) -> Self {
+        match side {
+            sbe::Side::Buy => Side::Bid,
+            sbe::Side::Sell => Side::Ask,
+        }
+    }
+}
+
+impl From<Side> for sbe::Side {
+    fn from(side: Side) -> Self {
+        match side {
+            Side::Bid => sbe::Side::Buy,
+            Side::Ask => sbe::Side::Sell,
+        }
+    }
+}
+
+/// An order entered on this connection, tracked so execution reports carry running totals.
+#[derive(Debug, Clone)]
+struct SbeOrder {
+    order_id: Uuid,
+    cl_ord_id: u64,
+    side: Side,
+    price: u64,
+    quantity: u64,
+    cum_quantity: u64,
+}
+
+impl SbeOrder {
+    fn leaves_quantity(&self) -> u64 {
+        self.quantity.saturating_sub(self.cum_quantity)
+    }
+
+    fn status(&self) -> OrdStatus {
+        match self.cum_quantity {
+            0 => OrdStatus::New,
+            cum if cum < self.quantity => OrdStatus::PartiallyFilled,
+            _ => OrdStatus::Filled,
+        }
+    }
+}
+
+async fn run(stream: TcpStream, gateway: Gateway) {
+    let peer = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    let (mut reader, writer) = stream.into_split();
+    let mut buf = Vec::new();
+
+    let logon = match tokio::time::timeout(LOGON_TIMEOUT, read_message(&mut reader, &mut buf)).await {
+        Ok(Ok(Some(Message::Logon(logon)))) => logon,
+        _ => return,
+    };
+    let mut session = Session {
+        writer,
+        gateway,
+        user_id: Uuid::nil(),
+        orders: HashMap::new(),
+        cl_ord_ids: HashMap::new(),
+        out: Vec::new(),
+    };
+
+    let response = match session.authenticate(&logon) {
+        Ok(user_id) => {
+            session.user_id = user_id;
+            LogonResponse { accepted: true, text: String::new() }
+        }
+        Err(e) => LogonResponse { accepted: false, text: e },
+    };
+    let accepted = response.accepted;
+    // subscribed before answering so nothing published after the logon is missed
+    let updates = accepted.then(|| session.gateway.user_updates.subscribe(session.user_id));
+    let sent = session.send(Message::LogonResponse(response)).await;
+    let (Ok(()), Some(updates)) = (sent, updates) else {
+        println!("binary order entry logon from {peer} refused");
+        return;
+    };
+    println!("binary order entry session for {} logged on from {peer}", session.user_id);
+
+    if let Err(e) = session.run(reader, buf, updates).await {
+        println!("binary order entry session for {} ended: {e}", session.user_id);
+    }
+}
+
+async fn read_message(reader: &mut OwnedReadHalf, buf: &mut Vec<u8>) -> io::Result<Option<Message>> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(msg) = sbe::take_message(buf) {
+            return msg.map(Some).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+        }
+        let n = reader.read(&mut chunk).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+}
+
+struct Session {
+    writer: OwnedWriteHalf,
+    gateway: Gateway,
+    user_id: Uuid,
+    orders: HashMap<Uuid, SbeOrder>,
+    cl_ord_ids: HashMap<u64, Uuid>,
+    /// Encoding buffer, reused for every message sent.
+    out: Vec<u8>,
+}
+
+impl Session {
+    /// The key's user, if the logon is signed by a key with the `trade` scope.
+    fn authenticate(&self, logon: &sbe::Logon) -> Result<Uuid, String> {
+        let api_key = std::str::from_utf8(&logon.api_key).map_err(|_| "unknown api key")?;
+        let key = self.gateway.api_keys
+            .verify(
+                api_key,
+                &logon.timestamp.to_string(),
+                &logon.nonce.to_string(),
+                &hex::encode(logon.signature),
+                sbe::LOGON_SIGNATURE_SUFFIX,
+            )
+            .map_err(String::from)?;
+        if !key.scopes.contains(&Scope::Trade) {
+            return Err("api key lacks the trade scope".into());
+        }
+        Ok(key.user_id)
+    }
+
+    async fn run(&mut self, mut reader: OwnedReadHalf, mut buf: Vec<u8>, mut updates: broadcast::Receiver<UserUpdate>) -> io::Result<()> {
+        let mut chunk = [0u8; 4096];
+        loop {
+            while let Some(msg) = sbe::take_message(&mut buf) {
+                match msg.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+                    Message::NewOrder(order) => self.on_new_order(order).await?,
+                    Message::CancelOrder(cancel) => self.on_cancel(cancel).await?,
+                    other => {
+                        let e = format!("unexpected template {} after logon", other.template_id());
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                    }
+                }
+            }
+
+            tokio::select! {
+                // reports already published go out before the next request is looked at
+                biased;
+                update = updates.recv() => match update {
+                    Ok(update) => self.on_user_event(update.event).await?,
+                    // dropping the connection is the only way to tell the client it missed reports
+                    Err(RecvError::Lagged(_)) => return Err(io::Error::other("execution reports fell behind")),
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                n = reader.read(&mut chunk) => {
+                    let n = n?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    buf.extend_from_slice(&chunk[..n]);
+                }
+            }
+        }
+    }
+
+    async fn on_new_order(&mut self, order: NewOrder) -> io::Result<()> {
+        let side = Side::from(order.side);
+        let (tx_oneshot, rx) = oneshot::channel();
+        let reply = match self.gateway.tx.send(EngineCommand::CreateOrder {
+            user_id: self.user_id,
+            side,
+            price: order.price,
+            quantity: order.quantity,
+            client_order_id: Some(order.cl_ord_id.to_string()),
+            tx_oneshot,
+        }) {
+            Ok(()) => rx.await.unwrap_or_else(|_| Err("engine failed to respond".into())),
+            Err(e) => Err(e.to_string()),
+        };
+
+        let report = match reply.and_then(|id| Uuid::parse_str(&id).map_err(|e| e.to_string())) {
+            Ok(order_id) => {
+                // a resent order comes back with the id it already has
+                let tracked = self.orders.entry(order_id).or_insert(SbeOrder {
+                    order_id,
+                    cl_ord_id: order.cl_ord_id,
+                    side,
+                    price: order.price,
+                    quantity: order.quantity,
+                    cum_quantity: 0,
+                });
+                self.cl_ord_ids.insert(order.cl_ord_id, order_id);
+                let status = tracked.status();
+                execution_report(tracked, ExecType::New, status)
+            }
+            Err(reason) => ExecutionReport {
+                cl_ord_id: order.cl_ord_id,
+                order_id: [0; 16],
+                exec_type: ExecType::Rejected,
+                ord_status: OrdStatus::Rejected,
+                side: order.side,
+                price: order.price,
+                quantity: order.quantity,
+                cum_quantity: 0,
+                leaves_quantity: 0,
+                last_price: 0,
+                last_quantity: 0,
+                trade_id: 0,
+                transact_time: now_millis(),
+                text: reason,
+            },
+        };
+        self.send(Message::ExecutionReport(report)).await
+    }
+
+    async fn on_cancel(&mut self, cancel: CancelOrder) -> io::Result<()> {
+        let Some(order_id) = self.cl_ord_ids.get(&cancel.orig_cl_ord_id).copied() else {
+            return self.send_cancel_reject(&cancel, "unknown order".into()).await;
+        };
+
+        let (tx_oneshot, rx) = oneshot::channel();
+        let reply = match self.gateway.tx.send(EngineCommand::CancelOrder {
+            user_id: self.user_id,
+            order: OrderKey::OrderId(order_id),
+            tx_oneshot,
+        }) {
+            Ok(()) => rx.await.unwrap_or_else(|_| Err("engine failed to respond".into())),
+            Err(e) => Err(e.to_string()),
+        };
+
+        match reply {
+            Ok(_) => match self.untrack(order_id) {
+                Some(order) => {
+                    let report = execution_report(&order, ExecType::Cancelled, OrdStatus::Cancelled);
+                    self.send(Message::ExecutionReport(report)).await
+                }
+                None => Ok(()),
+            },
+            Err(e) => self.send_cancel_reject(&cancel, e).await,
+        }
+    }
+
+    /// Reports fills, and cancels or expiries that didn't come from this connection, for the
+    /// orders it entered.
+    async fn on_user_event(&mut self, event: UserEvent) -> io::Result<()> {
+        let report = match event {
+            UserEvent::Fill(fill) => self.apply_fill(&fill),
+            UserEvent::Order(record) => match record.status {
+                OrderStatus::Cancelled => self.untrack(record.id).map(|o| execution_report(&o, ExecType::Cancelled, OrdStatus::Cancelled)),
+                OrderStatus::Expired => self.untrack(record.id).map(|o| execution_report(&o, ExecType::Expired, OrdStatus::Expired)),
+                _ => None,
+            },
+            UserEvent::Balance { .. } => None,
+        };
+        match report {
+            Some(report) => self.send(Message::ExecutionReport(report)).await,
+            None => Ok(()),
+        }
+    }
+
+    fn apply_fill(&mut self, fill: &Fill) -> Option<ExecutionReport> {
+        let order = self.orders.get_mut(&fill.order_id)?;
+        order.cum_quantity += fill.quantity;
+
+        let report = ExecutionReport {
+            last_price: fill.price,
+            last_quantity: fill.quantity,
+            trade_id: fill.trade_id,
+            ..execution_report(order, ExecType::Trade, order.status())
+        };
+        if order.leaves_quantity() == 0 {
+            self.untrack(fill.order_id);
+        }
+        Some(report)
+    }
+
+    fn untrack(&mut self, order_id: Uuid) -> Option<SbeOrder> {
+        let order = self.orders.remove(&order_id)?;
+        self.cl_ord_ids.remove(&order.cl_ord_id);
+        Some(order)
+    }
+
+    async fn send_cancel_reject(&mut self, cancel: &CancelOrder, text: String) -> io::Result<()> {
+        let reject = CancelReject {
+            cl_ord_id: cancel.cl_ord_id,
+            orig_cl_ord_id: cancel.orig_cl_ord_id,
+            text,
+        };
+        self.send(Message::CancelReject(reject)).await
+    }
+
+    async fn send(&mut self, msg: Message) -> io::Result<()> {
+        self.out.clear();
+        msg.encode(&mut self.out);
+        self.writer.write_all(&self.out).await
+    }
+}
+
+fn execution_report(order: &SbeOrder, exec_type: ExecType, ord_status: OrdStatus) -> ExecutionReport {
+    let leaves_quantity = match ord_status {
+        OrdStatus::New | OrdStatus::PartiallyFilled => order.leaves_quantity(),
+        _ => 0,
+    };
+    ExecutionReport {
+        cl_ord_id: order.cl_ord_id,
+        order_id: order.order_id.into_bytes(),
+        exec_type,
+        ord_status,
+        side: order.side.into(),
+        price: order.price,
+        quantity: order.quantity,
+        cum_quantity: order.cum_quantity,
+        leaves_quantity,
+        last_price: 0,
+        last_quantity: 0,
+        trade_id: 0,
+        transact_time: now_millis(),
+        text: String::new(),
+    }
+}
+
+fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,307 @@
+use single_threaded_orderbook::sbe::market_data::{
+    self, AddOrder, DeleteOrder, ExecuteOrder, MarketData, ModifyOrder, RetransmitRequest, RetransmitResponse,
+    SnapshotComplete, SnapshotOrder, Trade,
+};
+use std::collections::VecDeque;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::broadcast::{self, error::{RecvError, TryRecvError}};
+use tokio::sync::oneshot;
+
+use crate::engine::EngineCommand;
+use crate::engine::feed::{FeedEvent, L3Event, L3Order};
+use crate::engine::orderbook::Side;
+use crate::engine::queue::CommandSender;
+use crate::math;
+
+/// Published messages kept for retransmission.
+pub const RETRANSMIT_CAPACITY: usize = 100_000;
+/// Most messages a single RetransmitRequest gets back.
+const MAX_RETRANSMIT: u32 = 10_000;
+/// An empty packet goes out after this long without anything else.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Recently published messages, shared by the publisher and the recovery service. Sequences
+/// are contiguous, so the front message's is all that needs keeping.
+#[derive(Debug)]
+pub struct History {
+    first_sequence: u64,
+    messages: VecDeque<MarketData>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self { first_sequence: 1, messages: VecDeque::new() }
+    }
+}
+
+impl History {
+    fn push(&mut self, message: MarketData) {
+        if self.messages.len() == RETRANSMIT_CAPACITY {
+            self.messages.pop_front();
+            self.first_sequence += 1;
+        }
+        self.messages.push_back(message);
+    }
+
+    fn next_sequence(&self) -> u64 {
+        self.first_sequence + self.messages.len() as u64
+    }
+
+    /// Up to `count` messages from `from_sequence` on, and the sequence of the first one,
+    /// which is later than asked for if the earlier ones have aged out.
+    fn range(&self, from_sequence: u64, count: u32) -> (u64, Vec<MarketData>) {
+        let from_sequence = from_sequence.max(self.first_sequence);
+        let skip = (from_sequence - self.first_sequence) as usize;
+        let messages = self.messages.iter().skip(skip).take(count.min(MAX_RETRANSMIT) as usize).cloned().collect();
+        (from_sequence, messages)
+    }
+}
+
+/// Sends every book change and trade from `feed` to `dest`, sequenced and packed into as
+/// few packets as fit, and keeps them in `history` for retransmission. `dest` may be a
+/// multicast group.
+pub async fn publish(socket: std::net::UdpSocket, dest: SocketAddr, mut feed: broadcast::Receiver<FeedEvent>, history: Arc<Mutex<History>>) -> io::Result<()> {
+    if dest.ip().is_multicast() && dest.is_ipv4() {
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(1)?;
+    }
+    socket.set_nonblocking(true)?;
+    let mut publisher = Publisher {
+        socket: UdpSocket::from_std(socket)?,
+        dest,
+        history,
+        pending: Vec::new(),
+        pending_from: 1,
+        packet: Vec::with_capacity(market_data::MAX_PACKET_LEN),
+        last_sent: Instant::now(),
+    };
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            event = feed.recv() => {
+                match event {
+                    Ok(event) => publisher.add(event),
+                    Err(RecvError::Lagged(n)) => println!("market data publisher fell behind, {n} feed events lost"),
+                    Err(RecvError::Closed) => return Ok(()),
+                }
+                // whatever else is already waiting shares the packets
+                loop {
+                    match feed.try_recv() {
+                        Ok(event) => publisher.add(event),
+                        Err(TryRecvError::Lagged(n)) => println!("market data publisher fell behind, {n} feed events lost"),
+                        Err(_) => break,
+                    }
+                }
+                publisher.flush().await;
+            }
+            _ = heartbeat.tick() => {
+                if publisher.last_sent.elapsed() >= HEARTBEAT_INTERVAL {
+                    let next_sequence = publisher.history.lock().unwrap().next_sequence();
+                    publisher.send_packet(next_sequence, &[]).await;
+                }
+            }
+        }
+    }
+}
+
+struct Publisher {
+    socket: UdpSocket,
+    dest: SocketAddr,
+    history: Arc<Mutex<History>>,
+    /// Messages sequenced but not yet sent, the first of them numbered `pending_from`.
+    pending: Vec<MarketData>,
+    pending_from: u64,
+    packet: Vec<u8>,
+    last_sent: Instant,
+}
+
+impl Publisher {
+    fn add(&mut self, event: FeedEvent) {
+        let Some(message) = to_market_data(event) else {
+            return;
+        };
+        let mut history = self.history.lock().unwrap();
+        if self.pending.is_empty() {
+            self.pending_from = history.next_sequence();
+        }
+        history.push(message.clone());
+        self.pending.push(message);
+    }
+
+    async fn flush(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        let mut sequence = self.pending_from;
+        let mut rest = pending.as_slice();
+        while !rest.is_empty() {
+            let mut len = market_data::PACKET_HEADER_LEN;
+            let fits = rest
+                .iter()
+                .take_while(|message| {
+                    len += message.encoded_len();
+                    len <= market_data::MAX_PACKET_LEN
+                })
+                .count();
+            self.send_packet(sequence, &rest[..fits]).await;
+            sequence += fits as u64;
+            rest = &rest[fits..];
+        }
+    }
+
+    /// A lost packet is only logged; receivers fill the gap from the recovery service.
+    async fn send_packet(&mut self, sequence: u64, messages: &[MarketData]) {
+        self.packet.clear();
+        market_data::put_packet_header(&mut self.packet, sequence, messages.len() as u16);
+        for message in messages {
+            message.encode(&mut self.packet);
+        }
+        if let Err(e) = self.socket.send_to(&self.packet, self.dest).await {
+            println!("failed to send market data packet {sequence}: {e}");
+        }
+        self.last_sent = Instant::now();
+    }
+}
+
+/// The feed message for a book change or trade; other feed events aren't published.
+fn to_market_data(event: FeedEvent) -> Option<MarketData> {
+    let message = match event {
+        FeedEvent::L3(update) => {
+            let (l3_sequence, timestamp) = (update.sequence, update.timestamp);
+            match update.event {
+                L3Event::Add { order_id, side, price, quantity } => MarketData::AddOrder(AddOrder {
+                    l3_sequence,
+                    timestamp,
+                    order_id: order_id.into_bytes(),
+                    side: side.into(),
+                    price,
+                    quantity,
+                }),
+                L3Event::Modify { order_id, side, old_price, price, quantity } => MarketData::ModifyOrder(ModifyOrder {
+                    l3_sequence,
+                    timestamp,
+                    order_id: order_id.into_bytes(),
+                    side: side.into(),
+                    old_price,
+                    price,
+                    quantity,
+                }),
+                L3Event::Delete { order_id, side, price } => MarketData::DeleteOrder(DeleteOrder {
+                    l3_sequence,
+                    timestamp,
+                    order_id: order_id.into_bytes(),
+                    side: side.into(),
+                    price,
+                }),
+                L3Event::Execute { order_id, side, price, executed, remaining } => MarketData::ExecuteOrder(ExecuteOrder {
+                    l3_sequence,
+                    timestamp,
+                    order_id: order_id.into_bytes(),
+                    side: side.into(),
+                    price,
+                    executed,
+                    remaining,
+                }),
+            }
+        }
+        FeedEvent::Trade(trade) => MarketData::Trade(Trade {
+            trade_id: trade.trade_id,
+            timestamp: trade.timestamp,
+            price: math::price_to_micro_str(&trade.price).ok()?,
+            quantity: math::btc_to_sats_str(&trade.quantity).ok()?,
+            taker_side: trade.taker_side.into(),
+        }),
+        _ => return None,
+    };
+    Some(message)
+}
+
+/// What every recovery connection needs.
+#[derive(Clone)]
+pub struct Recovery {
+    pub tx: CommandSender,
+    pub history: Arc<Mutex<History>>,
+}
+
+/// TCP service answering retransmit and snapshot requests for the UDP feed.
+pub async fn serve_recovery(listener: std::net::TcpListener, recovery: Recovery) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let listener = TcpListener::from_std(listener)?;
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let _ = stream.set_nodelay(true);
+        let recovery = recovery.clone();
+        actix_web::rt::spawn(async move {
+            if let Err(e) = recovery.run(stream).await {
+                println!("market data recovery connection ended: {e}");
+            }
+        });
+    }
+}
+
+impl Recovery {
+    async fn run(&self, mut stream: TcpStream) -> io::Result<()> {
+        let mut buf = Vec::new();
+        let mut out = Vec::new();
+        let mut chunk = [0u8; 4096];
+        loop {
+            while let Some(request) = market_data::take_message(&mut buf) {
+                out.clear();
+                match request.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))? {
+                    MarketData::RetransmitRequest(request) => self.retransmit(&request, &mut out),
+                    MarketData::SnapshotRequest => self.snapshot(&mut out).await?,
+                    other => {
+                        let e = format!("unexpected template {} on the recovery service", other.template_id());
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                    }
+                }
+                stream.write_all(&out).await?;
+            }
+
+            let n = stream.read(&mut chunk).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    fn retransmit(&self, request: &RetransmitRequest, out: &mut Vec<u8>) {
+        let (from_sequence, messages) = self.history.lock().unwrap().range(request.from_sequence, request.count);
+        let response = RetransmitResponse { from_sequence, count: messages.len() as u32 };
+        MarketData::RetransmitResponse(response).encode_frame(out);
+        for message in messages {
+            message.encode_frame(out);
+        }
+    }
+
+    async fn snapshot(&self, out: &mut Vec<u8>) -> io::Result<()> {
+        let (tx_oneshot, rx) = oneshot::channel();
+        self.tx.send(EngineCommand::GetL3Snapshot { tx_oneshot }).map_err(|e| io::Error::other(e.to_string()))?;
+        let snapshot = rx.await.map_err(|_| io::Error::other("engine failed to respond"))?;
+
+        let bids = snapshot.bids.iter().map(|o| (Side::Bid, o));
+        let asks = snapshot.asks.iter().map(|o| (Side::Ask, o));
+        let mut orders = 0;
+        for (side, order) in bids.chain(asks) {
+            snapshot_order(side, order).encode_frame(out);
+            orders += 1;
+        }
+        let complete = SnapshotComplete { l3_sequence: snapshot.sequence, orders };
+        MarketData::SnapshotComplete(complete).encode_frame(out);
+        Ok(())
+    }
+}
+
+fn snapshot_order(side: Side, order: &L3Order) -> MarketData {
+    MarketData::SnapshotOrder(SnapshotOrder {
+        order_id: order.order_id.into_bytes(),
+        side: side.into(),
+        price: order.price,
+        quantity: order.quantity,
+    })
+}
@@ -0,0 +1,505 @@
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use actix_ws::{Closed, Message, MessageStream, Session};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::auth::{ApiKeys, Scope};
+use crate::rate_limit;
+use crate::engine::EngineCommand;
+use crate::engine::drop_copy::{DropCopyRecord, DropCopyReplay};
+use crate::engine::queue::CommandSender;
+use crate::engine::feed::FeedEvent;
+use crate::engine::market::MARKET_SYMBOL;
+use crate::engine::user_feed::{UserUpdate, UserUpdates};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum Channel {
+    Depth,
+    L3,
+    Trades,
+    Ticker,
+    Candles,
+    Bbo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { channel: Channel, market: String },
+    Unsubscribe { channel: Channel, market: String },
+    /// Asks for a fresh snapshot of a sequenced channel after the client spotted a gap.
+    Resync { channel: Channel, market: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum UserClientMessage {
+    /// Signed like an HTTP request, over `timestamp + nonce + "GET/ws/private"`.
+    Login { api_key: String, timestamp: String, nonce: String, signature: String },
+    /// Asks for a fresh snapshot after the client spotted a gap.
+    Resync,
+}
+
+#[derive(Deserialize, Debug)]
+struct DropCopyQuery {
+    /// Replay the records after this sequence before going live.
+    after: Option<u64>,
+    /// Comma separated user ids to copy; all the key may see if absent.
+    accounts: Option<String>,
+}
+
+/// What a connection is subscribed to. Sequenced channels hold the last sequence sent.
+#[derive(Debug, Default)]
+struct Subscriptions {
+    depth: Option<u64>,
+    l3: Option<u64>,
+    trades: bool,
+    ticker: bool,
+    candles: bool,
+    bbo: bool,
+}
+
+/// Public market data stream.
+///
+/// Clients send `{"op": "subscribe", "channel": "depth", "market": "BTC-USDC"}` (channels
+/// `depth`, `l3`, `trades`, `ticker`, `candles`, `bbo`) and get a snapshot followed by updates.
+/// Depth and L3 updates carry a sequence one above the last; on a gap, or when its own book
+/// no longer matches a depth message's `checksum` (see `OrderBook::checksum`), the client
+/// sends `"op": "resync"` for a new snapshot. A connection that falls behind the feed is
+/// resynced automatically.
+#[get("/ws")]
+async fn market_data(
+    req: HttpRequest,
+    body: web::Payload,
+    tx: web::Data<CommandSender>,
+    feed: web::Data<broadcast::Sender<FeedEvent>>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    let events = feed.subscribe();
+    let tx = tx.get_ref().clone();
+
+    actix_web::rt::spawn(async move {
+        let _ = run_session(session.clone(), msg_stream, tx, events).await;
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+async fn run_session(
+    mut session: Session,
+    mut msg_stream: MessageStream,
+    tx: CommandSender,
+    mut events: broadcast::Receiver<FeedEvent>,
+) -> Result<(), Closed> {
+    let mut subs = Subscriptions::default();
+
+    loop {
+        tokio::select! {
+            msg = msg_stream.recv() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(msg) => handle_client_message(&mut session, &tx, &mut subs, msg).await?,
+                    Err(e) => send_error(&mut session, &format!("invalid message: {e}")).await?,
+                },
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await?,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+            event = events.recv() => match event {
+                Ok(event) => forward(&mut session, &tx, &mut subs, event).await?,
+                Err(RecvError::Lagged(missed)) => {
+                    println!("market data subscriber lagged by {missed} events, resyncing");
+                    if subs.depth.is_some() {
+                        send_snapshot(&mut session, &tx, &mut subs, Channel::Depth).await?;
+                    }
+                    if subs.l3.is_some() {
+                        send_snapshot(&mut session, &tx, &mut subs, Channel::L3).await?;
+                    }
+                }
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn handle_client_message(session: &mut Session, tx: &CommandSender, subs: &mut Subscriptions, msg: ClientMessage) -> Result<(), Closed> {
+    let (ClientMessage::Subscribe { channel, market } | ClientMessage::Unsubscribe { channel, market } | ClientMessage::Resync { channel, market }) = &msg;
+    if market != MARKET_SYMBOL {
+        return send_error(session, &format!("unknown market {market}")).await;
+    }
+    let channel = *channel;
+
+    match msg {
+        ClientMessage::Subscribe { .. } => {
+            send(session, channel, "subscribed", serde_json::Value::Null).await?;
+            match channel {
+                Channel::Depth | Channel::L3 => send_snapshot(session, tx, subs, channel).await?,
+                Channel::Trades => subs.trades = true,
+                Channel::Candles => subs.candles = true,
+                Channel::Bbo => {
+                    subs.bbo = true;
+                    let (tx_oneshot, rx) = oneshot::channel();
+                    let _ = tx.send(EngineCommand::GetBbo { tx_oneshot });
+                    if let Ok(bbo) = rx.await {
+                        send(session, channel, "bbo", bbo).await?;
+                    }
+                }
+                Channel::Ticker => {
+                    subs.ticker = true;
+                    let (tx_oneshot, rx) = oneshot::channel();
+                    let _ = tx.send(EngineCommand::GetTicker { tx_oneshot });
+                    if let Ok(ticker) = rx.await {
+                        send(session, channel, "ticker", ticker).await?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        ClientMessage::Unsubscribe { .. } => {
+            match channel {
+                Channel::Depth => subs.depth = None,
+                Channel::L3 => subs.l3 = None,
+                Channel::Trades => subs.trades = false,
+                Channel::Ticker => subs.ticker = false,
+                Channel::Candles => subs.candles = false,
+                Channel::Bbo => subs.bbo = false,
+            }
+            send(session, channel, "unsubscribed", serde_json::Value::Null).await
+        }
+        ClientMessage::Resync { .. } => {
+            let subscribed = match channel {
+                Channel::Depth => subs.depth.is_some(),
+                Channel::L3 => subs.l3.is_some(),
+                Channel::Trades | Channel::Ticker | Channel::Candles | Channel::Bbo => return send_error(session, "only depth and l3 are sequenced").await,
+            };
+            if !subscribed {
+                return send_error(session, "not subscribed to that channel").await;
+            }
+            send_snapshot(session, tx, subs, channel).await
+        }
+    }
+}
+
+/// Sends the event if the connection wants it. An update that doesn't follow on from the
+/// last sequence sent means something was skipped, so a new snapshot goes out instead.
+async fn forward(session: &mut Session, tx: &CommandSender, subs: &mut Subscriptions, event: FeedEvent) -> Result<(), Closed> {
+    match event {
+        FeedEvent::Depth(update) => match subs.depth {
+            Some(last) if update.sequence <= last => Ok(()),
+            Some(last) if update.sequence == last + 1 => {
+                subs.depth = Some(update.sequence);
+                send(session, Channel::Depth, "update", update).await
+            }
+            Some(_) => send_snapshot(session, tx, subs, Channel::Depth).await,
+            None => Ok(()),
+        },
+        FeedEvent::L3(update) => match subs.l3 {
+            Some(last) if update.sequence <= last => Ok(()),
+            Some(last) if update.sequence == last + 1 => {
+                subs.l3 = Some(update.sequence);
+                send(session, Channel::L3, "update", update).await
+            }
+            Some(_) => send_snapshot(session, tx, subs, Channel::L3).await,
+            None => Ok(()),
+        },
+        FeedEvent::Trade(trade) if subs.trades => send(session, Channel::Trades, "trade", trade).await,
+        FeedEvent::Ticker(ticker) if subs.ticker => send(session, Channel::Ticker, "ticker", ticker).await,
+        FeedEvent::Candle(candle) if subs.candles => send(session, Channel::Candles, "candle", candle).await,
+        FeedEvent::Bbo(bbo) if subs.bbo => send(session, Channel::Bbo, "bbo", bbo).await,
+        FeedEvent::Trade(_) | FeedEvent::Ticker(_) | FeedEvent::Candle(_) | FeedEvent::Bbo(_) => Ok(()),
+    }
+}
+
+/// Fetches a full snapshot from the engine and sends it. A send failure means the engine
+/// thread is gone, which drops the reply sender. Updates already queued for this connection
+/// with a sequence at or below the snapshot's are skipped by `forward`.
+async fn send_snapshot(session: &mut Session, tx: &CommandSender, subs: &mut Subscriptions, channel: Channel) -> Result<(), Closed> {
+    match channel {
+        Channel::Depth => {
+            let (tx_oneshot, rx) = oneshot::channel();
+            let _ = tx.send(EngineCommand::GetDepth { levels: None, bucket: None, tx_oneshot });
+            match rx.await {
+                Ok(depth) => {
+                    subs.depth = Some(depth.sequence);
+                    send(session, channel, "snapshot", depth).await
+                }
+                _ => send_error(session, "engine failed to respond").await,
+            }
+        }
+        Channel::L3 => {
+            let (tx_oneshot, rx) = oneshot::channel();
+            let _ = tx.send(EngineCommand::GetL3Snapshot { tx_oneshot });
+            match rx.await {
+                Ok(snapshot) => {
+                    subs.l3 = Some(snapshot.sequence);
+                    send(session, channel, "snapshot", snapshot).await
+                }
+                _ => send_error(session, "engine failed to respond").await,
+            }
+        }
+        Channel::Trades | Channel::Ticker | Channel::Candles | Channel::Bbo => Ok(()),
+    }
+}
+
+async fn send(session: &mut Session, channel: Channel, kind: &str, data: impl Serialize) -> Result<(), Closed> {
+    let msg = serde_json::json!({
+        "channel": channel,
+        "type": kind,
+        "market": MARKET_SYMBOL,
+        "data": data
+    });
+    session.text(msg.to_string()).await
+}
+
+async fn send_error(session: &mut Session, msg: &str) -> Result<(), Closed> {
+    session.text(serde_json::json!({ "type": "error", "msg": msg }).to_string()).await
+}
+
+/// Private stream of one user's order updates, fills and balance changes.
+///
+/// The first message must be `{"op": "login", "api_key", "timestamp", "nonce", "signature"}`
+/// from a key with the `read` scope. The connection then gets a snapshot of the user's
+/// balances and open orders, followed by updates whose per-user sequence is one above the
+/// last. On a gap the client sends `{"op": "resync"}`.
+#[get("/ws/private")]
+async fn user_stream(
+    req: HttpRequest,
+    body: web::Payload,
+    tx: web::Data<CommandSender>,
+    user_updates: web::Data<UserUpdates>,
+    api_keys: web::Data<ApiKeys>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    let tx = tx.get_ref().clone();
+
+    actix_web::rt::spawn(async move {
+        let _ = run_user_session(session.clone(), msg_stream, tx, user_updates, api_keys).await;
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+async fn run_user_session(
+    mut session: Session,
+    mut msg_stream: MessageStream,
+    tx: CommandSender,
+    user_updates: web::Data<UserUpdates>,
+    api_keys: web::Data<ApiKeys>,
+) -> Result<(), Closed> {
+    // logged in user and the last sequence sent to them
+    let mut user: Option<(Uuid, u64)> = None;
+    // the user's updates, subscribed to before the first snapshot so none are missed
+    let mut updates: Option<broadcast::Receiver<UserUpdate>> = None;
+
+    loop {
+        tokio::select! {
+            msg = msg_stream.recv() => match msg {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<UserClientMessage>(&text) {
+                    Ok(UserClientMessage::Login { api_key, timestamp, nonce, signature }) => {
+                        if user.is_some() {
+                            send_error(&mut session, "already logged in").await?;
+                            continue;
+                        }
+                        match api_keys.verify(&api_key, &timestamp, &nonce, &signature, b"GET/ws/private") {
+                            Ok(key) if key.scopes.contains(&Scope::Read) => {
+                                updates = Some(user_updates.subscribe(key.user_id));
+                                user = send_user_snapshot(&mut session, &tx, key.user_id).await?;
+                            }
+                            Ok(_) => send_error(&mut session, "api key lacks the read scope").await?,
+                            Err(e) => send_error(&mut session, e).await?,
+                        }
+                    }
+                    Ok(UserClientMessage::Resync) => match user {
+                        Some((user_id, _)) => user = send_user_snapshot(&mut session, &tx, user_id).await?,
+                        None => send_error(&mut session, "log in first").await?,
+                    },
+                    Err(e) => send_error(&mut session, &format!("invalid message: {e}")).await?,
+                },
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await?,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+            update = next_update(&mut updates) => match (update, user) {
+                (Ok(update), Some((user_id, last))) => {
+                    if update.sequence == last + 1 {
+                        user = Some((user_id, update.sequence));
+                        send_user(&mut session, "update", update).await?;
+                    } else if update.sequence > last + 1 {
+                        user = send_user_snapshot(&mut session, &tx, user_id).await?;
+                    }
+                }
+                (Err(RecvError::Lagged(_)), Some((user_id, _))) => {
+                    user = send_user_snapshot(&mut session, &tx, user_id).await?;
+                }
+                (Err(RecvError::Closed), _) => return Ok(()),
+                _ => {}
+            },
+        }
+    }
+}
+
+/// The next of the user's updates, or never before they've logged in.
+async fn next_update(updates: &mut Option<broadcast::Receiver<UserUpdate>>) -> Result<UserUpdate, RecvError> {
+    match updates {
+        Some(updates) => updates.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sends the user's balances and open orders, returning the user and the snapshot's
+/// sequence, or `None` if the user doesn't exist.
+async fn send_user_snapshot(session: &mut Session, tx: &CommandSender, user_id: Uuid) -> Result<Option<(Uuid, u64)>, Closed> {
+    let (tx_oneshot, rx) = oneshot::channel();
+    let _ = tx.send(EngineCommand::GetUserSnapshot { user_id, tx_oneshot });
+    match rx.await {
+        Ok(Some(snapshot)) => {
+            let sequence = snapshot.sequence;
+            send_user(session, "snapshot", snapshot).await?;
+            Ok(Some((user_id, sequence)))
+        }
+        Ok(None) => send_error(session, "user not found").await.map(|_| None),
+        Err(_) => send_error(session, "engine failed to respond").await.map(|_| None),
+    }
+}
+
+async fn send_user(session: &mut Session, kind: &str, data: impl Serialize) -> Result<(), Closed> {
+    let msg = serde_json::json!({
+        "channel": "user",
+        "type": kind,
+        "data": data
+    });
+    session.text(msg.to_string()).await
+}
+
+/// Read-only drop copy of every fill and order state change across all users, for back
+/// office reconciliation.
+///
+/// The handshake is signed like an HTTP request, including its query string, by a key with
+/// the `drop_copy` scope. `?after=<sequence>` replays the records after that sequence
+/// first, and `?accounts=<user id>,<user id>` copies only those users, which must be among
+/// the ones the key was issued for; without it the connection gets everything the key may
+/// see. A `live` message carrying the latest sequence marks the end of the replay.
+/// Sequences are global, so with `accounts` set they skip other users' records; a consumer
+/// reconnects with the last one it processed. Asking for more history than is kept, or
+/// falling that far behind, gets an error and the connection is closed.
+#[get("/ws/drop_copy")]
+async fn drop_copy(
+    req: HttpRequest,
+    body: web::Payload,
+    query: web::Query<DropCopyQuery>,
+    tx: web::Data<CommandSender>,
+    drop_copy: web::Data<broadcast::Sender<DropCopyRecord>>,
+    api_keys: web::Data<ApiKeys>,
+) -> Result<HttpResponse, actix_web::Error> {
+    let key = match api_keys.verify_request(&req, b"") {
+        Ok(key) => key,
+        Err(e) => return Ok(HttpResponse::Unauthorized().body(e)),
+    };
+    if !key.scopes.contains(&Scope::DropCopy) {
+        return Ok(HttpResponse::Forbidden().body("api key lacks the drop_copy scope"));
+    }
+    rate_limit::charge_authenticated(&req, &key.api_key)?;
+
+    let mut accounts = match &query.accounts {
+        Some(accounts) => accounts
+            .split(',')
+            .map(|id| Uuid::parse_str(id.trim()))
+            .collect::<Result<HashSet<_>, _>>()
+            .map_err(|_| actix_web::error::ErrorBadRequest("accounts must be comma separated user ids"))?,
+        None => HashSet::new(),
+    };
+    if let Some(allowed) = &key.accounts {
+        if accounts.is_empty() {
+            accounts = allowed.clone();
+        } else if !accounts.is_subset(allowed) {
+            return Ok(HttpResponse::Forbidden().body("api key may not copy some of these accounts"));
+        }
+    }
+    let (response, session, msg_stream) = actix_ws::handle(&req, body)?;
+    let records = drop_copy.subscribe();
+    let tx = tx.get_ref().clone();
+    let after = query.after;
+
+    actix_web::rt::spawn(async move {
+        let _ = run_drop_copy(session.clone(), msg_stream, tx, records, accounts, after).await;
+        let _ = session.close(None).await;
+    });
+    Ok(response)
+}
+
+async fn run_drop_copy(
+    mut session: Session,
+    mut msg_stream: MessageStream,
+    tx: CommandSender,
+    mut records: broadcast::Receiver<DropCopyRecord>,
+    accounts: HashSet<Uuid>,
+    after: Option<u64>,
+) -> Result<(), Closed> {
+    // last sequence seen, copied or not
+    let Some(mut last) = replay_drop_copy(&mut session, &tx, &accounts, after).await? else {
+        return Ok(());
+    };
+    send_drop_copy(&mut session, "live", serde_json::json!({ "sequence": last })).await?;
+
+    loop {
+        tokio::select! {
+            msg = msg_stream.recv() => match msg {
+                Some(Ok(Message::Text(_))) => send_error(&mut session, "the drop copy is read-only").await?,
+                Some(Ok(Message::Ping(bytes))) => session.pong(&bytes).await?,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+            record = records.recv() => match record {
+                Ok(record) if record.sequence <= last => {}
+                Ok(record) if record.sequence == last + 1 => {
+                    last = record.sequence;
+                    if copies(&accounts, &record) {
+                        send_drop_copy(&mut session, "record", record).await?;
+                    }
+                }
+                // whatever was skipped is still in the engine's replay buffer
+                Ok(_) | Err(RecvError::Lagged(_)) => match replay_drop_copy(&mut session, &tx, &accounts, Some(last)).await? {
+                    Some(sequence) => last = sequence,
+                    None => return Ok(()),
+                },
+                Err(RecvError::Closed) => return Ok(()),
+            },
+        }
+    }
+}
+
+/// Sends the records after `after` and returns the latest sequence, or `None` once the
+/// client has been told they can't be replayed.
+async fn replay_drop_copy(session: &mut Session, tx: &CommandSender, accounts: &HashSet<Uuid>, after: Option<u64>) -> Result<Option<u64>, Closed> {
+    let (tx_oneshot, rx) = oneshot::channel();
+    let _ = tx.send(EngineCommand::GetDropCopy { after, tx_oneshot });
+    match rx.await {
+        Ok(DropCopyReplay::Records { sequence, records }) => {
+            for record in records.into_iter().filter(|r| copies(accounts, r)) {
+                send_drop_copy(session, "record", record).await?;
+            }
+            Ok(Some(sequence))
+        }
+        Ok(DropCopyReplay::TooOld { oldest }) => {
+            let msg = format!("records after {} are no longer kept, the oldest is {oldest}", after.unwrap_or_default());
+            send_error(session, &msg).await.map(|_| None)
+        }
+        Err(_) => send_error(session, "engine failed to respond").await.map(|_| None),
+    }
+}
+
+fn copies(accounts: &HashSet<Uuid>, record: &DropCopyRecord) -> bool {
+    accounts.is_empty() || accounts.contains(&record.user_id)
+}
+
+async fn send_drop_copy(session: &mut Session, kind: &str, data: impl Serialize) -> Result<(), Closed> {
+    let msg = serde_json::json!({
+        "channel": "drop_copy",
+        "type": kind,
+        "data": data
+    });
+    session.text(msg.to_string()).await
+}